@@ -4,6 +4,11 @@ use anchor_spl::token::{self, Token, TokenAccount, Transfer, Mint};
 use anchor_spl::associated_token::AssociatedToken;
 use pyth_sdk_solana::load_price_feed_from_account_info;
 use solana_program::{account_info::AccountInfo, clock::Clock, pubkey::Pubkey};
+
+mod migration;
+mod multisig;
+use multisig::{approve, propose, require_admin, set_signers, Multisig, Proposal, MAX_MULTISIG_SIGNERS};
+use migration::{migrate_account, write_account};
 #[allow(unused_imports)]
 
 declare_id!("CONTRACT");
@@ -30,7 +35,24 @@ pub mod presale_vesting {
         presale.max_sol = max_sol; // Set the SOL hard cap
         presale.is_closed = false;
         presale.bump = bump; // Save the bump seed
+        presale.version = PresaleAccount::VERSION;
         presale.public_sale_price = public_sale_price; // Set the public sale price
+
+        // Seed the treasury with its rent-exempt reserve so small contributions land
+        let reserve = Rent::get()?.minimum_balance(0);
+        let treasury_balance = ctx.accounts.treasury.lamports();
+        if treasury_balance < reserve {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.admin.to_account_info(),
+                        to: ctx.accounts.treasury.to_account_info(),
+                    },
+                ),
+                reserve - treasury_balance,
+            )?;
+        }
         Ok(())
     }
 
@@ -56,22 +78,23 @@ pub mod presale_vesting {
 
         // Transfer only the portion of lamports that fits within the SOL cap
         let lamports_to_accept = lamports_paid.min(remaining_sol_cap);
-        **ctx
-            .accounts
-            .admin_wallet
-            .to_account_info()
-            .try_borrow_mut_lamports()? += lamports_to_accept;
-        **ctx
-            .accounts
-            .contributor
-            .to_account_info()
-            .try_borrow_mut_lamports()? -= lamports_to_accept;
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.contributor.to_account_info(),
+                    to: ctx.accounts.treasury.to_account_info(),
+                },
+            ),
+            lamports_to_accept,
+        )?;
 
         // Update the total SOL collected
         presale.total_sol_collected += lamports_to_accept;
 
         // Update allocation account with the contributed amount
         let allocation = &mut ctx.accounts.allocation_account;
+        allocation.version = AllocationAccount::VERSION;
         allocation.amount += lamports_to_accept / discounted_price; // Allocate tokens
         allocation.cliff_timestamp = presale.cliff_timestamp;
         allocation.vesting_end_timestamp = presale.vesting_end_timestamp;
@@ -166,11 +189,14 @@ pub mod presale_vesting {
         // Ensure the presale is still active
         require!(!presale.is_closed, CustomError::PresaleClosed);
 
-        // Ensure only the admin can update the price
-        require!(
-            ctx.accounts.admin.key() == presale.admin,
-            CustomError::Unauthorized
-        );
+        // Ensure only the admin, or the multisig, can update the price
+        require_admin(
+            presale,
+            ctx.accounts.multisig.as_ref(),
+            ctx.accounts.proposal.as_mut(),
+            &ctx.accounts.admin.key(),
+            AdminAction::UpdatePresalePrice { new_public_sale_price },
+        )?;
 
         // Update the public sale price
         presale.public_sale_price = new_public_sale_price;
@@ -202,17 +228,28 @@ pub mod presale_vesting {
         let discounted_price = presale.public_sale_price * 85 / 100; // 15% discount
         let lamports_to_refund = token_amount * discounted_price;
 
-        // Perform the refund (lamports transfer)
-        **ctx
-            .accounts
-            .contributor
-            .to_account_info()
-            .try_borrow_mut_lamports()? += lamports_to_refund;
-        **ctx
-            .accounts
-            .presale_wallet
-            .to_account_info()
-            .try_borrow_mut_lamports()? -= lamports_to_refund;
+        // The treasury keeps its rent-exempt reserve
+        let reserve = Rent::get()?.minimum_balance(0);
+        require!(
+            ctx.accounts.treasury.lamports().saturating_sub(reserve) >= lamports_to_refund,
+            CustomError::InsufficientTreasuryBalance
+        );
+
+        // Perform the refund out of the treasury PDA
+        let presale_key = presale.key();
+        let treasury_bump = *ctx.bumps.get("treasury").unwrap();
+        let seeds = &[b"treasury".as_ref(), presale_key.as_ref(), &[treasury_bump]];
+        system_program::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.treasury.to_account_info(),
+                    to: ctx.accounts.contributor.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            lamports_to_refund,
+        )?;
 
         // Burn or transfer tokens from the contributor back to the presale wallet
         let cpi_accounts = Transfer {
@@ -230,14 +267,136 @@ pub mod presale_vesting {
         Ok(())
     }
 
-    pub fn close_presale(ctx: Context<ClosePresale>) -> Result<()> {
-        let presale = &mut ctx.accounts.presale_account;
+    // The admin (or multisig) withdraws raised SOL once the presale is closed and the refund window,
+    // which ends at the cliff, is over. The treasury keeps its rent-exempt reserve.
+    pub fn withdraw_sol(ctx: Context<WithdrawSol>, amount: u64) -> Result<()> {
+        let presale = &ctx.accounts.presale_account;
+        require_admin(
+            presale,
+            ctx.accounts.multisig.as_ref(),
+            ctx.accounts.proposal.as_mut(),
+            &ctx.accounts.admin.key(),
+            AdminAction::WithdrawSol { amount },
+        )?;
+        require!(presale.is_closed, CustomError::PresaleNotClosed);
+        let current_time = Clock::get()?.unix_timestamp as u64;
+        require!(
+            current_time >= presale.cliff_timestamp,
+            CustomError::RefundWindowOpen
+        );
 
-        // Only the admin can close the presale
+        let reserve = Rent::get()?.minimum_balance(0);
+        let available = ctx.accounts.treasury.lamports().saturating_sub(reserve);
         require!(
-            ctx.accounts.admin.key() == presale.admin,
-            CustomError::Unauthorized
+            amount > 0 && amount <= available,
+            CustomError::InsufficientTreasuryBalance
         );
+
+        let presale_key = presale.key();
+        let treasury_bump = *ctx.bumps.get("treasury").unwrap();
+        let seeds = &[b"treasury".as_ref(), presale_key.as_ref(), &[treasury_bump]];
+        system_program::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.treasury.to_account_info(),
+                    to: ctx.accounts.admin.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    // Hands admin actions to an M-of-N multisig. Once one exists, changing its signers takes
+    // an approved `ChangeSigners` proposal, like any other admin action.
+    pub fn set_admin_signers(
+        ctx: Context<SetAdminSigners>,
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        let presale = &mut ctx.accounts.presale_account;
+        require_admin(
+            presale,
+            Some(&ctx.accounts.multisig),
+            ctx.accounts.proposal.as_mut(),
+            &ctx.accounts.admin.key(),
+            AdminAction::ChangeSigners { signers: signers.clone(), threshold },
+        )?;
+
+        let multisig = &mut ctx.accounts.multisig;
+        if presale.signer_threshold == 0 {
+            multisig.presale_account = presale.key();
+            multisig.bump = *ctx.bumps.get("multisig").unwrap();
+            multisig.version = Multisig::VERSION;
+        }
+        set_signers(multisig, signers, threshold)?;
+        presale.signer_threshold = threshold;
+
+        Ok(())
+    }
+
+    // Any multisig signer proposes an admin action; proposing counts as the first approval
+    pub fn propose_admin_action(ctx: Context<ProposeAdminAction>, action: AdminAction) -> Result<()> {
+        propose(
+            &mut ctx.accounts.multisig,
+            &mut ctx.accounts.proposal,
+            &ctx.accounts.proposer.key(),
+            action,
+            *ctx.bumps.get("proposal").unwrap(),
+        )
+    }
+
+    pub fn approve_proposal(ctx: Context<ApproveProposal>) -> Result<()> {
+        approve(
+            &ctx.accounts.multisig,
+            &mut ctx.accounts.proposal,
+            &ctx.accounts.signer.key(),
+        )
+    }
+
+    // Bring the presale, and any allocation accounts passed as remaining accounts, up to the
+    // current layout. The admin pays for any added space; under a multisig they must be a
+    // signer executing an approved `Migrate` proposal.
+    pub fn migrate<'info>(ctx: Context<'_, '_, '_, 'info, Migrate<'info>>) -> Result<()> {
+        let payer = ctx.accounts.admin.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let presale_info = ctx.accounts.presale_account.to_account_info();
+
+        let mut presale = migrate_account::<PresaleAccount>(&presale_info, &payer, &system_program)?;
+        require_admin(
+            &presale,
+            ctx.accounts.multisig.as_ref(),
+            ctx.accounts.proposal.as_mut(),
+            &payer.key(),
+            AdminAction::Migrate,
+        )?;
+        presale.version = PresaleAccount::VERSION;
+        write_account(&presale_info, &presale)?;
+
+        for account in ctx.remaining_accounts {
+            require!(account.is_writable, CustomError::InvalidMigrationAccount);
+            let mut allocation = migrate_account::<AllocationAccount>(account, &payer, &system_program)?;
+            allocation.version = AllocationAccount::VERSION;
+            write_account(account, &allocation)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn close_presale(ctx: Context<ClosePresale>) -> Result<()> {
+        let presale = &mut ctx.accounts.presale_account;
+
+        // Only the admin, or the multisig once one is configured, can close the presale
+        require_admin(
+            presale,
+            ctx.accounts.multisig.as_ref(),
+            ctx.accounts.proposal.as_mut(),
+            &ctx.accounts.admin.key(),
+            AdminAction::ClosePresale,
+        )?;
         presale.is_closed = true;
 
         Ok(())
@@ -246,7 +405,7 @@ pub mod presale_vesting {
 
 #[derive(Accounts)]
 pub struct InitializePresale<'info> {
-    #[account(init, payer = admin, space = 8 + 32 + 32 + 8 + 8 + 8 + 8 + 8 + 1)]
+    #[account(init, payer = admin, space = 8 + PresaleAccount::INIT_SPACE)]
     pub presale_account: Account<'info, PresaleAccount>,
     #[account(
         init_if_needed,
@@ -258,6 +417,9 @@ pub struct InitializePresale<'info> {
     pub token_mint: Account<'info, Mint>,
     #[account(mut)]
     pub admin: Signer<'info>,
+    /// CHECK: System-owned treasury PDA that will hold the raised SOL
+    #[account(mut, seeds = [b"treasury", presale_account.key().as_ref()], bump)]
+    pub treasury: AccountInfo<'info>,
     #[account(address = system_program::ID)]
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
@@ -271,6 +433,13 @@ pub struct UpdatePresalePrice<'info> {
     pub presale_account: Account<'info, PresaleAccount>, // Presale state
     #[account(signer)]
     pub admin: AccountInfo<'info>, // Admin must sign the transaction
+    #[account(
+        seeds = [b"multisig", presale_account.key().as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Option<Account<'info, Multisig>>, // Required once a multisig is configured
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>, // Approved proposal for this action, under a multisig
 }
 
 #[derive(Accounts)]
@@ -280,13 +449,14 @@ pub struct Contribute<'info> {
     #[account(
         init_if_needed,
         payer = contributor,
-        space = 8 + 8 + 8 + 8 + 8
+        space = 8 + AllocationAccount::INIT_SPACE
     )]
     pub allocation_account: Account<'info, AllocationAccount>, // Allocation state for the contributor
     #[account(mut)]
     pub contributor: Signer<'info>, // Contributor wallet
-    /// CHECK: Admin wallet account (could add stricter validation here)
-    pub admin_wallet: AccountInfo<'info>, // Admin wallet to receive funds
+    /// CHECK: System-owned treasury PDA holding the raised SOL
+    #[account(mut, seeds = [b"treasury", presale_account.key().as_ref()], bump)]
+    pub treasury: AccountInfo<'info>,
     #[account(address = token::ID)]
     pub token_program: Program<'info, Token>, // Token program
     pub system_program: Program<'info, System>, // System program
@@ -334,8 +504,30 @@ pub struct RefundTokens<'info> {
     pub contributor_wallet: Account<'info, TokenAccount>, // Contributor token wallet
     #[account(mut)]
     pub contributor: Signer<'info>, // Contributor wallet
+    /// CHECK: System-owned treasury PDA, pays the SOL refund
+    #[account(mut, seeds = [b"treasury", presale_account.key().as_ref()], bump)]
+    pub treasury: AccountInfo<'info>,
     #[account(address = token::ID)]
     pub token_program: Program<'info, Token>, // Token program
+    pub system_program: Program<'info, System>, // System program
+}
+
+#[derive(Accounts)]
+pub struct WithdrawSol<'info> {
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(mut)]
+    pub admin: Signer<'info>, // Admin, receives the SOL
+    /// CHECK: System-owned treasury PDA holding the raised SOL
+    #[account(mut, seeds = [b"treasury", presale_account.key().as_ref()], bump)]
+    pub treasury: AccountInfo<'info>,
+    #[account(
+        seeds = [b"multisig", presale_account.key().as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Option<Account<'info, Multisig>>, // Required once a multisig is configured
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>, // Approved proposal for this action, under a multisig
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
@@ -344,9 +536,82 @@ pub struct ClosePresale<'info> {
     pub presale_account: Account<'info, PresaleAccount>,
     #[account(signer)]
     pub admin: AccountInfo<'info>,
+    #[account(
+        seeds = [b"multisig", presale_account.key().as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Option<Account<'info, Multisig>>, // Required once a multisig is configured
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>, // Approved proposal for this action, under a multisig
+}
+
+#[derive(Accounts)]
+pub struct SetAdminSigners<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + Multisig::INIT_SPACE,
+        seeds = [b"multisig", presale_account.key().as_ref()],
+        bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>, // Approved `ChangeSigners` proposal, once the multisig exists
+    #[account(mut)]
+    pub admin: Signer<'info>, // Admin on first setup, pays for the multisig; a signer afterwards
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAdminAction<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.presale_account.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + Proposal::INIT_SPACE,
+        seeds = [b"proposal", multisig.key().as_ref(), &multisig.proposal_count.to_le_bytes()],
+        bump
+    )]
+    pub proposal: Account<'info, Proposal>,
+    #[account(mut)]
+    pub proposer: Signer<'info>, // Must be in the signer set
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveProposal<'info> {
+    pub multisig: Account<'info, Multisig>,
+    #[account(mut, has_one = multisig)]
+    pub proposal: Account<'info, Proposal>,
+    pub signer: Signer<'info>, // Must be in the signer set
+}
+
+#[derive(Accounts)]
+pub struct Migrate<'info> {
+    /// CHECK: Owner and discriminator are checked by `migrate`, which decodes it once grown
+    #[account(mut)]
+    pub presale_account: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub admin: Signer<'info>, // Admin, or a multisig signer once one exists; pays for added space
+    #[account(
+        seeds = [b"multisig", presale_account.key().as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Option<Account<'info, Multisig>>, // Required once a multisig is configured
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>, // Approved proposal for this action, under a multisig
+    pub system_program: Program<'info, System>,
 }
 
 #[account]
+#[derive(InitSpace)]
 pub struct PresaleAccount {
     pub token_mint: Pubkey,          // Token mint address
     pub admin: Pubkey,               // Admin address
@@ -359,14 +624,43 @@ pub struct PresaleAccount {
     pub is_closed: bool,             // Whether the presale is closed
     pub bump: u8,                    // PDA bump seed
     pub public_sale_price: u64,      // Token price in public sale (e.g., 1 token = X lamports)
+    pub version: u8,                 // Layout version; new fields go before `reserved`
+    pub signer_threshold: u8,        // Multisig approvals needed for admin actions; zero means the single admin
+    pub reserved: [u8; 63],          // Zeroed space for future fields
+}
+
+impl PresaleAccount {
+    pub const VERSION: u8 = 2;
 }
 
 #[account]
+#[derive(InitSpace)]
 pub struct AllocationAccount {
     pub amount: u64,
     pub claimed_amount: u64,
     pub cliff_timestamp: u64,
     pub vesting_end_timestamp: u64,
+    pub version: u8,        // Layout version; new fields go before `reserved`
+    pub reserved: [u8; 32], // Zeroed space for future fields
+}
+
+impl AllocationAccount {
+    pub const VERSION: u8 = 1;
+}
+
+// What a multisig proposal authorizes; each runs in its own instruction, which checks the
+// arguments it is called with against the proposal
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub enum AdminAction {
+    UpdatePresalePrice { new_public_sale_price: u64 },
+    WithdrawSol { amount: u64 },
+    ChangeSigners {
+        #[max_len(MAX_MULTISIG_SIGNERS)]
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    },
+    Migrate,
+    ClosePresale,
 }
 
 #[error_code]
@@ -389,4 +683,28 @@ pub enum CustomError {
     InvalidContribution,
     #[msg("The presale SOL limit has been reached.")]
     PresaleLimitReached,
-}
+    #[msg("The treasury does not hold enough SOL for this payout.")]
+    InsufficientTreasuryBalance,
+    #[msg("The presale has not been closed yet.")]
+    PresaleNotClosed,
+    #[msg("Refunds are still open until the cliff.")]
+    RefundWindowOpen,
+    #[msg("Signers must be unique, at most ten, and cover the threshold.")]
+    InvalidSignerSet,
+    #[msg("Not enough signers approved this action.")]
+    ThresholdNotMet,
+    #[msg("Account cannot be migrated by this presale.")]
+    InvalidMigrationAccount,
+    #[msg("Signer is not part of the multisig.")]
+    NotMultisigSigner,
+    #[msg("This presale is administered by its multisig; pass it and an approved proposal.")]
+    MultisigRequired,
+    #[msg("The proposal does not authorize this action.")]
+    ProposalActionMismatch,
+    #[msg("The proposal has already been executed.")]
+    ProposalAlreadyExecuted,
+    #[msg("The signer set changed after this proposal was made.")]
+    StaleProposal,
+    #[msg("Arithmetic overflow.")]
+    MathOverflow,
+}
\ No newline at end of file
@@ -1,20 +1,54 @@
 use anchor_lang::prelude::*;
-#[allow(unused_imports)]
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
 use pyth_sdk_solana::load_price_feed_from_account_info;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use pyth_solana_receiver_sdk::price_update::{PriceUpdateV2, VerificationLevel};
+use switchboard_solana::AggregatorAccountData;
+use anchor_spl::token::{self, Burn, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::keccak;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
 use solana_program::{
     account_info::AccountInfo,
     pubkey::Pubkey,
 };
 
+mod migration;
+mod vesting;
+use migration::{migrate_account, write_account};
+use vesting::{valid_unlock_points, UnlockPoint, VestingMode, VestingSchedule};
+
 declare_id!("CONTRACTADDRESS");
 
+pub const MAX_SALE_ROUNDS: usize = 8; // Upper bound on configured sale rounds
+pub const BPS_DENOMINATOR: u64 = 10_000; // Basis points in 100%
+pub const MAX_PAYMENT_MINTS: usize = 4; // Upper bound on registered payment mints
+pub const USD_PEG_PRICE: u64 = 100; // Price of a USD-pegged stablecoin in USD cents
+pub const PRICE_DECIMALS: i32 = 2; // Program prices are USD cents
+pub const MAX_PRICE_OBSERVATIONS: usize = 16; // Size of the SOL/USD observation ring buffer
+pub const CURVE_FIXED_POINT: u128 = 1_000_000_000_000; // Scale of bonding-curve growth factors
+pub const LOTTERY_DRAW_DELAY_SLOTS: u64 = 10; // Slots between a draw request and its slot hash
+pub const SLOT_HASHES_DEPTH: u64 = 512; // Slots the SlotHashes sysvar keeps a hash for
+pub const MIN_LOTTERY_REVEAL_WINDOW: i64 = 60 * 60; // Shortest accepted reveal window, in seconds
+pub const MAX_LOTTERY_REVEAL_WINDOW: i64 = 30 * 24 * 60 * 60; // Longest accepted reveal window, in seconds
+pub const MAX_MULTISIG_SIGNERS: usize = 10; // Upper bound on the admin signer set
+pub const MAX_ROLE_ASSIGNMENTS: usize = 16; // Upper bound on granted roles
+pub const MAX_QUEUED_CHANGES: usize = 8; // Upper bound on pending timelocked changes
+pub const MAX_TIMELOCK_DELAY: i64 = 30 * 24 * 60 * 60; // Longest accepted timelock, in seconds
+pub const MAX_AIRDROP_PERIODS: usize = 12; // Upper bound on airdrop_percentages entries
+pub const MAX_MILESTONES: usize = 8; // Upper bound on vote-gated release tranches
+pub const MAX_UNLOCK_POINTS: usize = 32; // Upper bound on piecewise vesting unlock points
+pub const VOUCHER_MESSAGE_LEN: usize = 32 + 32 + 8 + 8 + 8; // buyer, presale, max_amount, expiry, nonce
+
 #[program]
 pub mod fam_presale_contract {
     use super::*;
-    // Initialize presale and vesting parameters
+    // Create the presale PDA and its sale-token vault, with vesting parameters and caps
     pub fn initialize(
         ctx: Context<Initialize>,
+        sale_id: u64,                  // Distinguishes sales of the same mint by the same authority
         presale_start: i64,
         presale_end: i64,
         public_sale_start: i64,
@@ -25,23 +59,42 @@ pub mod fam_presale_contract {
         vesting_interval: i64,
         airdrop_percentages: Vec<u64>, // Accept airdrop percentages as input
         max_airdrop_elements: u8,      // Accept maximum airdrop elements as input
+        oracle_kind: OracleKind,       // Price provider backing `sol_usd_feed`
+        sol_usd_feed: Pubkey,          // SOL/USD feed every price read is bound to
+        oracle_max_age: u64,           // Oldest accepted oracle price, in seconds
+        oracle_max_conf_bps: u16,      // Widest accepted confidence interval, in bps of price
+        min_buy_amount_usd: u64,       // Minimum spend per purchase, in USD cents
+        max_buy_amount_usd: u64,       // Maximum spend per user, in USD cents
+        hard_cap_usd: u64,             // Maximum raise for the entire presale, in USD cents
+        soft_cap_usd: u64,             // Minimum raise for the sale to succeed, in USD cents
+        release_schedule: ReleaseSchedule, // Fixed unlock schedule for the raised funds
+        milestones: Vec<MilestoneConfig>,  // Vote-gated release tranches, empty for none
+        unsold_policy: UnsoldTokenPolicy,  // What `finalize_sale` does with unsold vault tokens
+        vesting_mode: VestingMode,         // Stepped, linear after the cliff, or piecewise
+        tge_unlock_bps: u16,               // Share unlocked at TGE in linear mode
+        unlock_points: Vec<UnlockPoint>,   // Piecewise unlocks after TGE, empty in other modes
     ) -> Result<()> {
         // Cap the size of the airdrop_percentages vector (e.g., max 12 elements)
-        if airdrop_percentages.len() > max_airdrop_elements.into() {
+        if airdrop_percentages.len() > max_airdrop_elements.into()
+            || airdrop_percentages.len() > MAX_AIRDROP_PERIODS
+        {
             return Err(ErrorCode::AirdropConfigurationError.into());
         }
         let total_percentage: u64 = airdrop_percentages.iter().sum();
         if total_percentage > 100 {
             return Err(ErrorCode::AirdropConfigurationError.into());
         }
-        if vesting_period == 0 || vesting_interval == 0 {
+        validate_vesting_params(cliff_period, vesting_period, vesting_interval)?;
+        if tge_unlock_bps as u64 > BPS_DENOMINATOR {
             return Err(ErrorCode::InvalidVestingParameters.into());
         }
-        if vesting_interval > vesting_period {
-            return Err(ErrorCode::InvalidVestingParameters.into());
-        }
-        if cliff_period > vesting_period {
-            return Err(ErrorCode::InvalidVestingParameters.into());
+        let unlock_points_valid = if vesting_mode == VestingMode::Piecewise {
+            valid_unlock_points(&unlock_points, MAX_UNLOCK_POINTS)
+        } else {
+            unlock_points.is_empty()
+        };
+        if !unlock_points_valid {
+            return Err(ErrorCode::InvalidUnlockSchedule.into());
         }
         if presale_start >= presale_end {
             return Err(ErrorCode::InvalidPresaleTiming.into());
@@ -55,6 +108,18 @@ pub mod fam_presale_contract {
         if airdrop_percentages.iter().any(|&x| x == 0) {
             return Err(ErrorCode::AirdropConfigurationError.into());
         }
+        validate_oracle_config(oracle_max_age, oracle_max_conf_bps)?;
+
+        // Caps must nest: min buy <= max buy <= hard cap, soft cap within the hard cap
+        if hard_cap_usd == 0
+            || min_buy_amount_usd > max_buy_amount_usd
+            || max_buy_amount_usd > hard_cap_usd
+            || soft_cap_usd > hard_cap_usd
+        {
+            return Err(ErrorCode::InvalidParameterValue.into());
+        }
+        release_schedule.validate(presale_end)?;
+        validate_milestones(&milestones, presale_end)?;
 
         let presale_account = &mut ctx.accounts.presale_account;
         presale_account.presale_start = presale_start;
@@ -69,348 +134,510 @@ pub mod fam_presale_contract {
             .iter()
             .map(|&x| x as u8) 
             .collect();
+        presale_account.total_airdrop_periods = airdrop_percentages.len() as u8;
+        presale_account.min_buy_amount_usd = min_buy_amount_usd;
+        presale_account.max_buy_amount_usd = max_buy_amount_usd;
+        presale_account.hard_cap_usd = hard_cap_usd;
+        presale_account.soft_cap_usd = soft_cap_usd;
+        presale_account.authority = ctx.accounts.authority.key(); // The initializer is the super-admin
+        presale_account.sale_mint = ctx.accounts.sale_mint.key();
+        presale_account.sale_vault = ctx.accounts.sale_vault.key();
+        presale_account.sale_vault_bump = *ctx.bumps.get("sale_vault").unwrap();
+        presale_account.sale_id = sale_id;
+        presale_account.bump = *ctx.bumps.get("presale_account").unwrap();
+        presale_account.treasury_bump = *ctx.bumps.get("treasury").unwrap();
+        presale_account.release_schedule = release_schedule;
+        presale_account.milestones = milestones.iter().map(Milestone::from).collect();
+        presale_account.unsold_policy = unsold_policy;
+        presale_account.vesting_mode = vesting_mode;
+        presale_account.tge_unlock_bps = tge_unlock_bps;
+        presale_account.version = PresaleAccount::VERSION;
+
+        let unlock_schedule = &mut ctx.accounts.unlock_schedule;
+        unlock_schedule.presale_account = presale_account.key();
+        unlock_schedule.points = unlock_points;
+        unlock_schedule.bump = *ctx.bumps.get("unlock_schedule").unwrap();
+        unlock_schedule.version = UnlockSchedule::VERSION;
+
+        // Seed the treasury with its rent-exempt reserve so payouts never strand it
+        let reserve = Rent::get()?.minimum_balance(0);
+        let treasury_balance = ctx.accounts.treasury.lamports();
+        if treasury_balance < reserve {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.authority.to_account_info(),
+                        to: ctx.accounts.treasury.to_account_info(),
+                    },
+                ),
+                reserve - treasury_balance,
+            )?;
+        }
+
+        // Start with a single round spanning the whole presale at the base price
+        presale_account.rounds = vec![SaleRound {
+            start_time: presale_start,
+            end_time: presale_end,
+            price,
+            token_cap: u64::MAX,
+            wallet_cap: u64::MAX,
+            allowlist_only: false,
+            tokens_sold: 0,
+            sol_raised: 0,
+        }];
+        presale_account.total_tokens_sold = 0;
+        presale_account.oracle_kind = oracle_kind;
+        presale_account.sol_usd_feed = sol_usd_feed;
+        presale_account.oracle_max_age = oracle_max_age;
+        presale_account.oracle_max_conf_bps = oracle_max_conf_bps;
 
         Ok(())
     }
 
-    pub fn update_presale_params(
-        ctx: Context<UpdatePresaleParams>,
-        new_price: Option<u64>,
-        new_min_buy_amount: Option<u64>,
-        new_max_buy_amount: Option<u64>,
-        new_hard_cap: Option<u64>,
-    ) -> Result<()> {
-        let presale_account = &mut ctx.accounts.presale_account;
-
-        // Ensure caller is the authorized admin
-        if ctx.accounts.authority.key() != presale_account.authority {
-            return Err(ErrorCode::UnauthorizedAccess.into());
+    // The project deposits the tokens buyers will claim into the program-owned vault
+    pub fn fund_vault(ctx: Context<FundVault>, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::InvalidParameterValue.into());
         }
 
-        // Update parameters if provided
-        if let Some(price) = new_price {
-            presale_account.price = price;
-        }
-        if let Some(min_buy) = new_min_buy_amount {
-            presale_account.min_buy_amount_sol = min_buy;
-        }
-        if let Some(max_buy) = new_max_buy_amount {
-            presale_account.max_buy_amount_sol = max_buy;
-        }
-        if let Some(hard_cap) = new_hard_cap {
-            presale_account.hard_cap_sol = hard_cap;
-        }
+        token::transfer(ctx.accounts.into_transfer_context(), amount)?;
+
+        let presale_account = &mut ctx.accounts.presale_account;
+        presale_account.vault_funded = presale_account
+            .vault_funded
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
 
-        // Emit event with updated parameters
         let clock = Clock::get()?;
-        emit!(PresaleParamsUpdated {
-            new_price,
-            new_min_buy_amount,
-            new_max_buy_amount,
-            new_hard_cap,
+        emit!(VaultFunded {
+            presale_account: presale_account.key(),
+            funder: ctx.accounts.funder.key(),
+            amount,
             timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-    pub fn purchase(ctx: Context<Purchase>, amount: u64) -> Result<()> {
-        let presale_account = &mut ctx.accounts.presale_account;
+    // Buyers pull their vested tokens from the vault into their own token account
+    pub fn claim(ctx: Context<Claim>) -> Result<()> {
         let user_vesting = &mut ctx.accounts.user_vesting;
-
-        // Fetch current timestamp
         let clock = Clock::get()?;
         let current_time = clock.unix_timestamp;
 
-        // Ensure presale is not paused
-        if presale_account.paused {
-            return Err(ErrorCode::PresalePaused.into());
+        // A failed sale pays nothing out
+        if ctx.accounts.presale_account.sale_state == SaleState::Failed {
+            return Err(ErrorCode::SaleFailed.into());
         }
+        ensure_allocation_settled(&ctx.accounts.presale_account, user_vesting)?;
 
-        // Ensure presale is active
-        if !(current_time >= presale_account.presale_start
-            && current_time <= presale_account.presale_end)
-        {
-            return Err(ErrorCode::SaleNotActive.into());
+        // Calculate vested tokens
+        let vested_amount = ctx.accounts.presale_account.vested_amount(
+            ctx.accounts.unlock_schedule.as_deref(),
+            user_vesting.total_amount,
+            current_time,
+        )?;
+
+        let claimable_amount = vested_amount.saturating_sub(user_vesting.claimed_amount);
+        if claimable_amount == 0 {
+            return Err(ErrorCode::NoTokensToClaim.into());
         }
 
-        // Fetch SOL/USD price using fallback logic
-        let sol_price_in_usd = get_price_from_oracle(
-            &ctx.accounts.sol_to_usd_oracle,
-            presale_account.manual_price_override,
+        // Update claimed amount
+        user_vesting.claimed_amount = user_vesting
+            .claimed_amount
+            .checked_add(claimable_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        pay_from_sale_vault(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.sale_vault.to_account_info(),
+            &ctx.accounts.buyer_token_account.to_account_info(),
+            &ctx.accounts.presale_account,
+            claimable_amount,
         )?;
 
-        // Calculate the token price in SOL
-        let token_price_in_sol = presale_account
-            .price
-            .checked_mul(10u64.pow(9)) // Convert price from USD cents to lamports
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(sol_price_in_usd)
-            .ok_or(ErrorCode::MathOverflow)?;
+        // Emit event
+        emit!(ClaimEvent {
+            user: ctx.accounts.buyer.key(),
+            amount: claimable_amount,
+            total_claimed: ctx.accounts.user_vesting.claimed_amount,
+        });
 
-        // Calculate total cost in SOL (lamports)
-        let total_cost_in_sol = amount
-            .checked_mul(token_price_in_sol)
-            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
 
-        // Ensure the buyer has enough SOL
-        if **ctx.accounts.buyer.to_account_info().lamports.borrow() < total_cost_in_sol {
-            return Err(ErrorCode::InsufficientFunds.into());
+    // Withdraw raised SOL (`payment_mint` None) or stablecoin within the release schedule.
+    // Stablecoin withdrawals pass the payment vault and the recipient's token account as
+    // remaining accounts.
+    pub fn withdraw_proceeds<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawProceeds<'info>>,
+        payment_mint: Option<Pubkey>,
+        amount: u64,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Ensure caller holds the treasury withdrawer role
+        if !presale_account.has_role(&ctx.accounts.withdrawer.key(), Role::TreasuryWithdrawer) {
+            return Err(ErrorCode::UnauthorizedAccess.into());
         }
 
-        // Ensure the purchase is within allowed limits
-        if total_cost_in_sol < presale_account.min_buy_amount_sol {
-            return Err(ErrorCode::BelowMinimumPurchase.into());
+        // Proceeds stay put until buyers can no longer be owed them back
+        if current_time < presale_account.presale_end {
+            return Err(ErrorCode::PresaleNotEnded.into());
         }
-        if user_vesting
-            .total_purchased_sol
-            .checked_add(total_cost_in_sol)
-            .ok_or(ErrorCode::MathOverflow)?
-            > presale_account.max_buy_amount_sol
+        if presale_account.sale_state != SaleState::Active
+            || !presale_account.soft_cap_met()
         {
-            return Err(ErrorCode::ExceedsMaximumPurchase.into());
+            return Err(ErrorCode::InvalidSaleState.into());
         }
-
-        // Check if the total sold exceeds the global hard cap
-        if presale_account
-            .total_sold_sol
-            .checked_add(total_cost_in_sol)
-            .ok_or(ErrorCode::MathOverflow)?
-            > presale_account.hard_cap_sol
+        if presale_account.allocation_mode != AllocationMode::FirstCome
+            && (!presale_account.allocation_finalized
+                || presale_account.settled_contributors < presale_account.contributor_count)
         {
-            return Err(ErrorCode::HardCapReached.into());
+            return Err(ErrorCode::AllocationNotSettled.into());
+        }
+        if amount == 0 {
+            return Err(ErrorCode::InvalidParameterValue.into());
         }
 
-        // --- STATE UPDATES ---
-        presale_account.total_sold_sol = presale_account
-            .total_sold_sol
-            .checked_add(total_cost_in_sol)
-            .ok_or(ErrorCode::BadMath)?;
-
-        user_vesting.total_amount = user_vesting
-            .total_amount
-            .checked_add(amount)
-            .ok_or(ErrorCode::BadMath)?;
-        user_vesting.total_purchased_sol = user_vesting
-            .total_purchased_sol
-            .checked_add(total_cost_in_sol)
-            .ok_or(ErrorCode::BadMath)?;
-
-        // Ensure the amount is non-zero
-        if amount == 0 || total_cost_in_sol < presale_account.min_buy_amount_sol {
-            return Err(ErrorCode::BelowMinimumPurchase.into());
+        let status = presale_account.proceeds_status(payment_mint, current_time)?;
+        if amount > status.unlocked.saturating_sub(status.withdrawn) {
+            return Err(ErrorCode::ProceedsLocked.into());
+        }
+        if amount > status.period_remaining {
+            return Err(ErrorCode::WithdrawalPeriodCapExceeded.into());
+        }
+        presale_account.record_withdrawal(payment_mint, amount, current_time)?;
+
+        let presale_key = presale_account.key();
+        let recipient = ctx.accounts.recipient.key();
+        match payment_mint {
+            None => pay_from_treasury(
+                &ctx.accounts.system_program.to_account_info(),
+                &ctx.accounts.treasury.to_account_info(),
+                &ctx.accounts.recipient.to_account_info(),
+                &ctx.accounts.presale_account,
+                amount,
+            )?,
+            Some(mint) => {
+                let accepted = ctx
+                    .accounts
+                    .presale_account
+                    .accepted_mints
+                    .iter()
+                    .find(|accepted| accepted.mint == mint)
+                    .ok_or(ErrorCode::InvalidPaymentMethod)?;
+                pay_from_payment_vault(
+                    &mut ctx.remaining_accounts.iter(),
+                    &ctx.accounts.token_program.to_account_info(),
+                    presale_key,
+                    accepted,
+                    recipient,
+                    amount,
+                )?;
+            }
         }
 
-        // Derive a minimum token purchase based on `min_buy_amount_sol` and token price
-        let min_token_purchase = presale_account
-            .min_buy_amount_sol
-            .checked_mul(sol_price_in_usd)
-            .ok_or(ErrorCode::BadMath)?
-            .checked_div(presale_account.price)
-            .ok_or(ErrorCode::BadMath)?;
+        emit!(ProceedsWithdrawn {
+            presale_account: presale_key,
+            payment_mint,
+            recipient,
+            amount,
+            withdrawn: status.withdrawn + amount,
+            locked: status.locked,
+            timestamp: current_time,
+        });
 
-        if amount < min_token_purchase {
-            return Err(ErrorCode::BelowMinimumPurchase.into());
-        }
+        Ok(())
+    }
 
-        // --- EXTERNAL CALL ---
-        let program_pda = ctx.accounts.presale_account.to_account_info().key;
-        **ctx
-            .accounts
-            .buyer
-            .to_account_info()
-            .try_borrow_mut_lamports()? -= total_cost_in_sol;
+    // Read-only: how much of the raise in SOL or a stablecoin is released, withdrawn and
+    // still locked
+    pub fn get_proceeds_status(
+        ctx: Context<GetProceedsStatus>,
+        payment_mint: Option<Pubkey>,
+    ) -> Result<ProceedsStatus> {
+        let clock = Clock::get()?;
+        ctx.accounts
+            .presale_account
+            .proceeds_status(payment_mint, clock.unix_timestamp)
+    }
 
-        let pda_account_info = ctx.accounts.program_pda.to_account_info();
-        **pda_account_info.try_borrow_mut_lamports()? += total_cost_in_sol;
+    // Contributors vote on a milestone within its window, weighted by their token allocation
+    pub fn cast_milestone_vote(ctx: Context<CastMilestoneVote>, index: u8, approve: bool) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let user_vesting = &mut ctx.accounts.user_vesting;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
 
+        // Votes only count once the raise is final
+        if presale_account.sale_state != SaleState::Active
+            || !presale_account.soft_cap_met()
+            || presale_account.milestone_failed
+        {
+            return Err(ErrorCode::InvalidSaleState.into());
+        }
+        ensure_allocation_settled(presale_account, user_vesting)?;
+
+        let milestone = presale_account
+            .milestones
+            .get_mut(index as usize)
+            .ok_or(ErrorCode::InvalidMilestoneIndex)?;
+        if current_time < milestone.vote_start || current_time >= milestone.vote_end {
+            return Err(ErrorCode::MilestoneVotingClosed.into());
+        }
+        let vote_bit = 1u8 << index;
+        if user_vesting.milestone_votes & vote_bit != 0 {
+            return Err(ErrorCode::MilestoneAlreadyVoted.into());
+        }
+        let weight = user_vesting.total_amount;
+        if weight == 0 {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
 
-        // Emit event
-        emit!(PurchaseEvent {
-            presale_account: presale_account.key(), // Reference the public key of the account
-            buyer: ctx.accounts.buyer.key(),
-            cost_in_sol: Some(total_cost_in_sol),   // Wrap total_cost_in_sol in Some
-            timestamp: Clock::get()?.unix_timestamp // Example for timestamp, adjust as needed
+        user_vesting.milestone_votes |= vote_bit;
+        let tally = if approve {
+            &mut milestone.yes_weight
+        } else {
+            &mut milestone.no_weight
+        };
+        *tally = tally.checked_add(weight).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(MilestoneVoteCast {
+            presale_account: presale_account.key(),
+            voter: ctx.accounts.buyer.key(),
+            index,
+            approve,
+            weight,
         });
 
         Ok(())
     }
 
-    pub fn set_pause_state(ctx: Context<SetPauseState>, paused: bool) -> Result<()> {
+    // Permissionless: close a milestone vote after its window. A simple majority of the
+    // weight cast approves the tranche; anything else fails every remaining milestone.
+    pub fn resolve_milestone(ctx: Context<ResolveMilestone>, index: u8) -> Result<()> {
         let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
 
-        // Ensure caller is the authorized admin
-        if ctx.accounts.authority.key() != presale_account.authority {
-            return Err(ErrorCode::UnauthorizedAccess.into());
+        // Only a raise that succeeded is governed by milestones; a failed one refunds in full
+        if presale_account.sale_state != SaleState::Active
+            || !presale_account.soft_cap_met()
+        {
+            return Err(ErrorCode::InvalidSaleState.into());
+        }
+        if presale_account.milestone_failed {
+            return Err(ErrorCode::MilestoneAlreadyResolved.into());
+        }
+        let index_usize = index as usize;
+        if index_usize >= presale_account.milestones.len() {
+            return Err(ErrorCode::InvalidMilestoneIndex.into());
+        }
+        // Milestones resolve in order
+        if presale_account.milestones[..index_usize]
+            .iter()
+            .any(|milestone| milestone.status == MilestoneStatus::Pending)
+        {
+            return Err(ErrorCode::InvalidMilestoneIndex.into());
         }
 
-        // Update pause state
-        presale_account.paused = paused;
+        let milestone = &mut presale_account.milestones[index_usize];
+        if milestone.status != MilestoneStatus::Pending {
+            return Err(ErrorCode::MilestoneAlreadyResolved.into());
+        }
+        if clock.unix_timestamp < milestone.vote_end {
+            return Err(ErrorCode::MilestoneVoteNotEnded.into());
+        }
 
-        // Emit pause state change event
-        let clock = Clock::get()?;
-        emit!(PauseStateChanged {
-            paused,
+        let approved = milestone.yes_weight > milestone.no_weight;
+        milestone.status = if approved {
+            MilestoneStatus::Approved
+        } else {
+            MilestoneStatus::Rejected
+        };
+        let (yes_weight, no_weight) = (milestone.yes_weight, milestone.no_weight);
+        if !approved {
+            presale_account.milestone_failed = true;
+        }
+
+        emit!(MilestoneResolved {
+            presale_account: presale_account.key(),
+            index,
+            approved,
+            yes_weight,
+            no_weight,
             timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-    // Batch airdrop distribution to save compute units
-    const MAX_BATCH_SIZE: usize = 50; // Set a limit for batch size
-
-    pub fn distribute_airdrops_batch(
-        ctx: Context<BatchDistributeAirdrops>,
-        users: Vec<UserDistribution>,
+    // After a failed milestone, contributors take back their share of the raise that no
+    // approved tranche released: SOL from the treasury, stablecoins from their vaults
+    // through (vault, destination) pairs in the remaining accounts.
+    pub fn reclaim_milestone_refund<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReclaimMilestoneRefund<'info>>,
     ) -> Result<()> {
-        const MAX_BATCH_SIZE: usize = 50; // Set a limit for batch size
-
-        let presale_account = &mut ctx.accounts.presale_account;
-        let clock = Clock::get()?;
-        let current_time = clock.unix_timestamp;
-
-        // Ensure presale has ended
-        if current_time < presale_account.presale_end {
-            return Err(ErrorCode::PresaleNotEnded.into());
+        let presale_account = &ctx.accounts.presale_account;
+        if presale_account.sale_state != SaleState::Active
+            || !presale_account.soft_cap_met()
+        {
+            return Err(ErrorCode::InvalidSaleState.into());
         }
-
-        // Ensure batch size does not exceed MAX_BATCH_SIZE
-        if users.len() > MAX_BATCH_SIZE {
-            return Err(ErrorCode::BatchTooLarge.into());
+        if !presale_account.milestone_failed {
+            return Err(ErrorCode::MilestoneNotFailed.into());
         }
 
-        // Iterate over users and process airdrops
-        for user in users.iter() {
-            // Validate that the user_vesting_index is within bounds
-            if user.user_vesting_index >= ctx.remaining_accounts.len() {
-                return Err(ErrorCode::InvalidUserAccountIndex.into());
-            }
+        let user_vesting = &mut ctx.accounts.user_vesting;
+        if user_vesting.milestone_refunded {
+            return Err(ErrorCode::AlreadySettled.into());
+        }
+        let weight = user_vesting.total_amount;
+        let total_weight = presale_account.milestone_vote_weight();
+        if weight == 0 || total_weight == 0 {
+            return Err(ErrorCode::InsufficientRefundBalance.into());
+        }
+        user_vesting.milestone_refunded = true;
+
+        // The locked share is refunded, so the buyer gives up that share of their payment
+        // records and their allocation; no other refund path can pay it out again
+        let locked_bps = BPS_DENOMINATOR - presale_account.milestone_released_bps();
+        user_vesting.paid_lamports -= mul_div(user_vesting.paid_lamports, locked_bps, BPS_DENOMINATOR)?;
+        user_vesting.paid_usd -= mul_div(user_vesting.paid_usd, locked_bps, BPS_DENOMINATOR)?;
+        for paid in user_vesting.paid_by_mint.iter_mut() {
+            *paid -= mul_div(*paid, locked_bps, BPS_DENOMINATOR)?;
+        }
+        let forfeited = mul_div(weight, locked_bps, BPS_DENOMINATOR)?;
+        user_vesting.total_amount = (weight - forfeited).max(user_vesting.claimed_amount);
+        claw_back_referral(
+            &mut ctx.accounts.presale_account,
+            user_vesting,
+            ctx.accounts.referral_ledger.as_mut(),
+            true,
+            locked_bps,
+            BPS_DENOMINATOR,
+        )?;
+        let presale_account = &ctx.accounts.presale_account;
 
-            // Safely load the user vesting account
-            let user_vesting_account = &mut Account::<UserVesting>::try_from(
-                &ctx.remaining_accounts[user.user_vesting_index],
+        let raised_lamports = presale_account
+            .total_paid_lamports
+            .saturating_sub(presale_account.refunded_lamports);
+        let locked_lamports = mul_div(raised_lamports, locked_bps, BPS_DENOMINATOR)?;
+        let refund_lamports = mul_div(locked_lamports, weight, total_weight)?;
+        if refund_lamports > 0 {
+            pay_from_treasury(
+                &ctx.accounts.system_program.to_account_info(),
+                &ctx.accounts.treasury.to_account_info(),
+                &ctx.accounts.buyer.to_account_info(),
+                presale_account,
+                refund_lamports,
             )?;
+        }
 
-            // Skip users who have completed all their airdrops
-            if user_vesting_account.airdrops_completed >= presale_account.total_airdrop_periods {
+        // Stablecoin shares, each out of its own vault
+        let presale_key = presale_account.key();
+        let mut remaining = ctx.remaining_accounts.iter();
+        for accepted in presale_account.accepted_mints.iter() {
+            let raised = accepted.total_received.saturating_sub(accepted.total_refunded);
+            let locked = mul_div(raised, locked_bps, BPS_DENOMINATOR)?;
+            let refund_amount = mul_div(locked, weight, total_weight)?;
+            if refund_amount == 0 {
                 continue;
             }
-
-            // Ensure airdrop index is valid
-            let airdrop_percentage = presale_account
-                .airdrop_percentages
-                .get(user.airdrop_index as usize)
-                .ok_or(ErrorCode::AirdropConfigurationError)?;
-
-            // Calculate the airdrop amount
-            let airdrop_amount = user_vesting_account
-                .total_amount
-                .checked_mul(*airdrop_percentage as u64)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(100)
-                .ok_or(ErrorCode::MathOverflow)?;
-            // Transfer the airdrop tokens
-            token::transfer(
-                ctx.accounts.into_transfer_context(user_vesting_account),
-                airdrop_amount,
+            pay_from_payment_vault(
+                &mut remaining,
+                &ctx.accounts.token_program.to_account_info(),
+                presale_key,
+                accepted,
+                ctx.accounts.buyer.key(),
+                refund_amount,
             )?;
-            if airdrop_amount == 0 {
-                continue; // Avoid unnecessary transfers or updates
-            }
-            // Update user vesting account
-            user_vesting_account.claimed_amount = user_vesting_account
-                .claimed_amount
-                .checked_add(airdrop_amount)
-                .ok_or(ErrorCode::MathOverflow)?;
-            user_vesting_account.airdrops_completed = user_vesting_account
-                .airdrops_completed
-                .checked_add(1)
-                .ok_or(ErrorCode::MathOverflow)?;
         }
 
+        emit!(MilestoneRefundClaimed {
+            presale_account: presale_key,
+            buyer: ctx.accounts.buyer.key(),
+            weight,
+            refund_lamports,
+        });
+
         Ok(())
     }
 
-    pub fn refund(ctx: Context<Refund>, refund_amount: u64) -> Result<()> {
+    pub fn update_oracle_config(
+        ctx: Context<UpdatePresaleParams>,
+        new_oracle_kind: Option<OracleKind>,
+        new_sol_usd_feed: Option<Pubkey>,
+        new_max_age: Option<u64>,
+        new_max_conf_bps: Option<u16>,
+    ) -> Result<()> {
         let presale_account = &mut ctx.accounts.presale_account;
-        let user_vesting = &mut ctx.accounts.user_vesting;
-        let clock = Clock::get()?;
-        let current_time = clock.unix_timestamp;
 
-        // Ensure the refund period is active
-        if current_time < presale_account.presale_end {
-            return Err(ErrorCode::RefundNotAvailable.into());
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
         }
-
-        // Calculate claimable and refundable tokens
-        let claimable_tokens = calculate_vested_amount(
-            user_vesting.total_amount,
-            user_vesting.start_time,
-            presale_account.vesting_period,
-            presale_account.vesting_interval,
-            current_time,
-        ).saturating_sub(user_vesting.claimed_amount);
-
-        let refundable_tokens = user_vesting
-            .total_amount
-            .saturating_sub(claimable_tokens)
-            .saturating_sub(user_vesting.claimed_amount);
-
-        if refund_amount == 0 || refundable_tokens == 0 {
-            return Err(ErrorCode::InsufficientRefundBalance.into());
+        // Once a multisig is configured, this is queued through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+        // The feed drives the price, so with a timelock the change is queued instead
+        if presale_account.timelock_delay > 0 {
+            return Err(ErrorCode::TimelockRequired.into());
         }
 
-        // Fetch SOL/USD price using fallback logic
-        let sol_price_in_usd = get_price_from_oracle(
-            &ctx.accounts.sol_to_usd_oracle,
-            presale_account.manual_price_override,
-        )?;
+        apply_param_change(
+            presale_account,
+            ParamChange::OracleConfig {
+                oracle_kind: new_oracle_kind.unwrap_or(presale_account.oracle_kind),
+                sol_usd_feed: new_sol_usd_feed.unwrap_or(presale_account.sol_usd_feed),
+                max_age: new_max_age.unwrap_or(presale_account.oracle_max_age),
+                max_conf_bps: new_max_conf_bps.unwrap_or(presale_account.oracle_max_conf_bps),
+            },
+        )
+    }
 
-        // Calculate refund amount in SOL
-        let refund_sol =
-            calculate_sol_price(refund_amount, presale_account.price, sol_price_in_usd)?;
+    // Thresholds at which purchases pause the sale on their own; zero disables a check
+    pub fn set_circuit_breaker(
+        ctx: Context<UpdatePresaleParams>,
+        config: CircuitBreakerConfig,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
 
-        // Ensure the program PDA has enough SOL for the refund
-        let program_pda = ctx.accounts.presale_account.to_account_info();
-        if **program_pda.lamports.borrow() < refund_sol {
-            return Err(ErrorCode::InsufficientProgramBalance.into());
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
         }
 
-        // Process the refund
-        **program_pda.try_borrow_mut_lamports()? -= refund_sol;
-        **ctx
-            .accounts
-            .buyer
-            .to_account_info()
-            .try_borrow_mut_lamports()? += refund_sol;
-
-        // Update metrics
-        user_vesting.total_amount = user_vesting
-            .total_amount
-            .checked_sub(refund_amount)
-            .ok_or(ErrorCode::MathOverflow)?;
-        presale_account.total_sold_sol = presale_account
-            .total_sold_sol
-            .checked_sub(refund_amount)
-            .ok_or(ErrorCode::MathOverflow)?;
+        // A raise limit needs a window to count it over
+        if config.velocity_window < 0 || (config.max_raise_per_window > 0 && config.velocity_window == 0) {
+            return Err(ErrorCode::InvalidCircuitBreakerConfig.into());
+        }
+        presale_account.circuit_breaker = config;
+        presale_account.velocity_window_start = 0;
+        presale_account.velocity_window_raised = 0;
 
-        // Emit event
-        emit!(RefundEvent {
-            buyer: ctx.accounts.buyer.key(),
-            refund_amount,
-            refund_sol,
-            remaining_tokens: refundable_tokens - refund_amount,
-            total_refund_tokens: user_vesting.total_amount,
-            total_refunded_sol: presale_account.total_sold_sol,
+        let clock = Clock::get()?;
+        emit!(CircuitBreakerUpdated {
+            presale_account: presale_account.key(),
+            config,
+            timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-    pub fn update_manual_price_override(
-        ctx: Context<UpdateManualPriceOverride>,
-        new_price: Option<u64>,
+    pub fn set_price_mode(
+        ctx: Context<UpdatePresaleParams>,
+        price_mode: PriceMode,
+        twap_window: i64,
     ) -> Result<()> {
         let presale_account = &mut ctx.accounts.presale_account;
 
@@ -418,340 +645,6178 @@ pub mod fam_presale_contract {
         if ctx.accounts.authority.key() != presale_account.authority {
             return Err(ErrorCode::UnauthorizedAccess.into());
         }
-
-        // Validate the manual price override
-        if let Some(price) = new_price {
-            if price == 0 || price > 1_000_000 {
-                // Ensure price is non-zero and within reasonable bounds
-                return Err(ErrorCode::InvalidPrice.into());
-            }
+        // Once a multisig is configured, this is queued through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+        // With a timelock, price changes are queued instead
+        if presale_account.timelock_delay > 0 {
+            return Err(ErrorCode::TimelockRequired.into());
         }
 
-        // Update the manual price override
-        presale_account.manual_price_override = new_price;
+        apply_param_change(presale_account, ParamChange::PriceMode { price_mode, twap_window })
+    }
 
-        // Emit event
+    // Permissionless crank that records a SOL/USD observation for the TWAP
+    pub fn update_price(ctx: Context<UpdatePrice>) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
         let clock = Clock::get()?;
-        emit!(ManualPriceOverrideUpdated {
-            new_price,
-            timestamp: clock.unix_timestamp,
-        });
+        let current_time = clock.unix_timestamp;
+
+        // The crank never falls back to the manual override
+        let oracle_price = get_price_from_oracle(
+            &ctx.accounts.sol_to_usd_oracle,
+            presale_account.sol_usd_feed,
+            &presale_account.oracle_config(),
+            None,
+            current_time,
+        )?;
+        presale_account.record_price_observation(oracle_price.price, current_time);
+
+        emit!(PriceObserved {
+            presale_account: presale_account.key(),
+            price: oracle_price.price,
+            timestamp: current_time,
+        });
 
         Ok(())
     }
 
-    pub fn calculate_claimable(ctx: Context<CalculateClaimable>) -> Result<u64> {
-        let user_vesting = &ctx.accounts.user_vesting;
+    // Permissionless: pause the sale if the SOL/USD feed is stale or has moved too far
+    // from the last accepted price. Purchases only fail on these conditions; this is
+    // what makes the pause stick.
+    pub fn check_circuit_breaker(ctx: Context<UpdatePrice>) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
         let clock = Clock::get()?;
         let current_time = clock.unix_timestamp;
+        let presale_key = presale_account.key();
 
-        let vested_amount = calculate_vested_amount(
-            user_vesting.total_amount,
-            user_vesting.start_time,
-            ctx.accounts.presale_account.vesting_period,
-            ctx.accounts.presale_account.vesting_interval,
-            current_time,
-        );
+        if presale_account.paused {
+            return Err(ErrorCode::PresalePaused.into());
+        }
 
-        // Return the amount of claimable tokens
-        Ok(vested_amount.saturating_sub(user_vesting.claimed_amount))
+        let reason = match oracle_staleness_trip(presale_account, &ctx.accounts.sol_to_usd_oracle, current_time)? {
+            Some(reason) => Some(reason),
+            None => {
+                let spot_price = get_price_from_oracle(
+                    &ctx.accounts.sol_to_usd_oracle,
+                    presale_account.sol_usd_feed,
+                    &presale_account.oracle_config(),
+                    presale_account.manual_price_override,
+                    current_time,
+                )?;
+                presale_account.price_deviation_trip(spot_price.price)
+            }
+        };
+        match reason {
+            Some(reason) => trip_circuit_breaker(presale_account, presale_key, reason, current_time),
+            None => Err(ErrorCode::CircuitBreakerNotTripped.into()),
+        }
     }
 
-    pub fn update_presale_discount(
+    // Replace the round ladder (e.g. seed -> private -> public) before the sale opens
+    pub fn set_sale_rounds(ctx: Context<UpdatePresaleParams>, rounds: Vec<SaleRound>) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Sale setup is done before a multisig takes over
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        // Sold totals live on the rounds, so they cannot be swapped mid-sale
+        if clock.unix_timestamp >= presale_account.presale_start {
+            return Err(ErrorCode::SaleAlreadyStarted.into());
+        }
+
+        validate_sale_rounds(
+            &rounds,
+            presale_account.presale_start,
+            presale_account.presale_end,
+        )?;
+
+        presale_account.rounds = rounds
+            .into_iter()
+            .map(|round| SaleRound {
+                tokens_sold: 0,
+                sol_raised: 0,
+                ..round
+            })
+            .collect();
+        presale_account.total_tokens_sold = 0;
+
+        emit!(SaleRoundsUpdated {
+            presale_account: presale_account.key(),
+            round_count: presale_account.rounds.len() as u8,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Switch the sale to a descending-price Dutch auction (or back to rounds with None)
+    pub fn set_dutch_auction(
         ctx: Context<UpdatePresaleParams>,
-        new_price: Option<u64>,
+        config: Option<DutchAuctionConfig>,
     ) -> Result<()> {
         let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
 
-        // Validate and update price directly
-        if let Some(price) = new_price {
-            if price == 0 {
-                return Err(ErrorCode::InvalidPrice.into());
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Sale setup is done before a multisig takes over
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        // The curve is fixed once buyers have paid along it
+        if clock.unix_timestamp >= presale_account.presale_start {
+            return Err(ErrorCode::SaleAlreadyStarted.into());
+        }
+
+        match config {
+            Some(config) => {
+                validate_dutch_auction(
+                    &config,
+                    presale_account.presale_start,
+                    presale_account.presale_end,
+                )?;
+                presale_account.pricing_mode = PricingMode::DutchAuction;
+                presale_account.dutch_auction = config;
+            }
+            None => {
+                presale_account.pricing_mode = PricingMode::Rounds;
+                presale_account.dutch_auction = DutchAuctionConfig::default();
             }
-            presale_account.price = price;
         }
+        presale_account.last_auction_price = 0;
+        presale_account.clearing_price = 0;
+
+        emit!(DutchAuctionUpdated {
+            presale_account: presale_account.key(),
+            config,
+            timestamp: clock.unix_timestamp,
+        });
 
         Ok(())
     }
 
-    pub fn distribute_initial_airdrop(ctx: Context<DistributeAirdrop>) -> Result<()> {
-        let user_vesting = &mut ctx.accounts.user_vesting;
-        let presale_account = &ctx.accounts.presale_account;
+    // Switch the sale to a bonding curve priced off tokens sold (or back to rounds with None)
+    pub fn set_bonding_curve(
+        ctx: Context<UpdatePresaleParams>,
+        config: Option<BondingCurveConfig>,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
         let clock = Clock::get()?;
-        let current_time = clock.unix_timestamp;
 
-        // Ensure presale has ended
-        if current_time < presale_account.presale_end {
-            return Err(ErrorCode::PresaleNotEnded.into());
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Sale setup is done before a multisig takes over
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
         }
 
-        // Calculate initial airdrop percentage
-        let initial_percentage = *presale_account
-            .airdrop_percentages
-            .get(0)
-            .ok_or(ErrorCode::AirdropConfigurationError)?;
-        let initial_airdrop = user_vesting
-            .total_amount
-            .checked_mul(initial_percentage)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(100)
-            .ok_or(ErrorCode::MathOverflow)?;
+        // The curve is fixed once buyers have paid along it
+        if clock.unix_timestamp >= presale_account.presale_start {
+            return Err(ErrorCode::SaleAlreadyStarted.into());
+        }
 
-        // Transfer initial airdrop
-        token::transfer(ctx.accounts.into_transfer_context(), initial_airdrop)?;
+        match config {
+            Some(config) => {
+                config.validate()?;
+                presale_account.pricing_mode = PricingMode::BondingCurve;
+                presale_account.bonding_curve = config;
+            }
+            None => {
+                presale_account.pricing_mode = PricingMode::Rounds;
+                presale_account.bonding_curve = BondingCurveConfig::default();
+            }
+        }
 
-        // Update claimed amount and airdrop count
-        user_vesting.claimed_amount += initial_airdrop;
-        user_vesting.airdrops_completed = 1;
+        emit!(BondingCurveUpdated {
+            presale_account: presale_account.key(),
+            config,
+            timestamp: clock.unix_timestamp,
+        });
 
         Ok(())
     }
 
-    pub fn distribute_monthly_airdrop(ctx: Context<DistributeAirdrop>) -> Result<()> {
-        let user_vesting = &ctx.accounts.user_vesting;
-        let presale_account = &ctx.accounts.presale_account;
+    // Choose how an oversubscribed sale is allocated, and who receives rounding dust
+    pub fn set_allocation_mode(
+        ctx: Context<UpdatePresaleParams>,
+        allocation_mode: AllocationMode,
+        dust_recipient: Pubkey,
+        lottery: Option<LotteryConfig>,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
         let clock = Clock::get()?;
-        let current_time = clock.unix_timestamp;
 
-        // Ensure at least one month has passed since the last airdrop
-        let months_elapsed =
-            (current_time - user_vesting.start_time) / presale_account.vesting_interval;
-        if months_elapsed as u8 <= user_vesting.airdrops_completed {
-            return Err(ErrorCode::AirdropNotDue.into());
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Sale setup is done before a multisig takes over
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        // Buyers commit funds on the basis of the allocation rules
+        if clock.unix_timestamp >= presale_account.presale_start {
+            return Err(ErrorCode::SaleAlreadyStarted.into());
         }
 
-        // Ensure airdrops do not exceed total periods
-        if user_vesting.airdrops_completed >= presale_account.total_airdrop_periods {
-            return Err(ErrorCode::AirdropCompleted.into());
+        // A lottery needs its ticket size, winner count and reveal window
+        match (allocation_mode, lottery) {
+            (AllocationMode::Lottery, Some(config)) => {
+                if config.ticket_tokens == 0
+                    || config.winner_count == 0
+                    || config.reveal_window < MIN_LOTTERY_REVEAL_WINDOW
+                    || config.reveal_window > MAX_LOTTERY_REVEAL_WINDOW
+                {
+                    return Err(ErrorCode::InvalidLotteryConfig.into());
+                }
+                presale_account.lottery = config;
+            }
+            (AllocationMode::Lottery, None) => return Err(ErrorCode::InvalidLotteryConfig.into()),
+            _ => presale_account.lottery = LotteryConfig::default(),
         }
+        presale_account.allocation_mode = allocation_mode;
+        presale_account.dust_recipient = dust_recipient;
 
-        // Get the percentage for the current airdrop
-        let current_percentage = *presale_account
-            .airdrop_percentages
-            .get(user_vesting.airdrops_completed as usize)
-            .ok_or(ErrorCode::AirdropConfigurationError)?;
+        emit!(AllocationModeUpdated {
+            presale_account: presale_account.key(),
+            allocation_mode,
+            dust_recipient,
+            timestamp: clock.unix_timestamp,
+        });
 
-        // Calculate the airdrop amount
-        let airdrop_amount = user_vesting
-            .total_amount
-            .checked_mul(current_percentage)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(100)
-            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
 
-        // Transfer the airdrop amount
-        token::transfer(ctx.accounts.into_transfer_context(), airdrop_amount)?;
+    // Lengthening the timelock is immediate; shortening it has to be queued
+    pub fn set_timelock_delay(ctx: Context<UpdatePresaleParams>, new_delay: i64) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+        if new_delay < presale_account.timelock_delay {
+            return Err(ErrorCode::TimelockRequired.into());
+        }
+
+        apply_param_change(presale_account, ParamChange::TimelockDelay { new_delay })
+    }
+
+    // Queue a price, cap, vesting, oracle or timelock change to take effect no earlier than `eta`
+    pub fn queue_param_change(
+        ctx: Context<UpdatePresaleParams>,
+        change: ParamChange,
+        eta: i64,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Once a multisig is configured, this goes through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        let clock = Clock::get()?;
+        let presale_key = presale_account.key();
+        enqueue_param_change(presale_account, presale_key, change, eta, clock.unix_timestamp)
+    }
+
+    pub fn cancel_param_change(ctx: Context<UpdatePresaleParams>, id: u64) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Once a multisig is configured, this goes through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        let presale_key = presale_account.key();
+        dequeue_param_change(presale_account, presale_key, id, true)
+    }
+
+    // Permissionless: apply a queued change once its eta has passed
+    pub fn execute_param_change(ctx: Context<ExecuteParamChange>, id: u64) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+
+        let queued = presale_account
+            .queued_changes
+            .iter()
+            .find(|queued| queued.id == id)
+            .cloned()
+            .ok_or(ErrorCode::QueuedChangeNotFound)?;
+        if clock.unix_timestamp < queued.eta {
+            return Err(ErrorCode::TimelockNotElapsed.into());
+        }
+
+        let presale_key = presale_account.key();
+        dequeue_param_change(presale_account, presale_key, id, false)?;
+        apply_param_change(presale_account, queued.change.clone())?;
 
-        // Update claimed amount and airdrop count
-        user_vesting.claimed_amount += airdrop_amount;
-        user_vesting.airdrops_completed += 1;
+        emit!(ParamChangeExecuted {
+            presale_account: presale_account.key(),
+            id,
+            change: queued.change,
+            timestamp: clock.unix_timestamp,
+        });
 
         Ok(())
     }
-}
 
-// Utility function: Place this outside the #[program] module
-pub fn derive_presale_pda(program_id: &Pubkey, seed: &[u8]) -> Pubkey {
-    Pubkey::find_program_address(&[seed], program_id).0
-}
+    pub fn update_presale_params(
+        ctx: Context<UpdatePresaleParams>,
+        new_price: Option<u64>,
+        new_min_buy_amount: Option<u64>,
+        new_max_buy_amount: Option<u64>,
+        new_hard_cap: Option<u64>,
+        new_soft_cap: Option<u64>,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
 
-pub fn calculate_sol_price(
-    amount: u64,
-    price: u64,
-    sol_price_in_usd: u64,
-) -> Result<u64> {
-    amount
-        .checked_mul(price)
-        .ok_or(ErrorCode::MathOverflow)?
-        .checked_mul(10u64.pow(9)) // Convert USD cents to lamports
-        .ok_or(ErrorCode::MathOverflow)?
-        .checked_div(sol_price_in_usd)
-        .ok_or(ErrorCode::MathOverflow)
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Once a multisig is configured, this goes through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+        // With a timelock, price and cap changes are queued instead
+        if presale_account.timelock_delay > 0 {
+            return Err(ErrorCode::TimelockRequired.into());
+        }
+
+        apply_presale_params(
+            presale_account,
+            PresaleParams {
+                new_price,
+                new_min_buy_amount,
+                new_max_buy_amount,
+                new_hard_cap,
+                new_soft_cap,
+            },
+        )
+    }
+
+    // Require (or, with None, stop requiring) vouchers from an off-chain compliance signer
+    pub fn set_compliance_signer(
+        ctx: Context<UpdatePresaleParams>,
+        compliance_signer: Option<Pubkey>,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Once a multisig is configured, this goes through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        let presale_key = presale_account.key();
+        apply_compliance_signer(presale_account, presale_key, compliance_signer)
+    }
+
+    // Commit (or clear) the Merkle root of (wallet, max allocation) leaves for private rounds
+    pub fn set_allowlist_root(
+        ctx: Context<UpdatePresaleParams>,
+        new_root: Option<[u8; 32]>,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Once a multisig is configured, this goes through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        let presale_key = presale_account.key();
+        apply_allowlist_root(presale_account, presale_key, new_root)
+    }
+
+    pub fn set_referral_config(
+        ctx: Context<UpdatePresaleParams>,
+        reward_bps: u16,
+        reward_kind: ReferralRewardKind,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Once a multisig is configured, this goes through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        apply_referral_config(presale_account, reward_bps, reward_kind)
+    }
+
+    // Create the referrer's reward ledger so buyers can name them in `purchase`
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        let referral_ledger = &mut ctx.accounts.referral_ledger;
+        referral_ledger.presale_account = ctx.accounts.presale_account.key();
+        referral_ledger.referrer = ctx.accounts.referrer.key();
+        referral_ledger.bump = *ctx.bumps.get("referral_ledger").unwrap();
+        referral_ledger.version = ReferralLedger::VERSION;
+
+        Ok(())
+    }
+
+    pub fn purchase(
+        ctx: Context<Purchase>,
+        amount: u64,
+        allowlist: Option<AllowlistProof>,
+        referrer: Option<Pubkey>,
+        voucher: Option<PurchaseVoucher>,
+    ) -> Result<()> {
+        // Fetch current timestamp
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let buyer_key = ctx.accounts.buyer.key();
+        let presale_key = ctx.accounts.presale_account.key();
+
+        // Ensure presale is not paused, so a paused sale never re-trips the breaker
+        if ctx.accounts.presale_account.paused {
+            return Err(ErrorCode::PresalePaused.into());
+        }
+
+        // A stale feed fails the purchase; `check_circuit_breaker` records the pause
+        if oracle_staleness_trip(
+            &ctx.accounts.presale_account,
+            &ctx.accounts.sol_to_usd_oracle,
+            current_time,
+        )?
+        .is_some()
+        {
+            return Err(ErrorCode::CircuitBreakerTripped.into());
+        }
+
+        // Fetch SOL/USD price using fallback logic
+        let spot_price = get_price_from_oracle(
+            &ctx.accounts.sol_to_usd_oracle,
+            ctx.accounts.presale_account.sol_usd_feed,
+            &ctx.accounts.presale_account.oracle_config(),
+            ctx.accounts.presale_account.manual_price_override,
+            current_time,
+        )?;
+
+        // So does a price too far from the last accepted one
+        if ctx.accounts.presale_account.accept_price(spot_price.price).is_some() {
+            return Err(ErrorCode::CircuitBreakerTripped.into());
+        }
+
+        // Price off spot, TWAP or the worse of the two, per the project's price mode
+        let sol_price_in_usd =
+            ctx.accounts.presale_account.purchase_sol_price(spot_price, current_time)?;
+
+        // KYC-gated sales need a fresh voucher from the compliance signer
+        if let Some(compliance_signer) = ctx.accounts.presale_account.compliance_signer {
+            verify_purchase_voucher(
+                &ctx.accounts.instructions,
+                compliance_signer,
+                &mut ctx.accounts.user_vesting,
+                presale_key,
+                buyer_key,
+                amount,
+                voucher,
+                current_time,
+            )?;
+        }
+
+        let cost = execute_purchase(
+            &mut ctx.accounts.presale_account,
+            &mut ctx.accounts.user_vesting,
+            presale_key,
+            buyer_key,
+            amount,
+            sol_price_in_usd,
+            current_time,
+            allowlist,
+        )?;
+        let total_cost_in_sol = cost.sol;
+
+        // Ensure the buyer has enough SOL
+        if **ctx.accounts.buyer.to_account_info().lamports.borrow() < total_cost_in_sol {
+            return Err(ErrorCode::InsufficientFunds.into());
+        }
+
+        // Credit the referrer's ledger, passed as the first remaining account
+        if let Some(referrer) = referrer {
+            credit_referral(
+                ctx.remaining_accounts,
+                ctx.program_id,
+                &mut ctx.accounts.presale_account,
+                &mut ctx.accounts.user_vesting,
+                presale_key,
+                buyer_key,
+                referrer,
+                amount,
+                total_cost_in_sol,
+                None,
+            )?;
+        }
+
+        // Remember exactly what was paid so a failed sale can be unwound
+        let user_vesting = &mut ctx.accounts.user_vesting;
+        user_vesting.paid_lamports = user_vesting
+            .paid_lamports
+            .checked_add(total_cost_in_sol)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let presale_account = &mut ctx.accounts.presale_account;
+        presale_account.total_paid_lamports = presale_account
+            .total_paid_lamports
+            .checked_add(total_cost_in_sol)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // --- EXTERNAL CALL ---
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.buyer.to_account_info(),
+                    to: ctx.accounts.treasury.to_account_info(),
+                },
+            ),
+            total_cost_in_sol,
+        )?;
+
+        // Emit event
+        emit!(PurchaseEvent {
+            presale_account: presale_key, // Reference the public key of the account
+            buyer: buyer_key,
+            amount,
+            cost_in_sol: Some(total_cost_in_sol),
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    // Register a stablecoin (USDC, USDT, PYUSD...) and create its program-owned vault. Under
+    // a multisig a signer runs this against an approved `AddPaymentMint` proposal, since the
+    // vault has to be created here.
+    pub fn add_payment_mint(
+        ctx: Context<AddPaymentMint>,
+        oracle: Option<Pubkey>,
+        oracle_kind: OracleKind, // Provider behind `oracle`; ignored for a 1:1 peg
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let payment_mint = &ctx.accounts.payment_mint;
+
+        if presale_account.multisig.is_some() {
+            let action = consume_approved_proposal(
+                presale_account,
+                ctx.accounts.multisig.as_deref(),
+                ctx.accounts.proposal.as_mut(),
+                &ctx.accounts.authority.key(),
+            )?;
+            match action {
+                AdminAction::AddPaymentMint {
+                    mint,
+                    oracle: approved_oracle,
+                    oracle_kind: approved_kind,
+                } if mint == payment_mint.key()
+                    && approved_oracle == oracle
+                    && approved_kind == oracle_kind => {}
+                _ => return Err(ErrorCode::ProposalActionMismatch.into()),
+            }
+        } else if ctx.accounts.authority.key() != presale_account.authority {
+            // Ensure caller is the authorized admin
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+
+        if presale_account.accepted_mints.len() >= MAX_PAYMENT_MINTS {
+            return Err(ErrorCode::PaymentMintLimitReached.into());
+        }
+        if presale_account
+            .accepted_mints
+            .iter()
+            .any(|accepted| accepted.mint == payment_mint.key())
+        {
+            return Err(ErrorCode::PaymentMintAlreadyRegistered.into());
+        }
+
+        presale_account.accepted_mints.push(AcceptedMint {
+            mint: payment_mint.key(),
+            decimals: payment_mint.decimals,
+            oracle,
+            vault: ctx.accounts.payment_vault.key(),
+            vault_bump: *ctx.bumps.get("payment_vault").unwrap(),
+            enabled: true,
+            total_received: 0,
+            total_refunded: 0,
+        });
+        presale_account.oracle_kind_by_mint[presale_account.accepted_mints.len() - 1] = oracle_kind;
+
+        let clock = Clock::get()?;
+        emit!(PaymentMintUpdated {
+            presale_account: presale_account.key(),
+            mint: payment_mint.key(),
+            oracle,
+            oracle_kind,
+            enabled: true,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Mints are never removed from the registry so indexes stay stable; disable instead
+    pub fn set_payment_mint_enabled(
+        ctx: Context<UpdatePresaleParams>,
+        mint: Pubkey,
+        enabled: bool,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Once a multisig is configured, this goes through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        let presale_key = presale_account.key();
+        apply_payment_mint_enabled(presale_account, presale_key, mint, enabled)
+    }
+
+    pub fn purchase_with_token(
+        ctx: Context<PurchaseWithToken>,
+        amount: u64,
+        allowlist: Option<AllowlistProof>,
+        referrer: Option<Pubkey>,
+        voucher: Option<PurchaseVoucher>,
+    ) -> Result<()> {
+        // Fetch current timestamp
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let mint_key = ctx.accounts.payment_mint.key();
+        let mint_index = ctx
+            .accounts
+            .presale_account
+            .accepted_mints
+            .iter()
+            .position(|accepted| accepted.mint == mint_key)
+            .ok_or(ErrorCode::InvalidPaymentMethod)?;
+        let accepted = ctx.accounts.presale_account.accepted_mints[mint_index].clone();
+        if !accepted.enabled {
+            return Err(ErrorCode::InvalidPaymentMethod.into());
+        }
+        if ctx.accounts.payment_vault.key() != accepted.vault {
+            return Err(ErrorCode::InvalidPaymentVault.into());
+        }
+
+        // Price the payment mint in USD cents: either its own feed, read with its own
+        // provider, or a 1:1 peg
+        let oracle_config = ctx.accounts.presale_account.oracle_config();
+        let token_price_in_usd = match accepted.oracle {
+            Some(oracle) => get_price_from_oracle(
+                &ctx.accounts.payment_oracle,
+                oracle,
+                &OracleConfig {
+                    kind: ctx.accounts.presale_account.oracle_kind_by_mint[mint_index],
+                    ..oracle_config
+                },
+                None,
+                current_time,
+            )?
+            .price,
+            None => USD_PEG_PRICE,
+        };
+
+        let buyer_key = ctx.accounts.buyer.key();
+        let presale_key = ctx.accounts.presale_account.key();
+
+        // Ensure presale is not paused, so a paused sale never re-trips the breaker
+        if ctx.accounts.presale_account.paused {
+            return Err(ErrorCode::PresalePaused.into());
+        }
+
+        // A stale SOL/USD feed fails the purchase, as in `purchase`
+        if oracle_staleness_trip(
+            &ctx.accounts.presale_account,
+            &ctx.accounts.sol_to_usd_oracle,
+            current_time,
+        )?
+        .is_some()
+        {
+            return Err(ErrorCode::CircuitBreakerTripped.into());
+        }
+
+        // SOL/USD is still needed for the SOL-equivalent raise totals
+        let spot_price = get_price_from_oracle(
+            &ctx.accounts.sol_to_usd_oracle,
+            ctx.accounts.presale_account.sol_usd_feed,
+            &oracle_config,
+            ctx.accounts.presale_account.manual_price_override,
+            current_time,
+        )?;
+        if ctx.accounts.presale_account.accept_price(spot_price.price).is_some() {
+            return Err(ErrorCode::CircuitBreakerTripped.into());
+        }
+        let sol_price_in_usd =
+            ctx.accounts.presale_account.purchase_sol_price(spot_price, current_time)?;
+
+        // KYC-gated sales need a fresh voucher from the compliance signer
+        if let Some(compliance_signer) = ctx.accounts.presale_account.compliance_signer {
+            verify_purchase_voucher(
+                &ctx.accounts.instructions,
+                compliance_signer,
+                &mut ctx.accounts.user_vesting,
+                presale_key,
+                buyer_key,
+                amount,
+                voucher,
+                current_time,
+            )?;
+        }
+
+        let cost = execute_purchase(
+            &mut ctx.accounts.presale_account,
+            &mut ctx.accounts.user_vesting,
+            presale_key,
+            buyer_key,
+            amount,
+            sol_price_in_usd,
+            current_time,
+            allowlist,
+        )?;
+
+        let payment_amount = usd_to_token_amount(cost.usd, accepted.decimals, token_price_in_usd)?;
+
+        // Ensure the buyer has enough of the payment token
+        if ctx.accounts.buyer_payment_account.amount < payment_amount {
+            return Err(ErrorCode::InsufficientFunds.into());
+        }
+
+        let accepted = &mut ctx.accounts.presale_account.accepted_mints[mint_index];
+        accepted.total_received = accepted
+            .total_received
+            .checked_add(payment_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Remember exactly what was paid so a failed sale can be unwound
+        let user_vesting = &mut ctx.accounts.user_vesting;
+        user_vesting.paid_by_mint[mint_index] = user_vesting.paid_by_mint[mint_index]
+            .checked_add(payment_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Credit the referrer's ledger, passed as the first remaining account
+        if let Some(referrer) = referrer {
+            credit_referral(
+                ctx.remaining_accounts,
+                ctx.program_id,
+                &mut ctx.accounts.presale_account,
+                &mut ctx.accounts.user_vesting,
+                presale_key,
+                buyer_key,
+                referrer,
+                amount,
+                payment_amount,
+                Some(mint_index),
+            )?;
+        }
+
+        // --- EXTERNAL CALL ---
+        token::transfer(ctx.accounts.into_transfer_context(), payment_amount)?;
+
+        emit!(TokenPurchaseEvent {
+            presale_account: presale_key,
+            buyer: buyer_key,
+            amount,
+            payment_mint: mint_key,
+            payment_amount,
+            cost_in_usd: cost.usd,
+            cost_in_sol_equivalent: cost.sol,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_pause_state(ctx: Context<SetPauseState>, paused: bool) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller holds the pauser role
+        if !presale_account.has_role(&ctx.accounts.authority.key(), Role::Pauser) {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Resuming after a circuit-breaker trip is for the super-admin (or multisig) only
+        if !paused
+            && presale_account.pause_reason.is_some()
+            && !presale_account.has_role(&ctx.accounts.authority.key(), Role::SuperAdmin)
+        {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+
+        apply_pause_state(presale_account, paused)
+    }
+
+    // Batch airdrop distribution to save compute units
+    const MAX_BATCH_SIZE: usize = 50; // Set a limit for batch size
+
+    pub fn distribute_airdrops_batch<'info>(
+        ctx: Context<'_, '_, '_, 'info, BatchDistributeAirdrops<'info>>,
+        users: Vec<UserDistribution>,
+    ) -> Result<()> {
+        const MAX_BATCH_SIZE: usize = 50; // Set a limit for batch size
+
+        let presale_account = &ctx.accounts.presale_account;
+        let presale_key = presale_account.key();
+        let current_time = Clock::get()?.unix_timestamp;
+
+        // Ensure caller holds the distributor role
+        if !presale_account.has_role(&ctx.accounts.authority.key(), Role::Distributor) {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+
+        // Airdrops start at the TGE set by `finalize_sale`
+        if !presale_account.finalized {
+            return Err(ErrorCode::SaleNotFinalized.into());
+        }
+
+        // A failed sale pays nothing out; buyers reclaim their payment instead
+        if presale_account.sale_state == SaleState::Failed {
+            return Err(ErrorCode::SaleFailed.into());
+        }
+
+        // Ensure batch size does not exceed MAX_BATCH_SIZE
+        if users.len() > MAX_BATCH_SIZE {
+            return Err(ErrorCode::BatchTooLarge.into());
+        }
+
+        // Iterate over users and process airdrops
+        for user in users.iter() {
+            // Validate that both indexes are within bounds
+            if user.user_vesting_index >= ctx.remaining_accounts.len()
+                || user.recipient_index >= ctx.remaining_accounts.len()
+            {
+                return Err(ErrorCode::InvalidUserAccountIndex.into());
+            }
+
+            // Safely load the user vesting account
+            let user_vesting_account = &mut Account::<UserVesting>::try_from(
+                &ctx.remaining_accounts[user.user_vesting_index],
+            )?;
+            if user_vesting_account.presale_account != presale_key {
+                return Err(ErrorCode::UnauthorizedAccess.into());
+            }
+            ensure_allocation_settled(presale_account, user_vesting_account)?;
+
+            // Tokens only go to the buyer's own sale token account
+            let recipient_info = &ctx.remaining_accounts[user.recipient_index];
+            let recipient = Account::<TokenAccount>::try_from(recipient_info)?;
+            if recipient.mint != presale_account.sale_mint
+                || recipient.owner != user_vesting_account.owner
+            {
+                return Err(ErrorCode::InvalidRecipientAccount.into());
+            }
+
+            let airdrop_amount = if presale_account.vesting_mode == VestingMode::Piecewise {
+                // Piecewise sales send whatever has unlocked since the last payout
+                presale_account.unclaimed_vested(
+                    ctx.accounts.unlock_schedule.as_deref(),
+                    user_vesting_account,
+                    current_time,
+                )?
+            } else {
+                // Skip users who have completed all their airdrops
+                if user_vesting_account.airdrops_completed >= presale_account.total_airdrop_periods {
+                    continue;
+                }
+
+                // Airdrops go out in order, each once it is due
+                if user.airdrop_index != user_vesting_account.airdrops_completed {
+                    return Err(ErrorCode::AirdropConfigurationError.into());
+                }
+                if !presale_account.airdrop_due(user_vesting_account, current_time) {
+                    continue;
+                }
+
+                // Ensure airdrop index is valid
+                let airdrop_percentage = presale_account
+                    .airdrop_percentages
+                    .get(user.airdrop_index as usize)
+                    .ok_or(ErrorCode::AirdropConfigurationError)?;
+
+                // Calculate the airdrop amount
+                presale_account.airdrop_amount(user_vesting_account, *airdrop_percentage)?
+            };
+            if airdrop_amount == 0 {
+                continue; // Avoid unnecessary transfers or updates
+            }
+            // Update user vesting account
+            user_vesting_account.claimed_amount = user_vesting_account
+                .claimed_amount
+                .checked_add(airdrop_amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+            if presale_account.vesting_mode != VestingMode::Piecewise {
+                user_vesting_account.airdrops_completed = user_vesting_account
+                    .airdrops_completed
+                    .checked_add(1)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+            user_vesting_account.exit(ctx.program_id)?;
+
+            // Transfer the airdrop tokens
+            pay_from_sale_vault(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.sale_vault.to_account_info(),
+                recipient_info,
+                presale_account,
+                airdrop_amount,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Remaining accounts: a (payment vault, buyer token account) pair for every accepted
+    // mint the buyer still has a payment in, in registry order
+    pub fn refund<'info>(
+        ctx: Context<'_, '_, '_, 'info, Refund<'info>>,
+        refund_amount: u64,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let user_vesting = &mut ctx.accounts.user_vesting;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Ensure the refund period is active
+        if current_time < presale_account.presale_end {
+            return Err(ErrorCode::RefundNotAvailable.into());
+        }
+
+        // A failed sale refunds in full through `reclaim_sol_payment`
+        if presale_account.sale_state == SaleState::Failed {
+            return Err(ErrorCode::SaleFailed.into());
+        }
+
+        // Allocated sales refund the unfilled excess through settlement instead
+        if presale_account.allocation_mode != AllocationMode::FirstCome {
+            return Err(ErrorCode::RefundNotAvailable.into());
+        }
+
+        // Clearing-price sales settle every payment at the clearing price instead
+        if presale_account.pricing_mode == PricingMode::DutchAuction
+            && presale_account.dutch_auction.clearing_settlement.is_some()
+        {
+            return Err(ErrorCode::RefundNotAvailable.into());
+        }
+
+        // Calculate claimable and refundable tokens
+        let claimable_tokens = presale_account.unclaimed_vested(
+            ctx.accounts.unlock_schedule.as_deref(),
+            user_vesting,
+            current_time,
+        )?;
+
+        let refundable_tokens = user_vesting
+            .total_amount
+            .saturating_sub(claimable_tokens)
+            .saturating_sub(user_vesting.claimed_amount);
+
+        if refund_amount == 0 || refund_amount > refundable_tokens {
+            return Err(ErrorCode::InsufficientRefundBalance.into());
+        }
+
+        // The soft cap stays judged on the raise at the close, not on what refunds leave
+        if presale_account.raise_at_close_usd.is_none() {
+            presale_account.raise_at_close_usd = Some(presale_account.total_sold_usd);
+        }
+
+        // The buyer gets back exactly the refunded share of what they paid, in the
+        // currencies they paid in. The same share leaves their payment records, so a
+        // later failed-sale reclaim cannot pay it out again.
+        let total_amount = user_vesting.total_amount;
+        let refund_sol = mul_div(user_vesting.paid_lamports, refund_amount, total_amount)?;
+        let refunded_usd = mul_div(user_vesting.paid_usd, refund_amount, total_amount)?;
+        let refunded_sol_equivalent =
+            mul_div(user_vesting.total_purchased_sol, refund_amount, total_amount)?;
+        user_vesting.paid_lamports -= refund_sol;
+        user_vesting.paid_usd -= refunded_usd;
+        user_vesting.total_purchased_sol -= refunded_sol_equivalent;
+        user_vesting.total_amount = total_amount - refund_amount;
+        claw_back_referral(
+            presale_account,
+            user_vesting,
+            ctx.accounts.referral_ledger.as_mut(),
+            true,
+            refund_amount,
+            total_amount,
+        )?;
+        presale_account.refunded_lamports = presale_account
+            .refunded_lamports
+            .checked_add(refund_sol)
+            .ok_or(ErrorCode::MathOverflow)?;
+        presale_account.total_sold_usd = presale_account.total_sold_usd.saturating_sub(refunded_usd);
+        presale_account.total_sold_sol = presale_account
+            .total_sold_sol
+            .saturating_sub(refunded_sol_equivalent);
+
+        // SOL share, out of the treasury
+        if refund_sol > 0 {
+            pay_from_treasury(
+                &ctx.accounts.system_program.to_account_info(),
+                &ctx.accounts.treasury.to_account_info(),
+                &ctx.accounts.buyer.to_account_info(),
+                &ctx.accounts.presale_account,
+                refund_sol,
+            )?;
+        }
+
+        // Stablecoin shares, each out of its own vault
+        let presale_key = ctx.accounts.presale_account.key();
+        let mut remaining = ctx.remaining_accounts.iter();
+        for index in 0..ctx.accounts.presale_account.accepted_mints.len() {
+            let user_vesting = &mut ctx.accounts.user_vesting;
+            let mint_refund = mul_div(user_vesting.paid_by_mint[index], refund_amount, total_amount)?;
+            if mint_refund == 0 {
+                continue;
+            }
+            user_vesting.paid_by_mint[index] -= mint_refund;
+
+            let accepted_mint = &mut ctx.accounts.presale_account.accepted_mints[index];
+            accepted_mint.total_refunded = accepted_mint
+                .total_refunded
+                .checked_add(mint_refund)
+                .ok_or(ErrorCode::MathOverflow)?;
+
+            pay_from_payment_vault(
+                &mut remaining,
+                &ctx.accounts.token_program.to_account_info(),
+                presale_key,
+                &ctx.accounts.presale_account.accepted_mints[index],
+                ctx.accounts.buyer.key(),
+                mint_refund,
+            )?;
+        }
+
+        // Emit event
+        emit!(RefundEvent {
+            buyer: ctx.accounts.buyer.key(),
+            refund_amount,
+            refund_sol,
+            remaining_tokens: refundable_tokens - refund_amount,
+            total_refund_tokens: ctx.accounts.user_vesting.total_amount,
+            total_refunded_sol: ctx.accounts.presale_account.total_sold_sol,
+        });
+
+        Ok(())
+    }
+
+    pub fn update_manual_price_override(
+        ctx: Context<UpdateManualPriceOverride>,
+        new_price: Option<u64>,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller holds the price-operator role
+        if !presale_account.has_role(&ctx.accounts.authority.key(), Role::PriceOperator) {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+
+        apply_manual_price_override(presale_account, new_price)
+    }
+
+    pub fn grant_role(ctx: Context<UpdatePresaleParams>, role: Role, holder: Pubkey) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Once a multisig is configured, this goes through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        let presale_key = presale_account.key();
+        apply_grant_role(presale_account, presale_key, role, holder)
+    }
+
+    pub fn revoke_role(ctx: Context<UpdatePresaleParams>, role: Role, holder: Pubkey) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Once a multisig is configured, this goes through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        let presale_key = presale_account.key();
+        apply_revoke_role(presale_account, presale_key, role, holder)
+    }
+
+    // Step one of the super-admin handover; None cancels a pending proposal
+    pub fn propose_authority(
+        ctx: Context<UpdatePresaleParams>,
+        new_authority: Option<Pubkey>,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        // Once a multisig is configured, this goes through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+
+        let presale_key = presale_account.key();
+        apply_propose_authority(presale_account, presale_key, new_authority)
+    }
+
+    // Step two: the proposed key signs to take over, proving it is controlled
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let new_authority = ctx.accounts.new_authority.key();
+
+        if presale_account.pending_authority != Some(new_authority) {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+
+        let previous_authority = presale_account.authority;
+        presale_account.authority = new_authority;
+        presale_account.pending_authority = None;
+
+        let clock = Clock::get()?;
+        emit!(AuthorityTransferred {
+            presale_account: presale_account.key(),
+            previous_authority,
+            new_authority,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Grow the presale, and any of its vesting, referral, unlock schedule, multisig or proposal
+    // accounts passed as remaining accounts, to the current layout. The super-admin pays for
+    // the extra space. Under a multisig a signer runs it instead, passing the multisig and an
+    // approved `Migrate` proposal among the remaining accounts so both are grown first.
+    pub fn migrate<'info>(ctx: Context<'_, '_, '_, 'info, Migrate<'info>>) -> Result<()> {
+        let payer = ctx.accounts.authority.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let presale_info = ctx.accounts.presale_account.to_account_info();
+        let presale_key = presale_info.key();
+        let clock = Clock::get()?;
+
+        let mut presale_account =
+            migrate_account::<PresaleAccount>(&presale_info, &payer, &system_program)?;
+
+        // Ensure caller is the authorized admin
+        if presale_account.multisig.is_none() && !presale_account.has_role(&payer.key(), Role::SuperAdmin) {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        let from_version = presale_account.upgrade();
+        write_account(&presale_info, &presale_account)?;
+        emit!(AccountMigrated {
+            account: presale_key,
+            from_version,
+            to_version: PresaleAccount::VERSION,
+            timestamp: clock.unix_timestamp,
+        });
+
+        let mut approving_multisig = None;
+        let mut migrate_proposal = None;
+        for account in ctx.remaining_accounts.iter() {
+            if !account.is_writable {
+                return Err(ErrorCode::InvalidMigrationAccount.into());
+            }
+            let mut discriminator = [0u8; 8];
+            if let Some(head) = account.try_borrow_data()?.get(..8) {
+                discriminator.copy_from_slice(head);
+            }
+
+            let (from_version, to_version) = if discriminator == UserVesting::DISCRIMINATOR {
+                let mut user_vesting = migrate_account::<UserVesting>(account, &payer, &system_program)?;
+                // Records from before buyer binding carry no presale yet
+                if user_vesting.presale_account != presale_key
+                    && user_vesting.presale_account != Pubkey::default()
+                {
+                    return Err(ErrorCode::InvalidMigrationAccount.into());
+                }
+                let from_version = user_vesting.upgrade();
+                write_account(account, &user_vesting)?;
+                (from_version, UserVesting::VERSION)
+            } else if discriminator == ReferralLedger::DISCRIMINATOR {
+                let mut referral_ledger = migrate_account::<ReferralLedger>(account, &payer, &system_program)?;
+                if referral_ledger.presale_account != presale_key {
+                    return Err(ErrorCode::InvalidMigrationAccount.into());
+                }
+                let from_version = referral_ledger.upgrade();
+                write_account(account, &referral_ledger)?;
+                (from_version, ReferralLedger::VERSION)
+            } else if discriminator == UnlockSchedule::DISCRIMINATOR {
+                let mut unlock_schedule = migrate_account::<UnlockSchedule>(account, &payer, &system_program)?;
+                if unlock_schedule.presale_account != presale_key {
+                    return Err(ErrorCode::InvalidMigrationAccount.into());
+                }
+                let from_version = unlock_schedule.upgrade();
+                write_account(account, &unlock_schedule)?;
+                (from_version, UnlockSchedule::VERSION)
+            } else if discriminator == Multisig::DISCRIMINATOR {
+                let mut multisig = migrate_account::<Multisig>(account, &payer, &system_program)?;
+                if multisig.presale_account != presale_key {
+                    return Err(ErrorCode::InvalidMigrationAccount.into());
+                }
+                let from_version = multisig.upgrade();
+                write_account(account, &multisig)?;
+                if presale_account.multisig == Some(account.key()) {
+                    approving_multisig = Some(multisig);
+                }
+                (from_version, Multisig::VERSION)
+            } else if discriminator == Proposal::DISCRIMINATOR {
+                let mut proposal = migrate_account::<Proposal>(account, &payer, &system_program)?;
+                if presale_account.multisig != Some(proposal.multisig) {
+                    return Err(ErrorCode::InvalidMigrationAccount.into());
+                }
+                let from_version = proposal.upgrade();
+                write_account(account, &proposal)?;
+                if matches!(proposal.action, AdminAction::Migrate) && !proposal.executed {
+                    migrate_proposal = Some((account, proposal));
+                }
+                (from_version, Proposal::VERSION)
+            } else {
+                return Err(ErrorCode::InvalidMigrationAccount.into());
+            };
+
+            emit!(AccountMigrated {
+                account: account.key(),
+                from_version,
+                to_version,
+                timestamp: clock.unix_timestamp,
+            });
+        }
+
+        if presale_account.multisig.is_some() {
+            let multisig = approving_multisig.ok_or(ErrorCode::MultisigRequired)?;
+            let (proposal_info, mut proposal) = migrate_proposal.ok_or(ErrorCode::MultisigRequired)?;
+            consume_proposal(&multisig, &mut proposal, &payer.key())?;
+            write_account(proposal_info, &proposal)?;
+            emit!(ProposalExecuted {
+                proposal: proposal_info.key(),
+                executor: payer.key(),
+                timestamp: clock.unix_timestamp,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Hand the price, pause and manual-price controls to an M-of-N signer set
+    pub fn create_multisig(
+        ctx: Context<CreateMultisig>,
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        validate_multisig_signers(&signers, threshold)?;
+
+        let multisig = &mut ctx.accounts.multisig;
+        multisig.presale_account = presale_account.key();
+        multisig.signers = signers;
+        multisig.threshold = threshold;
+        multisig.signer_set_version = 0;
+        multisig.proposal_count = 0;
+        multisig.bump = *ctx.bumps.get("multisig").unwrap();
+        multisig.version = Multisig::VERSION;
+        presale_account.multisig = Some(multisig.key());
+
+        let clock = Clock::get()?;
+        emit!(MultisigUpdated {
+            multisig: multisig.key(),
+            signers: multisig.signers.clone(),
+            threshold,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Any signer proposes an admin action; proposing counts as the first approval
+    pub fn propose_admin_action(ctx: Context<ProposeAdminAction>, action: AdminAction) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let signer_index = multisig.signer_index(&ctx.accounts.proposer.key())?;
+        if let AdminAction::ChangeSigners { signers, threshold } = &action {
+            validate_multisig_signers(signers, *threshold)?;
+        }
+
+        let clock = Clock::get()?;
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.multisig = multisig.key();
+        proposal.index = multisig.proposal_count;
+        proposal.proposer = ctx.accounts.proposer.key();
+        proposal.action = action;
+        proposal.signer_set_version = multisig.signer_set_version;
+        proposal.approvals = 1 << signer_index;
+        proposal.executed = false;
+        proposal.created_at = clock.unix_timestamp;
+        proposal.bump = *ctx.bumps.get("proposal").unwrap();
+        proposal.version = Proposal::VERSION;
+        multisig.proposal_count = multisig
+            .proposal_count
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(ProposalCreated {
+            multisig: multisig.key(),
+            proposal: proposal.key(),
+            index: proposal.index,
+            proposer: proposal.proposer,
+            action: proposal.action.clone(),
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn approve_proposal(ctx: Context<ApproveProposal>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let proposal = &mut ctx.accounts.proposal;
+        let signer_index = multisig.signer_index(&ctx.accounts.signer.key())?;
+
+        if proposal.executed {
+            return Err(ErrorCode::ProposalAlreadyExecuted.into());
+        }
+        // Approvals are positional, so they only count for the signer set they were made under
+        if proposal.signer_set_version != multisig.signer_set_version {
+            return Err(ErrorCode::StaleProposal.into());
+        }
+        proposal.approvals |= 1 << signer_index;
+
+        let clock = Clock::get()?;
+        emit!(ProposalApproved {
+            proposal: proposal.key(),
+            signer: ctx.accounts.signer.key(),
+            approvals: proposal.approvals.count_ones() as u8,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Any signer executes a proposal once it has reached the threshold
+    pub fn execute_proposal(ctx: Context<ExecuteProposal>) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let proposal = &mut ctx.accounts.proposal;
+        consume_proposal(multisig, proposal, &ctx.accounts.executor.key())?;
+
+        let presale_account = &mut ctx.accounts.presale_account;
+        match proposal.action.clone() {
+            AdminAction::UpdatePresaleParams(params) => {
+                // With a timelock, the multisig queues the change like anyone else
+                if presale_account.timelock_delay > 0 {
+                    return Err(ErrorCode::TimelockRequired.into());
+                }
+                apply_presale_params(presale_account, params)?
+            }
+            AdminAction::QueueParamChange { change, eta } => {
+                let clock = Clock::get()?;
+                let presale_key = presale_account.key();
+                enqueue_param_change(presale_account, presale_key, change, eta, clock.unix_timestamp)?
+            }
+            AdminAction::CancelParamChange { id } => {
+                let presale_key = presale_account.key();
+                dequeue_param_change(presale_account, presale_key, id, true)?
+            }
+            AdminAction::SetPauseState { paused } => apply_pause_state(presale_account, paused)?,
+            AdminAction::UpdateManualPriceOverride { new_price } => {
+                apply_manual_price_override(presale_account, new_price)?
+            }
+            AdminAction::SetComplianceSigner { compliance_signer } => {
+                let presale_key = presale_account.key();
+                apply_compliance_signer(presale_account, presale_key, compliance_signer)?
+            }
+            AdminAction::SetAllowlistRoot { new_root } => {
+                let presale_key = presale_account.key();
+                apply_allowlist_root(presale_account, presale_key, new_root)?
+            }
+            AdminAction::SetReferralConfig { reward_bps, reward_kind } => {
+                apply_referral_config(presale_account, reward_bps, reward_kind)?
+            }
+            AdminAction::SetPaymentMintEnabled { mint, enabled } => {
+                let presale_key = presale_account.key();
+                apply_payment_mint_enabled(presale_account, presale_key, mint, enabled)?
+            }
+            AdminAction::GrantRole { role, holder } => {
+                let presale_key = presale_account.key();
+                apply_grant_role(presale_account, presale_key, role, holder)?
+            }
+            AdminAction::RevokeRole { role, holder } => {
+                let presale_key = presale_account.key();
+                apply_revoke_role(presale_account, presale_key, role, holder)?
+            }
+            AdminAction::ProposeAuthority { new_authority } => {
+                let presale_key = presale_account.key();
+                apply_propose_authority(presale_account, presale_key, new_authority)?
+            }
+            // Run in `add_payment_mint`, which creates the vault, and `migrate`, which grows accounts
+            AdminAction::AddPaymentMint { .. } | AdminAction::Migrate => {
+                return Err(ErrorCode::ActionNotExecutable.into())
+            }
+            AdminAction::ChangeSigners { signers, threshold } => {
+                multisig.signers = signers;
+                multisig.threshold = threshold;
+                multisig.signer_set_version += 1;
+
+                let clock = Clock::get()?;
+                emit!(MultisigUpdated {
+                    multisig: multisig.key(),
+                    signers: multisig.signers.clone(),
+                    threshold,
+                    timestamp: clock.unix_timestamp,
+                });
+            }
+        }
+
+        let clock = Clock::get()?;
+        emit!(ProposalExecuted {
+            proposal: proposal.key(),
+            executor: ctx.accounts.executor.key(),
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn calculate_claimable(ctx: Context<CalculateClaimable>) -> Result<u64> {
+        let user_vesting = &ctx.accounts.user_vesting;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let vested_amount = ctx.accounts.presale_account.vested_amount(
+            ctx.accounts.unlock_schedule.as_deref(),
+            user_vesting.total_amount,
+            current_time,
+        )?;
+
+        // Return the amount of claimable tokens
+        Ok(vested_amount.saturating_sub(user_vesting.claimed_amount))
+    }
+
+    // Read-only cost of buying `amount` tokens right now, so clients can show price impact.
+    // Covers the curve-priced modes; round ladders depend on per-wallet round caps.
+    pub fn quote_purchase(ctx: Context<QuotePurchase>, amount: u64) -> Result<PurchaseQuote> {
+        let presale_account = &ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+
+        if amount == 0 {
+            return Err(ErrorCode::BelowMinimumPurchase.into());
+        }
+
+        match presale_account.pricing_mode {
+            PricingMode::BondingCurve => {
+                let curve = &presale_account.bonding_curve;
+                let sold = presale_account.total_tokens_sold;
+                let cost_in_usd = curve.cost(sold, amount)?;
+                Ok(PurchaseQuote {
+                    cost_in_usd,
+                    average_price: div_ceil(cost_in_usd as u128, amount as u128) as u64,
+                    start_price: curve.price_at(sold)?,
+                    end_price: curve.price_at(sold.checked_add(amount).ok_or(ErrorCode::MathOverflow)?)?,
+                })
+            }
+            PricingMode::DutchAuction => {
+                let token_price = presale_account.dutch_auction_price(clock.unix_timestamp);
+                Ok(PurchaseQuote {
+                    cost_in_usd: amount.checked_mul(token_price).ok_or(ErrorCode::MathOverflow)?,
+                    average_price: token_price,
+                    start_price: token_price,
+                    end_price: token_price,
+                })
+            }
+            PricingMode::Rounds => Err(ErrorCode::QuoteUnavailable.into()),
+        }
+    }
+
+    // Referrers claim their rewards after the sale, on the same vesting curve as buyers
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        let presale_account = &ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Ensure presale has ended
+        if current_time < presale_account.presale_end {
+            return Err(ErrorCode::PresaleNotEnded.into());
+        }
+
+        // A failed sale pays nothing out
+        if presale_account.sale_state == SaleState::Failed {
+            return Err(ErrorCode::SaleFailed.into());
+        }
+
+        let referral_ledger = &ctx.accounts.referral_ledger;
+        let unlock_schedule = ctx.accounts.unlock_schedule.as_deref();
+        let vested_tokens =
+            presale_account.vested_amount(unlock_schedule, referral_ledger.reward_tokens, current_time)?;
+        let vested_lamports = presale_account.vested_amount(
+            unlock_schedule,
+            referral_ledger.reward_lamports,
+            current_time,
+        )?;
+        let claimable_tokens = vested_tokens.saturating_sub(referral_ledger.claimed_tokens);
+        let claimable_lamports = vested_lamports.saturating_sub(referral_ledger.claimed_lamports);
+
+        if claimable_tokens == 0 && claimable_lamports == 0 {
+            return Err(ErrorCode::NoTokensToClaim.into());
+        }
+
+        if claimable_tokens > 0 {
+            pay_from_sale_vault(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.sale_vault.to_account_info(),
+                &ctx.accounts.referrer_token_account.to_account_info(),
+                presale_account,
+                claimable_tokens,
+            )?;
+        }
+
+        if claimable_lamports > 0 {
+            pay_from_treasury(
+                &ctx.accounts.system_program.to_account_info(),
+                &ctx.accounts.treasury.to_account_info(),
+                &ctx.accounts.referrer.to_account_info(),
+                presale_account,
+                claimable_lamports,
+            )?;
+        }
+
+        let referral_ledger = &mut ctx.accounts.referral_ledger;
+        referral_ledger.claimed_tokens = referral_ledger
+            .claimed_tokens
+            .checked_add(claimable_tokens)
+            .ok_or(ErrorCode::MathOverflow)?;
+        referral_ledger.claimed_lamports = referral_ledger
+            .claimed_lamports
+            .checked_add(claimable_lamports)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(ReferralRewardsClaimed {
+            referrer: ctx.accounts.referrer.key(),
+            tokens: claimable_tokens,
+            lamports: claimable_lamports,
+        });
+
+        Ok(())
+    }
+
+    // Referral rewards credited in a registered payment mint are paid from its vault
+    pub fn claim_referral_payment_rewards(ctx: Context<ClaimReferralPaymentRewards>) -> Result<()> {
+        let presale_account = &ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Ensure presale has ended
+        if current_time < presale_account.presale_end {
+            return Err(ErrorCode::PresaleNotEnded.into());
+        }
+
+        // A failed sale pays nothing out
+        if presale_account.sale_state == SaleState::Failed {
+            return Err(ErrorCode::SaleFailed.into());
+        }
+
+        let mint_key = ctx.accounts.payment_mint.key();
+        let mint_index = presale_account
+            .accepted_mints
+            .iter()
+            .position(|accepted| accepted.mint == mint_key)
+            .ok_or(ErrorCode::InvalidPaymentMethod)?;
+        let vault_bump = presale_account.accepted_mints[mint_index].vault_bump;
+
+        let referral_ledger = &ctx.accounts.referral_ledger;
+        let vested = presale_account.vested_amount(
+            ctx.accounts.unlock_schedule.as_deref(),
+            referral_ledger.reward_by_mint[mint_index],
+            current_time,
+        )?;
+        let claimable = vested.saturating_sub(referral_ledger.claimed_by_mint[mint_index]);
+        if claimable == 0 {
+            return Err(ErrorCode::NoTokensToClaim.into());
+        }
+
+        let presale_key = presale_account.key();
+        let seeds = &[
+            b"payment_vault".as_ref(),
+            presale_key.as_ref(),
+            mint_key.as_ref(),
+            &[vault_bump],
+        ];
+        let signer = &[&seeds[..]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.payment_vault.to_account_info(),
+                    to: ctx.accounts.referrer_payment_account.to_account_info(),
+                    authority: ctx.accounts.payment_vault.to_account_info(),
+                },
+                signer,
+            ),
+            claimable,
+        )?;
+
+        let referral_ledger = &mut ctx.accounts.referral_ledger;
+        referral_ledger.claimed_by_mint[mint_index] = referral_ledger.claimed_by_mint[mint_index]
+            .checked_add(claimable)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(ReferralPaymentRewardsClaimed {
+            referrer: ctx.accounts.referrer.key(),
+            payment_mint: mint_key,
+            amount: claimable,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless: once the sale ends below the soft cap, switch it into full-refund mode
+    pub fn mark_sale_failed(ctx: Context<MarkSaleFailed>) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Ensure presale has ended
+        if current_time < presale_account.presale_end {
+            return Err(ErrorCode::PresaleNotEnded.into());
+        }
+        if presale_account.sale_state != SaleState::Active {
+            return Err(ErrorCode::InvalidSaleState.into());
+        }
+        if presale_account.soft_cap_met() {
+            return Err(ErrorCode::SoftCapReached.into());
+        }
+
+        presale_account.sale_state = SaleState::Failed;
+
+        emit!(SaleFailed {
+            presale_account: presale_account.key(),
+            total_sold_usd: presale_account.closing_raise_usd(),
+            soft_cap_usd: presale_account.soft_cap_usd,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless once the sale has ended or sold out: fix the TGE every vesting
+    // schedule runs from, freeze the sale and dispose of unsold vault tokens
+    pub fn finalize_sale(ctx: Context<FinalizeSale>) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        if presale_account.finalized {
+            return Err(ErrorCode::SaleAlreadyFinalized.into());
+        }
+        if current_time < presale_account.presale_end
+            && presale_account.total_sold_usd < presale_account.hard_cap_usd
+        {
+            return Err(ErrorCode::PresaleNotEnded.into());
+        }
+        // Below the soft cap the sale fails instead
+        if presale_account.sale_state != SaleState::Active
+            || !presale_account.soft_cap_met()
+        {
+            return Err(ErrorCode::InvalidSaleState.into());
+        }
+        // Allocated sales only know what they owe once every buyer is settled
+        if presale_account.allocation_mode != AllocationMode::FirstCome
+            && (!presale_account.allocation_finalized
+                || presale_account.settled_contributors < presale_account.contributor_count)
+        {
+            return Err(ErrorCode::AllocationNotSettled.into());
+        }
+        if presale_account.pricing_mode == PricingMode::DutchAuction
+            && presale_account.dutch_auction.clearing_settlement.is_some()
+            && presale_account.clearing_price == 0
+        {
+            return Err(ErrorCode::ClearingPriceNotFinalized.into());
+        }
+
+        presale_account.finalized = true;
+        presale_account.tge_time = current_time;
+
+        let unsold = ctx
+            .accounts
+            .sale_vault
+            .amount
+            .saturating_sub(presale_account.tokens_owed()?);
+        let presale_key = presale_account.key();
+        let policy = presale_account.unsold_policy;
+        if unsold > 0 {
+            match policy {
+                UnsoldTokenPolicy::KeepForLiquidity => {}
+                UnsoldTokenPolicy::ReturnToProject => {
+                    let project_token_account = ctx
+                        .accounts
+                        .project_token_account
+                        .as_ref()
+                        .ok_or(ErrorCode::InvalidRecipientAccount)?;
+                    pay_from_sale_vault(
+                        &ctx.accounts.token_program.to_account_info(),
+                        &ctx.accounts.sale_vault.to_account_info(),
+                        &project_token_account.to_account_info(),
+                        &ctx.accounts.presale_account,
+                        unsold,
+                    )?;
+                }
+                UnsoldTokenPolicy::Burn => {
+                    let seeds = &[
+                        b"sale_vault".as_ref(),
+                        presale_key.as_ref(),
+                        &[ctx.accounts.presale_account.sale_vault_bump],
+                    ];
+                    let signer = &[&seeds[..]];
+                    token::burn(
+                        CpiContext::new_with_signer(
+                            ctx.accounts.token_program.to_account_info(),
+                            Burn {
+                                mint: ctx.accounts.sale_mint.to_account_info(),
+                                from: ctx.accounts.sale_vault.to_account_info(),
+                                authority: ctx.accounts.sale_vault.to_account_info(),
+                            },
+                            signer,
+                        ),
+                        unsold,
+                    )?;
+                }
+            }
+        }
+
+        emit!(SaleFinalized {
+            presale_account: presale_key,
+            tge_time: current_time,
+            total_sold_sol: ctx.accounts.presale_account.total_sold_sol,
+            unsold_tokens: unsold,
+            unsold_policy: policy,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless: fix the uniform clearing price once a Dutch auction has ended
+    pub fn finalize_clearing_price(ctx: Context<FinalizeClearingPrice>) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Ensure presale has ended
+        if current_time < presale_account.presale_end {
+            return Err(ErrorCode::PresaleNotEnded.into());
+        }
+        if presale_account.pricing_mode != PricingMode::DutchAuction
+            || presale_account.dutch_auction.clearing_settlement.is_none()
+        {
+            return Err(ErrorCode::UniformClearingDisabled.into());
+        }
+        if presale_account.sale_state != SaleState::Active || presale_account.clearing_price != 0 {
+            return Err(ErrorCode::InvalidSaleState.into());
+        }
+
+        // The price only falls, so the last sale was the lowest; with no sales it is the floor
+        presale_account.clearing_price = if presale_account.last_auction_price > 0 {
+            presale_account.last_auction_price
+        } else {
+            presale_account.dutch_auction.floor_price
+        };
+
+        emit!(ClearingPriceFinalized {
+            presale_account: presale_account.key(),
+            clearing_price: presale_account.clearing_price,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    // Give one buyer the clearing price: top up their tokens, or refund the overpayment
+    // pro-rata across what they paid in. Token refunds take (payment_vault,
+    // buyer_payment_account) pairs in remaining accounts, in registry order, for every
+    // mint the buyer paid with.
+    pub fn settle_clearing_price<'info>(
+        ctx: Context<'_, '_, '_, 'info, SettleClearingPrice<'info>>,
+    ) -> Result<()> {
+        let presale_account = &ctx.accounts.presale_account;
+        let clearing_price = presale_account.clearing_price;
+        if clearing_price == 0 {
+            return Err(ErrorCode::ClearingPriceNotFinalized.into());
+        }
+        if presale_account.sale_state != SaleState::Active {
+            return Err(ErrorCode::SaleFailed.into());
+        }
+        if ctx.accounts.user_vesting.clearing_settled {
+            return Err(ErrorCode::AlreadySettled.into());
+        }
+        let settlement = presale_account
+            .dutch_auction
+            .clearing_settlement
+            .ok_or(ErrorCode::UniformClearingDisabled)?;
+
+        let user_vesting = &mut ctx.accounts.user_vesting;
+        user_vesting.clearing_settled = true;
+
+        // Tokens the buyer's spend is worth at the clearing price
+        let entitled = user_vesting.paid_usd / clearing_price;
+        let extra_tokens = entitled.saturating_sub(user_vesting.total_amount);
+        if extra_tokens == 0 {
+            return Ok(());
+        }
+
+        let mut refunded_lamports = 0;
+        match settlement {
+            ClearingSettlement::ExtraTokens => {
+                user_vesting.total_amount = entitled;
+                let presale_account = &mut ctx.accounts.presale_account;
+                presale_account.total_tokens_sold = presale_account
+                    .total_tokens_sold
+                    .checked_add(extra_tokens)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+            ClearingSettlement::Refund => {
+                let owed_usd = user_vesting
+                    .total_amount
+                    .checked_mul(clearing_price)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                let paid_usd = user_vesting.paid_usd;
+                let overpaid_usd = paid_usd - owed_usd;
+                user_vesting.paid_usd = owed_usd;
+
+                // The buyer keeps their tokens, so only payment-currency rewards shrink
+                claw_back_referral(
+                    &mut ctx.accounts.presale_account,
+                    user_vesting,
+                    ctx.accounts.referral_ledger.as_mut(),
+                    false,
+                    overpaid_usd,
+                    paid_usd,
+                )?;
+
+                // SOL share of the overpayment, out of the treasury
+                refunded_lamports = mul_div(user_vesting.paid_lamports, overpaid_usd, paid_usd)?;
+                if refunded_lamports > 0 {
+                    user_vesting.paid_lamports -= refunded_lamports;
+                    let presale_account = &mut ctx.accounts.presale_account;
+                    presale_account.refunded_lamports = presale_account
+                        .refunded_lamports
+                        .checked_add(refunded_lamports)
+                        .ok_or(ErrorCode::MathOverflow)?;
+                    pay_from_treasury(
+                        &ctx.accounts.system_program.to_account_info(),
+                        &ctx.accounts.treasury.to_account_info(),
+                        &ctx.accounts.buyer.to_account_info(),
+                        &ctx.accounts.presale_account,
+                        refunded_lamports,
+                    )?;
+                }
+
+                // Stablecoin shares, each out of its own vault
+                let presale_key = ctx.accounts.presale_account.key();
+                let mut remaining = ctx.remaining_accounts.iter();
+                for index in 0..ctx.accounts.presale_account.accepted_mints.len() {
+                    let user_vesting = &mut ctx.accounts.user_vesting;
+                    let refund_amount = mul_div(user_vesting.paid_by_mint[index], overpaid_usd, paid_usd)?;
+                    if refund_amount == 0 {
+                        continue;
+                    }
+                    user_vesting.paid_by_mint[index] -= refund_amount;
+
+                    let accepted_mint = &mut ctx.accounts.presale_account.accepted_mints[index];
+                    accepted_mint.total_refunded = accepted_mint
+                        .total_refunded
+                        .checked_add(refund_amount)
+                        .ok_or(ErrorCode::MathOverflow)?;
+
+                    pay_from_payment_vault(
+                        &mut remaining,
+                        &ctx.accounts.token_program.to_account_info(),
+                        presale_key,
+                        &ctx.accounts.presale_account.accepted_mints[index],
+                        ctx.accounts.buyer.key(),
+                        refund_amount,
+                    )?;
+                }
+            }
+        }
+
+        emit!(ClearingPriceSettled {
+            buyer: ctx.accounts.buyer.key(),
+            clearing_price,
+            extra_tokens: if settlement == ClearingSettlement::ExtraTokens { extra_tokens } else { 0 },
+            refunded_lamports,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless: fix the fill ratio of a pro-rata sale once the window has closed
+    pub fn finalize_pro_rata(ctx: Context<FinalizeProRata>) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Ensure presale has ended
+        if current_time < presale_account.presale_end {
+            return Err(ErrorCode::PresaleNotEnded.into());
+        }
+        if presale_account.allocation_mode != AllocationMode::ProRata {
+            return Err(ErrorCode::InvalidSaleState.into());
+        }
+        if presale_account.sale_state != SaleState::Active || presale_account.allocation_finalized {
+            return Err(ErrorCode::InvalidSaleState.into());
+        }
+
+        // Up to the hard cap is accepted; everything above it is refunded pro-rata. The
+        // accepted share of the USD raise carries over to its SOL-equivalent.
+        let accepted_usd = presale_account
+            .total_sold_usd
+            .min(presale_account.hard_cap_usd);
+        presale_account.accepted_sol = if presale_account.total_sold_usd == 0 {
+            presale_account.total_sold_sol
+        } else {
+            mul_div(presale_account.total_sold_sol, accepted_usd, presale_account.total_sold_usd)?
+        };
+        presale_account.allocation_finalized = true;
+
+        emit!(ProRataFinalized {
+            presale_account: presale_account.key(),
+            contributed_sol: presale_account.total_sold_sol,
+            accepted_sol: presale_account.accepted_sol,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless crank: settle one contributor's final allocation (pro-rata share, or
+    // lottery win/loss) and refund the unfilled part of every payment to the buyer, so no
+    // absent buyer can hold up finalization. Token refunds take (payment_vault,
+    // buyer_payment_account) pairs in remaining accounts, in registry order, for every
+    // mint the buyer paid with.
+    pub fn settle_allocation<'info>(
+        ctx: Context<'_, '_, '_, 'info, SettleAllocation<'info>>,
+    ) -> Result<()> {
+        let presale_account = &ctx.accounts.presale_account;
+        if !presale_account.allocation_finalized {
+            return Err(ErrorCode::AllocationNotFinalized.into());
+        }
+        if presale_account.sale_state != SaleState::Active {
+            return Err(ErrorCode::SaleFailed.into());
+        }
+        if ctx.accounts.user_vesting.allocation_settled {
+            return Err(ErrorCode::AlreadySettled.into());
+        }
+
+        // Fraction of the buyer's order that is filled
+        let (filled, total) = match presale_account.allocation_mode {
+            AllocationMode::ProRata => (presale_account.accepted_sol, presale_account.total_sold_sol),
+            AllocationMode::Lottery => {
+                let seed = presale_account
+                    .lottery_seed
+                    .ok_or(ErrorCode::AllocationNotFinalized)?;
+                let rank = lottery_rank(
+                    &seed,
+                    ctx.accounts.user_vesting.contributor_index,
+                    presale_account.contributor_count,
+                );
+                if rank < presale_account.lottery_winners {
+                    (1, 1)
+                } else {
+                    (0, 1)
+                }
+            }
+            AllocationMode::FirstCome => return Err(ErrorCode::AllocationNotFinalized.into()),
+        };
+        let unfilled = total - filled;
+
+        let user_vesting = &mut ctx.accounts.user_vesting;
+        user_vesting.allocation_settled = true;
+        let allocation = mul_div(user_vesting.total_amount, filled, total)?;
+        user_vesting.total_amount = allocation;
+
+        // SOL share of the unfilled contribution, out of the treasury
+        let refunded_lamports = mul_div(user_vesting.paid_lamports, unfilled, total)?;
+        user_vesting.paid_lamports -= refunded_lamports;
+
+        let presale_account = &mut ctx.accounts.presale_account;
+        claw_back_referral(
+            presale_account,
+            user_vesting,
+            ctx.accounts.referral_ledger.as_mut(),
+            true,
+            unfilled,
+            total,
+        )?;
+        presale_account.settled_contributors += 1;
+        presale_account.allocated_tokens = presale_account
+            .allocated_tokens
+            .checked_add(allocation)
+            .ok_or(ErrorCode::MathOverflow)?;
+        presale_account.refunded_lamports = presale_account
+            .refunded_lamports
+            .checked_add(refunded_lamports)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        if refunded_lamports > 0 {
+            pay_from_treasury(
+                &ctx.accounts.system_program.to_account_info(),
+                &ctx.accounts.treasury.to_account_info(),
+                &ctx.accounts.buyer.to_account_info(),
+                &ctx.accounts.presale_account,
+                refunded_lamports,
+            )?;
+        }
+
+        // Stablecoin shares, each out of its own vault
+        let presale_key = ctx.accounts.presale_account.key();
+        let mut remaining = ctx.remaining_accounts.iter();
+        for index in 0..ctx.accounts.presale_account.accepted_mints.len() {
+            let user_vesting = &mut ctx.accounts.user_vesting;
+            let refund_amount = mul_div(user_vesting.paid_by_mint[index], unfilled, total)?;
+            if refund_amount == 0 {
+                continue;
+            }
+            user_vesting.paid_by_mint[index] -= refund_amount;
+
+            let accepted_mint = &mut ctx.accounts.presale_account.accepted_mints[index];
+            accepted_mint.total_refunded = accepted_mint
+                .total_refunded
+                .checked_add(refund_amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+
+            pay_from_payment_vault(
+                &mut remaining,
+                &ctx.accounts.token_program.to_account_info(),
+                presale_key,
+                &ctx.accounts.presale_account.accepted_mints[index],
+                ctx.accounts.buyer.key(),
+                refund_amount,
+            )?;
+        }
+
+        emit!(AllocationSettled {
+            buyer: ctx.accounts.buyer.key(),
+            allocation,
+            refunded_lamports,
+        });
+
+        Ok(())
+    }
+
+    // Once every contributor has settled, send the rounding dust left by the pro-rata
+    // floor divisions to the dust recipient: unallocated sale tokens, unrefunded lamports
+    // and, via (payment_vault, recipient_token_account) remaining-account pairs in registry
+    // order, unrefunded stablecoin units.
+    pub fn sweep_pro_rata_dust<'info>(
+        ctx: Context<'_, '_, '_, 'info, SweepProRataDust<'info>>,
+    ) -> Result<()> {
+        let presale_account = &ctx.accounts.presale_account;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        if presale_account.allocation_mode != AllocationMode::ProRata
+            || !presale_account.allocation_finalized
+            || presale_account.dust_swept
+        {
+            return Err(ErrorCode::AllocationNotFinalized.into());
+        }
+        if presale_account.settled_contributors < presale_account.contributor_count {
+            return Err(ErrorCode::AllocationNotSettled.into());
+        }
+
+        let contributed = presale_account.total_sold_sol;
+        let accepted = presale_account.accepted_sol;
+        let excess = contributed - accepted;
+        if contributed == 0 {
+            ctx.accounts.presale_account.dust_swept = true;
+            return Ok(());
+        }
+
+        // Exact pro-rata totals, less what the per-contributor floors actually paid out
+        let token_dust = mul_div(presale_account.total_tokens_sold, accepted, contributed)?
+            .saturating_sub(presale_account.allocated_tokens);
+        let lamport_dust = mul_div(presale_account.total_paid_lamports, excess, contributed)?
+            .saturating_sub(presale_account.refunded_lamports);
+
+        if token_dust > 0 {
+            pay_from_sale_vault(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.sale_vault.to_account_info(),
+                &ctx.accounts.dust_recipient_token_account.to_account_info(),
+                presale_account,
+                token_dust,
+            )?;
+        }
+        if lamport_dust > 0 {
+            pay_from_treasury(
+                &ctx.accounts.system_program.to_account_info(),
+                &ctx.accounts.treasury.to_account_info(),
+                &ctx.accounts.dust_recipient.to_account_info(),
+                presale_account,
+                lamport_dust,
+            )?;
+        }
+
+        let presale_key = ctx.accounts.presale_account.key();
+        let dust_recipient = ctx.accounts.presale_account.dust_recipient;
+        let mut remaining = ctx.remaining_accounts.iter();
+        for accepted_mint in ctx.accounts.presale_account.accepted_mints.iter() {
+            let mint_dust = mul_div(accepted_mint.total_received, excess, contributed)?
+                .saturating_sub(accepted_mint.total_refunded);
+            if mint_dust == 0 {
+                continue;
+            }
+            pay_from_payment_vault(
+                &mut remaining,
+                &ctx.accounts.token_program.to_account_info(),
+                presale_key,
+                accepted_mint,
+                dust_recipient,
+                mint_dust,
+            )?;
+        }
+
+        ctx.accounts.presale_account.dust_swept = true;
+
+        emit!(ProRataDustSwept {
+            presale_account: presale_key,
+            dust_recipient,
+            token_dust,
+            lamport_dust,
+        });
+
+        Ok(())
+    }
+
+    // Admin commits to keccak(secret) before the sale closes; the secret is revealed later
+    pub fn commit_lottery_seed(
+        ctx: Context<UpdatePresaleParams>,
+        commitment: [u8; 32],
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+
+        if presale_account.allocation_mode != AllocationMode::Lottery {
+            return Err(ErrorCode::InvalidSaleState.into());
+        }
+        // The commitment is final and must predate the close of ticket sales
+        if presale_account.lottery_commitment.is_some()
+            || clock.unix_timestamp >= presale_account.presale_end
+        {
+            return Err(ErrorCode::LotteryCommitmentLocked.into());
+        }
+
+        presale_account.lottery_commitment = Some(commitment);
+
+        emit!(LotteryCommitted {
+            presale_account: presale_account.key(),
+            commitment,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless: after close, anyone adds their own entropy and schedules the draw
+    // a few slots ahead, so neither the admin nor the revealer knows the outcome alone.
+    // A request is replaced once it can no longer be drawn: its slot passed before the
+    // admin revealed, or its slot hash dropped out of SlotHashes before anyone drew.
+    pub fn request_lottery_draw(ctx: Context<RequestLotteryDraw>, entropy: [u8; 32]) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+
+        if presale_account.allocation_mode != AllocationMode::Lottery
+            || presale_account.sale_state != SaleState::Active
+            || presale_account.lottery_seed.is_some()
+        {
+            return Err(ErrorCode::InvalidSaleState.into());
+        }
+        // Ensure presale has ended
+        if clock.unix_timestamp < presale_account.presale_end {
+            return Err(ErrorCode::PresaleNotEnded.into());
+        }
+        // Past the deadline the lottery is voided instead, so requests cannot hold it open
+        if clock.unix_timestamp > presale_account.lottery_reveal_deadline() {
+            return Err(ErrorCode::LotteryRevealExpired.into());
+        }
+        if presale_account.lottery_commitment.is_none() {
+            return Err(ErrorCode::LotteryNotCommitted.into());
+        }
+        if presale_account.lottery_draw_live(clock.slot) {
+            return Err(ErrorCode::LotteryDrawAlreadyRequested.into());
+        }
+
+        presale_account.lottery_entropy = entropy;
+        presale_account.lottery_revealer = ctx.accounts.revealer.key();
+        presale_account.lottery_draw_slot = clock.slot + LOTTERY_DRAW_DELAY_SLOTS;
+
+        emit!(LotteryDrawRequested {
+            presale_account: presale_account.key(),
+            revealer: ctx.accounts.revealer.key(),
+            entropy,
+            draw_slot: presale_account.lottery_draw_slot,
+        });
+
+        Ok(())
+    }
+
+    // Admin reveals the committed secret after close, before the hash of the pending
+    // draw slot exists. The admin therefore never sees the outcome before committing to
+    // it, and withholding the reveal can only void the sale, not re-roll it.
+    pub fn reveal_lottery_seed(ctx: Context<RevealLotterySeed>, secret: [u8; 32]) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+
+        // Ensure caller is the authorized admin
+        if ctx.accounts.authority.key() != presale_account.authority {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+
+        if presale_account.lottery_secret.is_some() || presale_account.lottery_seed.is_some() {
+            return Err(ErrorCode::AlreadySettled.into());
+        }
+        // Ensure presale has ended
+        if clock.unix_timestamp < presale_account.presale_end {
+            return Err(ErrorCode::PresaleNotEnded.into());
+        }
+        if clock.unix_timestamp > presale_account.lottery_reveal_deadline() {
+            return Err(ErrorCode::LotteryRevealExpired.into());
+        }
+        // Once the draw slot is reached its hash is known; the request has to be replaced
+        if presale_account.lottery_draw_slot != 0 && clock.slot >= presale_account.lottery_draw_slot {
+            return Err(ErrorCode::LotteryRevealExpired.into());
+        }
+        if presale_account.lottery_commitment != Some(keccak::hash(&secret).0) {
+            return Err(ErrorCode::InvalidLotteryReveal.into());
+        }
+
+        presale_account.lottery_secret = Some(secret);
+
+        emit!(LotterySecretRevealed {
+            presale_account: presale_account.key(),
+            secret,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless: once the secret is revealed and the draw slot has passed, anyone
+    // fixes the seed as keccak(secret || requester entropy || draw slot hash)
+    pub fn draw_lottery(ctx: Context<DrawLottery>) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+
+        if presale_account.sale_state != SaleState::Active {
+            return Err(ErrorCode::SaleFailed.into());
+        }
+        if presale_account.lottery_seed.is_some() {
+            return Err(ErrorCode::AlreadySettled.into());
+        }
+        let secret = presale_account
+            .lottery_secret
+            .ok_or(ErrorCode::LotteryDrawNotReady)?;
+        if presale_account.lottery_draw_slot == 0 || clock.slot <= presale_account.lottery_draw_slot {
+            return Err(ErrorCode::LotteryDrawNotReady.into());
+        }
+
+        let slot_hash = find_slot_hash(
+            &ctx.accounts.slot_hashes,
+            presale_account.lottery_draw_slot,
+        )?;
+        let seed = keccak::hashv(&[&secret, &presale_account.lottery_entropy, &slot_hash]).0;
+
+        presale_account.lottery_seed = Some(seed);
+        presale_account.lottery_winners = presale_account
+            .lottery
+            .winner_count
+            .min(presale_account.contributor_count);
+        presale_account.allocation_finalized = true;
+
+        emit!(LotteryDrawn {
+            presale_account: presale_account.key(),
+            seed,
+            tickets: presale_account.contributor_count,
+            winners: presale_account.lottery_winners,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless: a lottery never drawn by its deadline fails, and every ticket is
+    // refunded in full through `reclaim_sol_payment` / `reclaim_token_payment`. A revealed
+    // draw whose slot hash is still available can be drawn instead.
+    pub fn void_lottery(ctx: Context<VoidLottery>) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+
+        if presale_account.allocation_mode != AllocationMode::Lottery
+            || presale_account.lottery_seed.is_some()
+            || presale_account.sale_state != SaleState::Active
+        {
+            return Err(ErrorCode::InvalidSaleState.into());
+        }
+        if clock.unix_timestamp <= presale_account.lottery_reveal_deadline()
+            || (presale_account.lottery_secret.is_some() && presale_account.lottery_draw_live(clock.slot))
+        {
+            return Err(ErrorCode::LotteryDrawNotReady.into());
+        }
+
+        presale_account.sale_state = SaleState::Failed;
+
+        emit!(SaleFailed {
+            presale_account: presale_account.key(),
+            total_sold_usd: presale_account.total_sold_usd,
+            soft_cap_usd: presale_account.soft_cap_usd,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Return every lamport the buyer paid for a failed sale
+    pub fn reclaim_sol_payment(ctx: Context<ReclaimSolPayment>) -> Result<()> {
+        let presale_account = &ctx.accounts.presale_account;
+        if presale_account.sale_state != SaleState::Failed {
+            return Err(ErrorCode::RefundNotAvailable.into());
+        }
+
+        let user_vesting = &mut ctx.accounts.user_vesting;
+        let refund_lamports = user_vesting.paid_lamports;
+        if refund_lamports == 0 {
+            return Err(ErrorCode::InsufficientRefundBalance.into());
+        }
+
+        // Nothing vests from a failed sale
+        user_vesting.paid_lamports = 0;
+        user_vesting.total_amount = 0;
+
+        pay_from_treasury(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.treasury.to_account_info(),
+            &ctx.accounts.buyer.to_account_info(),
+            &ctx.accounts.presale_account,
+            refund_lamports,
+        )?;
+
+        emit!(PaymentReclaimed {
+            buyer: ctx.accounts.buyer.key(),
+            payment_mint: None,
+            amount: refund_lamports,
+        });
+
+        Ok(())
+    }
+
+    // Return every unit of a stablecoin the buyer paid for a failed sale, from its vault
+    pub fn reclaim_token_payment(ctx: Context<ReclaimTokenPayment>) -> Result<()> {
+        let presale_account = &ctx.accounts.presale_account;
+        if presale_account.sale_state != SaleState::Failed {
+            return Err(ErrorCode::RefundNotAvailable.into());
+        }
+
+        let mint_key = ctx.accounts.payment_mint.key();
+        let mint_index = presale_account
+            .accepted_mints
+            .iter()
+            .position(|accepted| accepted.mint == mint_key)
+            .ok_or(ErrorCode::InvalidPaymentMethod)?;
+        let vault_bump = presale_account.accepted_mints[mint_index].vault_bump;
+
+        let user_vesting = &mut ctx.accounts.user_vesting;
+        let refund_amount = user_vesting.paid_by_mint[mint_index];
+        if refund_amount == 0 {
+            return Err(ErrorCode::InsufficientRefundBalance.into());
+        }
+
+        // Nothing vests from a failed sale
+        user_vesting.paid_by_mint[mint_index] = 0;
+        user_vesting.total_amount = 0;
+
+        let presale_key = presale_account.key();
+        let seeds = &[
+            b"payment_vault".as_ref(),
+            presale_key.as_ref(),
+            mint_key.as_ref(),
+            &[vault_bump],
+        ];
+        let signer = &[&seeds[..]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.payment_vault.to_account_info(),
+                    to: ctx.accounts.buyer_payment_account.to_account_info(),
+                    authority: ctx.accounts.payment_vault.to_account_info(),
+                },
+                signer,
+            ),
+            refund_amount,
+        )?;
+
+        emit!(PaymentReclaimed {
+            buyer: ctx.accounts.buyer.key(),
+            payment_mint: Some(mint_key),
+            amount: refund_amount,
+        });
+
+        Ok(())
+    }
+
+    // The project takes back the sale tokens it deposited once the sale has failed
+    pub fn withdraw_sale_tokens_on_failure(ctx: Context<WithdrawSaleTokensOnFailure>) -> Result<()> {
+        let presale_account = &ctx.accounts.presale_account;
+        if presale_account.sale_state != SaleState::Failed {
+            return Err(ErrorCode::InvalidSaleState.into());
+        }
+
+        let amount = ctx.accounts.sale_vault.amount;
+        if amount == 0 {
+            return Err(ErrorCode::NoTokensToClaim.into());
+        }
+        pay_from_sale_vault(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.sale_vault.to_account_info(),
+            &ctx.accounts.project_token_account.to_account_info(),
+            presale_account,
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn update_presale_discount(
+        ctx: Context<UpdatePresaleParams>,
+        new_price: Option<u64>,
+    ) -> Result<()> {
+        let presale_account = &mut ctx.accounts.presale_account;
+
+        // Once a multisig is configured, this is queued through a proposal instead
+        if presale_account.multisig.is_some() {
+            return Err(ErrorCode::MultisigRequired.into());
+        }
+        // With a timelock, price changes are queued instead
+        if presale_account.timelock_delay > 0 {
+            return Err(ErrorCode::TimelockRequired.into());
+        }
+
+        // Validate and update price directly
+        if let Some(price) = new_price {
+            apply_param_change(presale_account, ParamChange::Price { new_price: price })?;
+        }
+
+        Ok(())
+    }
+
+    pub fn distribute_initial_airdrop(ctx: Context<DistributeAirdrop>) -> Result<()> {
+        let user_vesting = &mut ctx.accounts.user_vesting;
+        let presale_account = &ctx.accounts.presale_account;
+
+        // Ensure caller holds the distributor role
+        if !presale_account.has_role(&ctx.accounts.authority.key(), Role::Distributor) {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+
+        // Airdrops start at the TGE set by `finalize_sale`
+        if !presale_account.finalized {
+            return Err(ErrorCode::SaleNotFinalized.into());
+        }
+
+        // A failed sale pays nothing out
+        if presale_account.sale_state == SaleState::Failed {
+            return Err(ErrorCode::SaleFailed.into());
+        }
+
+        ensure_allocation_settled(presale_account, user_vesting)?;
+
+        let initial_airdrop = if presale_account.vesting_mode == VestingMode::Piecewise {
+            // Piecewise sales send whatever has unlocked so far
+            let unlocked = presale_account.unclaimed_vested(
+                ctx.accounts.unlock_schedule.as_deref(),
+                user_vesting,
+                Clock::get()?.unix_timestamp,
+            )?;
+            if unlocked == 0 {
+                return Err(ErrorCode::AirdropNotDue.into());
+            }
+            unlocked
+        } else {
+            // The initial airdrop goes out once
+            if user_vesting.airdrops_completed != 0 {
+                return Err(ErrorCode::AirdropCompleted.into());
+            }
+
+            // Calculate initial airdrop percentage
+            let initial_percentage = *presale_account
+                .airdrop_percentages
+                .get(0)
+                .ok_or(ErrorCode::AirdropConfigurationError)?;
+            user_vesting.airdrops_completed = 1;
+            presale_account.airdrop_amount(user_vesting, initial_percentage)?
+        };
+
+        // Update claimed amount
+        user_vesting.claimed_amount = user_vesting
+            .claimed_amount
+            .checked_add(initial_airdrop)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Transfer initial airdrop
+        pay_from_sale_vault(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.sale_vault.to_account_info(),
+            &ctx.accounts.recipient_account.to_account_info(),
+            presale_account,
+            initial_airdrop,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn distribute_monthly_airdrop(ctx: Context<DistributeAirdrop>) -> Result<()> {
+        let user_vesting = &mut ctx.accounts.user_vesting;
+        let presale_account = &ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Ensure caller holds the distributor role
+        if !presale_account.has_role(&ctx.accounts.authority.key(), Role::Distributor) {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+
+        // A failed sale pays nothing out
+        if presale_account.sale_state == SaleState::Failed {
+            return Err(ErrorCode::SaleFailed.into());
+        }
+        if !presale_account.finalized {
+            return Err(ErrorCode::SaleNotFinalized.into());
+        }
+
+        ensure_allocation_settled(presale_account, user_vesting)?;
+
+        let airdrop_amount = if presale_account.vesting_mode == VestingMode::Piecewise {
+            // Piecewise sales send whatever has unlocked since the last payout; once the
+            // final point is reached that includes every bit of rounding dust
+            let unlocked = presale_account.unclaimed_vested(
+                ctx.accounts.unlock_schedule.as_deref(),
+                user_vesting,
+                current_time,
+            )?;
+            if unlocked == 0 {
+                return Err(ErrorCode::AirdropNotDue.into());
+            }
+            unlocked
+        } else {
+            // Ensure the initial airdrop went out and another month has passed since the last one
+            if user_vesting.airdrops_completed == 0
+                || !presale_account.airdrop_due(user_vesting, current_time)
+            {
+                return Err(ErrorCode::AirdropNotDue.into());
+            }
+
+            // Ensure airdrops do not exceed total periods
+            if user_vesting.airdrops_completed >= presale_account.total_airdrop_periods {
+                return Err(ErrorCode::AirdropCompleted.into());
+            }
+
+            // Get the percentage for the current airdrop
+            let current_percentage = *presale_account
+                .airdrop_percentages
+                .get(user_vesting.airdrops_completed as usize)
+                .ok_or(ErrorCode::AirdropConfigurationError)?;
+            user_vesting.airdrops_completed += 1;
+
+            // Calculate the airdrop amount
+            presale_account.airdrop_amount(user_vesting, current_percentage)?
+        };
+
+        // Update claimed amount
+        user_vesting.claimed_amount = user_vesting
+            .claimed_amount
+            .checked_add(airdrop_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Transfer the airdrop amount
+        pay_from_sale_vault(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.sale_vault.to_account_info(),
+            &ctx.accounts.recipient_account.to_account_info(),
+            presale_account,
+            airdrop_amount,
+        )?;
+
+        Ok(())
+    }
+}
+
+// Utility function: Place this outside the #[program] module
+pub fn derive_presale_pda(program_id: &Pubkey, seed: &[u8]) -> Pubkey {
+    Pubkey::find_program_address(&[seed], program_id).0
+}
+
+pub fn calculate_sol_price(
+    amount: u64,
+    price: u64,
+    sol_price_in_usd: u64,
+) -> Result<u64> {
+    amount
+        .checked_mul(price)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_mul(10u64.pow(9)) // Convert USD cents to lamports
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(sol_price_in_usd)
+        .ok_or(ErrorCode::MathOverflow)
+}
+
+pub fn apply_bps(amount: u64, bps: u16) -> Result<u64> {
+    let scaled = (amount as u128)
+        .checked_mul(bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / BPS_DENOMINATOR as u128;
+    Ok(scaled as u64)
+}
+
+pub fn validate_sale_rounds(rounds: &[SaleRound], presale_start: i64, presale_end: i64) -> Result<()> {
+    if rounds.is_empty() || rounds.len() > MAX_SALE_ROUNDS {
+        return Err(ErrorCode::InvalidSaleRounds.into());
+    }
+    let mut previous_end = presale_start;
+    for round in rounds.iter() {
+        // Rounds must sit inside the presale window, in order, without overlapping
+        if round.start_time < previous_end
+            || round.start_time >= round.end_time
+            || round.end_time > presale_end
+        {
+            return Err(ErrorCode::InvalidSaleRounds.into());
+        }
+        if round.price == 0 || round.token_cap == 0 || round.wallet_cap == 0 {
+            return Err(ErrorCode::InvalidSaleRounds.into());
+        }
+        previous_end = round.end_time;
+    }
+    Ok(())
+}
+
+// Convert a USD-cent cost into base units of a payment mint, rounding up so the
+// buyer never underpays by a fraction of a unit.
+pub fn usd_to_token_amount(cost_in_usd: u64, decimals: u8, token_price_in_usd: u64) -> Result<u64> {
+    if token_price_in_usd == 0 {
+        return Err(ErrorCode::InvalidPrice.into());
+    }
+    let numerator = (cost_in_usd as u128)
+        .checked_mul(10u128.pow(decimals as u32))
+        .ok_or(ErrorCode::MathOverflow)?;
+    let amount = numerator
+        .checked_add(token_price_in_usd as u128 - 1)
+        .ok_or(ErrorCode::MathOverflow)?
+        / token_price_in_usd as u128;
+    u64::try_from(amount).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+// Pay `amount` out of a registered payment vault, signed by the vault's own seeds. The
+// next two remaining accounts must be the vault and a token account of `recipient`.
+pub fn pay_from_payment_vault<'a, 'info: 'a>(
+    remaining_accounts: &mut impl Iterator<Item = &'a AccountInfo<'info>>,
+    token_program: &AccountInfo<'info>,
+    presale_key: Pubkey,
+    accepted: &AcceptedMint,
+    recipient: Pubkey,
+    amount: u64,
+) -> Result<()> {
+    let vault_info = remaining_accounts.next().ok_or(ErrorCode::InvalidPaymentVault)?;
+    let destination_info = remaining_accounts.next().ok_or(ErrorCode::InvalidPaymentVault)?;
+    if vault_info.key() != accepted.vault {
+        return Err(ErrorCode::InvalidPaymentVault.into());
+    }
+    let destination = Account::<TokenAccount>::try_from(destination_info)?;
+    if destination.mint != accepted.mint || destination.owner != recipient {
+        return Err(ErrorCode::InvalidPaymentVault.into());
+    }
+
+    let seeds = &[
+        b"payment_vault".as_ref(),
+        presale_key.as_ref(),
+        accepted.mint.as_ref(),
+        &[accepted.vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program.clone(),
+            Transfer {
+                from: vault_info.clone(),
+                to: destination_info.clone(),
+                authority: vault_info.clone(),
+            },
+            signer,
+        ),
+        amount,
+    )
+}
+
+// Pay `amount` sale tokens out of the presale's vault, signed by the vault's own seeds.
+// Callers bind `recipient` to the sale mint and the rightful owner in their context.
+pub fn pay_from_sale_vault<'info>(
+    token_program: &AccountInfo<'info>,
+    sale_vault: &AccountInfo<'info>,
+    recipient: &AccountInfo<'info>,
+    presale_account: &Account<'info, PresaleAccount>,
+    amount: u64,
+) -> Result<()> {
+    let presale_key = presale_account.key();
+    let seeds = &[
+        b"sale_vault".as_ref(),
+        presale_key.as_ref(),
+        &[presale_account.sale_vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program.clone(),
+            Transfer {
+                from: sale_vault.clone(),
+                to: recipient.clone(),
+                authority: sale_vault.clone(),
+            },
+            signer,
+        ),
+        amount,
+    )
+}
+
+// Pay `amount` lamports out of the presale treasury, signed by its seeds. The treasury
+// keeps its rent-exempt reserve, so only the balance above it can be paid out.
+pub fn pay_from_treasury<'info>(
+    system_program: &AccountInfo<'info>,
+    treasury: &AccountInfo<'info>,
+    recipient: &AccountInfo<'info>,
+    presale_account: &Account<'info, PresaleAccount>,
+    amount: u64,
+) -> Result<()> {
+    let reserve = Rent::get()?.minimum_balance(0);
+    if treasury.lamports().saturating_sub(reserve) < amount {
+        return Err(ErrorCode::InsufficientProgramBalance.into());
+    }
+
+    let presale_key = presale_account.key();
+    let seeds = &[
+        b"treasury".as_ref(),
+        presale_key.as_ref(),
+        &[presale_account.treasury_bump],
+    ];
+    let signer = &[&seeds[..]];
+    system_program::transfer(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            system_program::Transfer {
+                from: treasury.clone(),
+                to: recipient.clone(),
+            },
+            signer,
+        ),
+        amount,
+    )
+}
+
+// Oversubscribed pro-rata sales hold every payout until the buyer's allocation is settled
+pub fn ensure_allocation_settled(
+    presale_account: &PresaleAccount,
+    user_vesting: &UserVesting,
+) -> Result<()> {
+    if presale_account.allocation_mode != AllocationMode::FirstCome
+        && !user_vesting.allocation_settled
+    {
+        return Err(ErrorCode::AllocationNotSettled.into());
+    }
+    Ok(())
+}
+
+// Position of `ticket` in a seeded permutation of [0, tickets); the first `winners`
+// positions win, so exactly that many tickets are drawn. The permutation is a
+// four-round Feistel network on the smallest even bit width covering `tickets`,
+// cycle-walked back into range.
+pub fn lottery_rank(seed: &[u8; 32], ticket: u32, tickets: u32) -> u32 {
+    let domain = tickets.max(1) as u64;
+    let width = (64 - (domain - 1).leading_zeros()).max(2);
+    let half = (width + 1) / 2;
+    let mask = (1u64 << half) - 1;
+
+    let mut position = ticket as u64;
+    loop {
+        let (mut left, mut right) = (position >> half, position & mask);
+        for round in 0..4u8 {
+            let hash = keccak::hashv(&[seed, &[round], &right.to_le_bytes()]).0;
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&hash[..8]);
+            let mixed = left ^ (u64::from_le_bytes(word) & mask);
+            left = right;
+            right = mixed;
+        }
+        position = (left << half) | right;
+        if position < domain {
+            return position as u32;
+        }
+    }
+}
+
+// Look up a slot's hash in the SlotHashes sysvar: a u64 length followed by
+// (slot u64, hash [u8; 32]) entries, newest first
+pub fn find_slot_hash(slot_hashes: &AccountInfo, slot: u64) -> Result<[u8; 32]> {
+    let data = slot_hashes.try_borrow_data()?;
+    let entry_count = data
+        .get(..8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(ErrorCode::LotterySlotHashUnavailable)? as usize;
+    for index in 0..entry_count {
+        let offset = 8 + index * 40;
+        let entry = data
+            .get(offset..offset + 40)
+            .ok_or(ErrorCode::LotterySlotHashUnavailable)?;
+        let entry_slot = u64::from_le_bytes(entry[..8].try_into().unwrap());
+        if entry_slot == slot {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(&entry[8..]);
+            return Ok(hash);
+        }
+        if entry_slot < slot {
+            break;
+        }
+    }
+    Err(ErrorCode::LotterySlotHashUnavailable.into())
+}
+
+// Admin actions shared by the single-authority instructions and multisig proposals
+
+// Sale parameters are frozen once `finalize_sale` has run
+pub fn ensure_not_finalized(presale_account: &PresaleAccount) -> Result<()> {
+    if presale_account.finalized {
+        return Err(ErrorCode::SaleAlreadyFinalized.into());
+    }
+    Ok(())
+}
+
+pub fn apply_presale_params(presale_account: &mut PresaleAccount, params: PresaleParams) -> Result<()> {
+    ensure_not_finalized(presale_account)?;
+
+    // Update parameters if provided
+    if let Some(price) = params.new_price {
+        presale_account.price = price;
+    }
+    if let Some(min_buy) = params.new_min_buy_amount {
+        presale_account.min_buy_amount_usd = min_buy;
+    }
+    if let Some(max_buy) = params.new_max_buy_amount {
+        presale_account.max_buy_amount_usd = max_buy;
+    }
+    if let Some(hard_cap) = params.new_hard_cap {
+        presale_account.hard_cap_usd = hard_cap;
+    }
+    if let Some(soft_cap) = params.new_soft_cap {
+        presale_account.soft_cap_usd = soft_cap;
+    }
+    if presale_account.soft_cap_usd > presale_account.hard_cap_usd {
+        return Err(ErrorCode::InvalidParameterValue.into());
+    }
+
+    // Emit event with updated parameters
+    let clock = Clock::get()?;
+    emit!(PresaleParamsUpdated {
+        new_price: params.new_price,
+        new_min_buy_amount: params.new_min_buy_amount,
+        new_max_buy_amount: params.new_max_buy_amount,
+        new_hard_cap: params.new_hard_cap,
+        new_soft_cap: params.new_soft_cap,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn apply_pause_state(presale_account: &mut PresaleAccount, paused: bool) -> Result<()> {
+    // Update pause state
+    presale_account.paused = paused;
+
+    // Resuming clears the trip and restarts the breaker's reference points
+    if !paused {
+        presale_account.pause_reason = None;
+        presale_account.last_accepted_price = 0;
+        presale_account.velocity_window_start = 0;
+        presale_account.velocity_window_raised = 0;
+    }
+
+    // Emit pause state change event
+    let clock = Clock::get()?;
+    emit!(PauseStateChanged {
+        paused,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn apply_manual_price_override(
+    presale_account: &mut PresaleAccount,
+    new_price: Option<u64>,
+) -> Result<()> {
+    // Validate the manual price override
+    if let Some(price) = new_price {
+        if price == 0 || price > 1_000_000 {
+            // Ensure price is non-zero and within reasonable bounds
+            return Err(ErrorCode::InvalidPrice.into());
+        }
+    }
+
+    // Update the manual price override
+    presale_account.manual_price_override = new_price;
+
+    // Emit event
+    let clock = Clock::get()?;
+    emit!(ManualPriceOverrideUpdated {
+        new_price,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn apply_compliance_signer(
+    presale_account: &mut PresaleAccount,
+    presale_key: Pubkey,
+    compliance_signer: Option<Pubkey>,
+) -> Result<()> {
+    presale_account.compliance_signer = compliance_signer;
+
+    let clock = Clock::get()?;
+    emit!(ComplianceSignerUpdated {
+        presale_account: presale_key,
+        compliance_signer,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn apply_allowlist_root(
+    presale_account: &mut PresaleAccount,
+    presale_key: Pubkey,
+    new_root: Option<[u8; 32]>,
+) -> Result<()> {
+    presale_account.allowlist_root = new_root;
+
+    let clock = Clock::get()?;
+    emit!(AllowlistRootUpdated {
+        presale_account: presale_key,
+        new_root,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn apply_referral_config(
+    presale_account: &mut PresaleAccount,
+    reward_bps: u16,
+    reward_kind: ReferralRewardKind,
+) -> Result<()> {
+    ensure_not_finalized(presale_account)?;
+    if reward_bps as u64 > BPS_DENOMINATOR {
+        return Err(ErrorCode::InvalidParameterValue.into());
+    }
+
+    presale_account.referral_bps = reward_bps;
+    presale_account.referral_reward_kind = reward_kind;
+
+    Ok(())
+}
+
+pub fn apply_payment_mint_enabled(
+    presale_account: &mut PresaleAccount,
+    presale_key: Pubkey,
+    mint: Pubkey,
+    enabled: bool,
+) -> Result<()> {
+    let index = presale_account.payment_mint_index(mint)?;
+    let accepted = &mut presale_account.accepted_mints[index];
+    accepted.enabled = enabled;
+    let oracle = accepted.oracle;
+    let oracle_kind = presale_account.oracle_kind_by_mint[index];
+
+    let clock = Clock::get()?;
+    emit!(PaymentMintUpdated {
+        presale_account: presale_key,
+        mint,
+        oracle,
+        oracle_kind,
+        enabled,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn apply_grant_role(
+    presale_account: &mut PresaleAccount,
+    presale_key: Pubkey,
+    role: Role,
+    holder: Pubkey,
+) -> Result<()> {
+    // Super-admin is the `authority` itself and only moves by handover
+    if role == Role::SuperAdmin {
+        return Err(ErrorCode::InvalidRoleAssignment.into());
+    }
+    let assignment = RoleAssignment { role, holder };
+    if presale_account.role_assignments.contains(&assignment) {
+        return Err(ErrorCode::InvalidRoleAssignment.into());
+    }
+    if presale_account.role_assignments.len() >= MAX_ROLE_ASSIGNMENTS {
+        return Err(ErrorCode::RoleLimitReached.into());
+    }
+    presale_account.role_assignments.push(assignment);
+
+    let clock = Clock::get()?;
+    emit!(RoleUpdated {
+        presale_account: presale_key,
+        role,
+        holder,
+        granted: true,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn apply_revoke_role(
+    presale_account: &mut PresaleAccount,
+    presale_key: Pubkey,
+    role: Role,
+    holder: Pubkey,
+) -> Result<()> {
+    let assignment = RoleAssignment { role, holder };
+    let index = presale_account
+        .role_assignments
+        .iter()
+        .position(|existing| *existing == assignment)
+        .ok_or(ErrorCode::InvalidRoleAssignment)?;
+    presale_account.role_assignments.swap_remove(index);
+
+    let clock = Clock::get()?;
+    emit!(RoleUpdated {
+        presale_account: presale_key,
+        role,
+        holder,
+        granted: false,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn apply_propose_authority(
+    presale_account: &mut PresaleAccount,
+    presale_key: Pubkey,
+    new_authority: Option<Pubkey>,
+) -> Result<()> {
+    presale_account.pending_authority = new_authority;
+
+    let clock = Clock::get()?;
+    emit!(AuthorityTransferProposed {
+        presale_account: presale_key,
+        current_authority: presale_account.authority,
+        pending_authority: new_authority,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Marks a proposal executed once it has reached the threshold under the current signer set.
+// The executor must be one of the signers, and their signature counts as an approval.
+pub fn consume_proposal(multisig: &Multisig, proposal: &mut Proposal, executor: &Pubkey) -> Result<()> {
+    let signer_index = multisig.signer_index(executor)?;
+
+    if proposal.executed {
+        return Err(ErrorCode::ProposalAlreadyExecuted.into());
+    }
+    if proposal.signer_set_version != multisig.signer_set_version {
+        return Err(ErrorCode::StaleProposal.into());
+    }
+    proposal.approvals |= 1 << signer_index;
+    if (proposal.approvals.count_ones() as u8) < multisig.threshold {
+        return Err(ErrorCode::ThresholdNotMet.into());
+    }
+    proposal.executed = true;
+    Ok(())
+}
+
+// Actions that need accounts `execute_proposal` does not have run in their own instruction
+// against the sale's multisig and an approved proposal, which this consumes and returns
+pub fn consume_approved_proposal(
+    presale_account: &PresaleAccount,
+    multisig: Option<&Multisig>,
+    proposal: Option<&mut Account<Proposal>>,
+    executor: &Pubkey,
+) -> Result<AdminAction> {
+    let multisig_key = presale_account.multisig.ok_or(ErrorCode::MultisigRequired)?;
+    let multisig = multisig.ok_or(ErrorCode::MultisigRequired)?;
+    let proposal = proposal.ok_or(ErrorCode::MultisigRequired)?;
+    if proposal.multisig != multisig_key {
+        return Err(ErrorCode::ProposalActionMismatch.into());
+    }
+    consume_proposal(multisig, proposal, executor)?;
+
+    let clock = Clock::get()?;
+    emit!(ProposalExecuted {
+        proposal: proposal.key(),
+        executor: *executor,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(proposal.action.clone())
+}
+
+pub fn validate_multisig_signers(signers: &[Pubkey], threshold: u8) -> Result<()> {
+    if signers.is_empty() || signers.len() > MAX_MULTISIG_SIGNERS {
+        return Err(ErrorCode::InvalidMultisigConfig.into());
+    }
+    if threshold == 0 || threshold as usize > signers.len() {
+        return Err(ErrorCode::InvalidMultisigConfig.into());
+    }
+    for (index, signer) in signers.iter().enumerate() {
+        if signers[..index].contains(signer) {
+            return Err(ErrorCode::InvalidMultisigConfig.into());
+        }
+    }
+    Ok(())
+}
+
+// Tranches gate at most the whole raise, and their voting windows open after the sale
+// and follow one another
+pub fn validate_milestones(milestones: &[MilestoneConfig], presale_end: i64) -> Result<()> {
+    if milestones.len() > MAX_MILESTONES {
+        return Err(ErrorCode::InvalidMilestones.into());
+    }
+    let mut total_bps: u64 = 0;
+    let mut previous_end = presale_end;
+    for milestone in milestones {
+        if milestone.release_bps == 0
+            || milestone.vote_start < previous_end
+            || milestone.vote_end <= milestone.vote_start
+        {
+            return Err(ErrorCode::InvalidMilestones.into());
+        }
+        total_bps += milestone.release_bps as u64;
+        previous_end = milestone.vote_end;
+    }
+    if total_bps > BPS_DENOMINATOR {
+        return Err(ErrorCode::InvalidMilestones.into());
+    }
+    Ok(())
+}
+
+pub fn validate_vesting_params(cliff_period: i64, vesting_period: i64, vesting_interval: i64) -> Result<()> {
+    if vesting_period == 0 || vesting_interval == 0 {
+        return Err(ErrorCode::InvalidVestingParameters.into());
+    }
+    if vesting_interval > vesting_period {
+        return Err(ErrorCode::InvalidVestingParameters.into());
+    }
+    if cliff_period > vesting_period {
+        return Err(ErrorCode::InvalidVestingParameters.into());
+    }
+    Ok(())
+}
+
+pub fn enqueue_param_change(
+    presale_account: &mut PresaleAccount,
+    presale_key: Pubkey,
+    change: ParamChange,
+    eta: i64,
+    current_time: i64,
+) -> Result<()> {
+    if change.frozen_at_finalize() {
+        ensure_not_finalized(presale_account)?;
+    }
+
+    let earliest = current_time
+        .checked_add(presale_account.timelock_delay)
+        .ok_or(ErrorCode::MathOverflow)?;
+    if eta < earliest {
+        return Err(ErrorCode::TimelockTooShort.into());
+    }
+    if presale_account.queued_changes.len() >= MAX_QUEUED_CHANGES {
+        return Err(ErrorCode::ChangeQueueFull.into());
+    }
+
+    let id = presale_account.next_change_id;
+    presale_account.next_change_id += 1;
+    presale_account.queued_changes.push(QueuedChange {
+        id,
+        change: change.clone(),
+        eta,
+        queued_at: current_time,
+    });
+
+    emit!(ParamChangeQueued {
+        presale_account: presale_key,
+        id,
+        change,
+        eta,
+        timestamp: current_time,
+    });
+
+    Ok(())
+}
+
+// Remove a queued change; `cancelled` distinguishes an admin cancel from execution
+pub fn dequeue_param_change(
+    presale_account: &mut PresaleAccount,
+    presale_key: Pubkey,
+    id: u64,
+    cancelled: bool,
+) -> Result<()> {
+    let index = presale_account
+        .queued_changes
+        .iter()
+        .position(|queued| queued.id == id)
+        .ok_or(ErrorCode::QueuedChangeNotFound)?;
+    presale_account.queued_changes.remove(index);
+
+    if cancelled {
+        let clock = Clock::get()?;
+        emit!(ParamChangeCancelled {
+            presale_account: presale_key,
+            id,
+            timestamp: clock.unix_timestamp,
+        });
+    }
+
+    Ok(())
+}
+
+pub fn apply_param_change(presale_account: &mut PresaleAccount, change: ParamChange) -> Result<()> {
+    if change.frozen_at_finalize() {
+        ensure_not_finalized(presale_account)?;
+    }
+
+    match change {
+        ParamChange::PresaleParams(params) => apply_presale_params(presale_account, params)?,
+        ParamChange::Price { new_price } => {
+            if new_price == 0 {
+                return Err(ErrorCode::InvalidPrice.into());
+            }
+            presale_account.price = new_price;
+        }
+        ParamChange::VestingParams {
+            cliff_period,
+            vesting_period,
+            vesting_interval,
+        } => {
+            validate_vesting_params(cliff_period, vesting_period, vesting_interval)?;
+            presale_account.cliff_period = cliff_period;
+            presale_account.vesting_period = vesting_period;
+            presale_account.vesting_interval = vesting_interval;
+        }
+        ParamChange::TimelockDelay { new_delay } => {
+            if !(0..=MAX_TIMELOCK_DELAY).contains(&new_delay) {
+                return Err(ErrorCode::InvalidParameterValue.into());
+            }
+            presale_account.timelock_delay = new_delay;
+        }
+        ParamChange::OracleConfig {
+            oracle_kind,
+            sol_usd_feed,
+            max_age,
+            max_conf_bps,
+        } => {
+            validate_oracle_config(max_age, max_conf_bps)?;
+            presale_account.oracle_kind = oracle_kind;
+            presale_account.sol_usd_feed = sol_usd_feed;
+            presale_account.oracle_max_age = max_age;
+            presale_account.oracle_max_conf_bps = max_conf_bps;
+
+            let clock = Clock::get()?;
+            emit!(OracleConfigUpdated {
+                oracle_kind,
+                sol_usd_feed,
+                max_age,
+                max_conf_bps,
+                timestamp: clock.unix_timestamp,
+            });
+        }
+        ParamChange::PriceMode { price_mode, twap_window } => {
+            if twap_window <= 0 {
+                return Err(ErrorCode::InvalidParameterValue.into());
+            }
+            presale_account.price_mode = price_mode;
+            presale_account.twap_window = twap_window;
+        }
+    }
+    Ok(())
+}
+
+pub fn div_ceil(numerator: u128, denominator: u128) -> u128 {
+    (numerator + denominator - 1) / denominator
+}
+
+pub fn mul_div(value: u64, numerator: u64, denominator: u64) -> Result<u64> {
+    if denominator == 0 {
+        return Err(ErrorCode::MathOverflow.into());
+    }
+    let scaled = (value as u128)
+        .checked_mul(numerator as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / denominator as u128;
+    u64::try_from(scaled).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+pub fn validate_dutch_auction(
+    config: &DutchAuctionConfig,
+    presale_start: i64,
+    presale_end: i64,
+) -> Result<()> {
+    if config.floor_price == 0 || config.start_price <= config.floor_price {
+        return Err(ErrorCode::InvalidDutchAuction.into());
+    }
+    if config.curve == AuctionCurve::Stepped
+        && (config.step_duration <= 0 || config.step_duration > presale_end - presale_start)
+    {
+        return Err(ErrorCode::InvalidDutchAuction.into());
+    }
+    Ok(())
+}
+
+// Shared by every payment path: validates the sale window and limits, fills the
+// rounds and books the purchase against the buyer and the presale.
+pub fn execute_purchase(
+    presale_account: &mut PresaleAccount,
+    user_vesting: &mut UserVesting,
+    presale_key: Pubkey,
+    buyer: Pubkey,
+    amount: u64,
+    sol_price_in_usd: u64,
+    current_time: i64,
+    allowlist: Option<AllowlistProof>,
+) -> Result<PurchaseCost> {
+    // Ensure presale is not paused
+    if presale_account.paused {
+        return Err(ErrorCode::PresalePaused.into());
+    }
+
+    if presale_account.sale_state != SaleState::Active || presale_account.finalized {
+        return Err(ErrorCode::SaleNotActive.into());
+    }
+
+    // Ensure presale is active
+    if !(current_time >= presale_account.presale_start
+        && current_time <= presale_account.presale_end)
+    {
+        return Err(ErrorCode::SaleNotActive.into());
+    }
+
+    // Ensure the amount is non-zero
+    if amount == 0 {
+        return Err(ErrorCode::BelowMinimumPurchase.into());
+    }
+
+    // A lottery ticket is exactly one fixed-size allocation, one per wallet
+    if presale_account.allocation_mode == AllocationMode::Lottery
+        && (amount != presale_account.lottery.ticket_tokens || user_vesting.total_amount != 0)
+    {
+        return Err(ErrorCode::InvalidLotteryTicket.into());
+    }
+
+    // Bind the vesting record to its buyer and presale on first purchase
+    if user_vesting.owner == Pubkey::default() {
+        user_vesting.owner = buyer;
+        user_vesting.presale_account = presale_key;
+        user_vesting.version = UserVesting::VERSION;
+    } else if user_vesting.owner != buyer || user_vesting.presale_account != presale_key {
+        return Err(ErrorCode::UnauthorizedAccess.into());
+    }
+
+    // Verify the buyer's allowlist leaf if one was supplied
+    let allowlist_allocation = match &allowlist {
+        Some(entry) => {
+            let root = presale_account
+                .allowlist_root
+                .ok_or(ErrorCode::AllowlistNotConfigured)?;
+            if !verify_allowlist_proof(root, &buyer, entry.max_allocation, &entry.proof) {
+                return Err(ErrorCode::InvalidAllowlistProof.into());
+            }
+            Some(entry.max_allocation)
+        }
+        None => None,
+    };
+
+    let cost = match presale_account.pricing_mode {
+        // Fill the order from the active round, rolling into later rounds as caps are hit
+        PricingMode::Rounds => fill_sale_rounds(
+            presale_account,
+            user_vesting,
+            presale_key,
+            amount,
+            sol_price_in_usd,
+            current_time,
+            allowlist_allocation.is_some(),
+        )?,
+        // Integrate the curve over the tokens this order adds to the total sold
+        PricingMode::BondingCurve => {
+            let cost_in_usd = presale_account
+                .bonding_curve
+                .cost(presale_account.total_tokens_sold, amount)?;
+            PurchaseCost {
+                usd: cost_in_usd,
+                sol: calculate_sol_price(cost_in_usd, 1, sol_price_in_usd)?,
+            }
+        }
+        // The whole order is priced at the current point on the auction curve
+        PricingMode::DutchAuction => {
+            let token_price = presale_account.dutch_auction_price(current_time);
+            presale_account.last_auction_price = token_price;
+            PurchaseCost {
+                usd: amount.checked_mul(token_price).ok_or(ErrorCode::MathOverflow)?,
+                sol: calculate_sol_price(amount, token_price, sol_price_in_usd)?,
+            }
+        }
+    };
+
+    // Ensure the purchase is within allowed limits, counted in USD whatever the payment
+    if cost.usd < presale_account.min_buy_amount_usd {
+        return Err(ErrorCode::BelowMinimumPurchase.into());
+    }
+    if user_vesting
+        .paid_usd
+        .checked_add(cost.usd)
+        .ok_or(ErrorCode::MathOverflow)?
+        > presale_account.max_buy_amount_usd
+    {
+        return Err(ErrorCode::ExceedsMaximumPurchase.into());
+    }
+
+    // The allowlist leaf caps the wallet's total USD spend on top of max_buy_amount_usd
+    if let Some(max_allocation) = allowlist_allocation {
+        if user_vesting
+            .paid_usd
+            .checked_add(cost.usd)
+            .ok_or(ErrorCode::MathOverflow)?
+            > max_allocation
+        {
+            return Err(ErrorCode::AllowlistAllocationExceeded.into());
+        }
+    }
+
+    // Check if the total sold exceeds the global hard cap; pro-rata sales accept the
+    // excess and scale everyone down at finalization
+    if presale_account.allocation_mode == AllocationMode::FirstCome
+        && presale_account
+            .total_sold_usd
+            .checked_add(cost.usd)
+            .ok_or(ErrorCode::MathOverflow)?
+            > presale_account.hard_cap_usd
+    {
+        return Err(ErrorCode::HardCapReached.into());
+    }
+    if user_vesting.total_amount == 0 {
+        user_vesting.contributor_index = presale_account.contributor_count;
+        presale_account.contributor_count += 1;
+    }
+
+    // --- STATE UPDATES ---
+    presale_account.total_sold_sol = presale_account
+        .total_sold_sol
+        .checked_add(cost.sol)
+        .ok_or(ErrorCode::BadMath)?;
+    presale_account.total_sold_usd = presale_account
+        .total_sold_usd
+        .checked_add(cost.usd)
+        .ok_or(ErrorCode::BadMath)?;
+    presale_account.total_tokens_sold = presale_account
+        .total_tokens_sold
+        .checked_add(amount)
+        .ok_or(ErrorCode::BadMath)?;
+
+    user_vesting.total_amount = user_vesting
+        .total_amount
+        .checked_add(amount)
+        .ok_or(ErrorCode::BadMath)?;
+    user_vesting.total_purchased_sol = user_vesting
+        .total_purchased_sol
+        .checked_add(cost.sol)
+        .ok_or(ErrorCode::BadMath)?;
+    user_vesting.paid_usd = user_vesting
+        .paid_usd
+        .checked_add(cost.usd)
+        .ok_or(ErrorCode::BadMath)?;
+
+    // The purchase that pushes the window past the raise limit settles, then the sale pauses
+    if presale_account.record_raise(cost.sol, current_time)? {
+        trip_circuit_breaker(presale_account, presale_key, PauseReason::RaiseVelocity, current_time)?;
+    }
+
+    Ok(cost)
+}
+
+// Credit the referrer's ledger (first remaining account). `payment_mint_index` is None
+// for SOL payments and the registry index for token payments. The buyer's record keeps
+// what it credited, so refunds can take the same share back.
+pub fn credit_referral<'info>(
+    remaining_accounts: &[AccountInfo<'info>],
+    program_id: &Pubkey,
+    presale_account: &mut PresaleAccount,
+    user_vesting: &mut UserVesting,
+    presale_key: Pubkey,
+    buyer: Pubkey,
+    referrer: Pubkey,
+    amount: u64,
+    payment_amount: u64,
+    payment_mint_index: Option<usize>,
+) -> Result<()> {
+    if referrer == buyer {
+        return Err(ErrorCode::SelfReferral.into());
+    }
+    // One referrer per buyer, so every credit can be traced back to a single ledger
+    if user_vesting.referrer.map_or(false, |bound| bound != referrer) {
+        return Err(ErrorCode::ReferrerMismatch.into());
+    }
+    user_vesting.referrer = Some(referrer);
+    let ledger_info = remaining_accounts
+        .get(0)
+        .ok_or(ErrorCode::InvalidReferralLedger)?;
+    let (expected_ledger, _) = Pubkey::find_program_address(
+        &[b"referral", presale_key.as_ref(), referrer.as_ref()],
+        program_id,
+    );
+    if ledger_info.key() != expected_ledger {
+        return Err(ErrorCode::InvalidReferralLedger.into());
+    }
+    let mut referral_ledger = Account::<ReferralLedger>::try_from(ledger_info)?;
+
+    let mut reward_tokens = 0;
+    let mut reward_payment = 0;
+    match presale_account.referral_reward_kind {
+        ReferralRewardKind::SaleToken => {
+            reward_tokens = apply_bps(amount, presale_account.referral_bps)?;
+            referral_ledger.reward_tokens = referral_ledger
+                .reward_tokens
+                .checked_add(reward_tokens)
+                .ok_or(ErrorCode::MathOverflow)?;
+            presale_account.referral_tokens_owed = presale_account
+                .referral_tokens_owed
+                .checked_add(reward_tokens)
+                .ok_or(ErrorCode::MathOverflow)?;
+            user_vesting.referral_tokens = user_vesting
+                .referral_tokens
+                .checked_add(reward_tokens)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        ReferralRewardKind::PaymentCurrency => {
+            reward_payment = apply_bps(payment_amount, presale_account.referral_bps)?;
+            let (credited, owed, buyer_credited) = match payment_mint_index {
+                Some(index) => (
+                    &mut referral_ledger.reward_by_mint[index],
+                    &mut presale_account.referral_owed_by_mint[index],
+                    &mut user_vesting.referral_by_mint[index],
+                ),
+                None => (
+                    &mut referral_ledger.reward_lamports,
+                    &mut presale_account.referral_lamports_owed,
+                    &mut user_vesting.referral_lamports,
+                ),
+            };
+            for total in [credited, owed, buyer_credited] {
+                *total = total
+                    .checked_add(reward_payment)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+        }
+    }
+    referral_ledger.exit(program_id)?;
+
+    emit!(ReferralCredited {
+        presale_account: presale_key,
+        referrer,
+        buyer,
+        reward_tokens,
+        payment_mint: payment_mint_index.map(|index| presale_account.accepted_mints[index].mint),
+        reward_payment,
+    });
+
+    Ok(())
+}
+
+// Take back the share `num / den` of the referral rewards a buyer's purchases credited,
+// as that share of their payment is returned. Sale-token rewards follow the buyer's
+// tokens, so a payment-only refund leaves them with `include_tokens` false. Rewards the
+// referrer has already claimed stay claimed.
+pub fn claw_back_referral(
+    presale_account: &mut PresaleAccount,
+    user_vesting: &mut UserVesting,
+    referral_ledger: Option<&mut Account<ReferralLedger>>,
+    include_tokens: bool,
+    num: u64,
+    den: u64,
+) -> Result<()> {
+    let referrer = match user_vesting.referrer {
+        Some(referrer) => referrer,
+        None => return Ok(()),
+    };
+    let credited_tokens = if include_tokens { user_vesting.referral_tokens } else { 0 };
+    if num == 0
+        || (credited_tokens == 0
+            && user_vesting.referral_lamports == 0
+            && user_vesting.referral_by_mint.iter().all(|credited| *credited == 0))
+    {
+        return Ok(());
+    }
+    let referral_ledger = referral_ledger.ok_or(ErrorCode::InvalidReferralLedger)?;
+    if referral_ledger.presale_account != user_vesting.presale_account
+        || referral_ledger.referrer != referrer
+    {
+        return Err(ErrorCode::InvalidReferralLedger.into());
+    }
+
+    let ledger = &mut **referral_ledger;
+    let mut tokens = 0;
+    if include_tokens {
+        tokens = claw_back_share(
+            &mut user_vesting.referral_tokens,
+            &mut ledger.reward_tokens,
+            ledger.claimed_tokens,
+            &mut presale_account.referral_tokens_owed,
+            num,
+            den,
+        )?;
+    }
+    let lamports = claw_back_share(
+        &mut user_vesting.referral_lamports,
+        &mut ledger.reward_lamports,
+        ledger.claimed_lamports,
+        &mut presale_account.referral_lamports_owed,
+        num,
+        den,
+    )?;
+    for index in 0..MAX_PAYMENT_MINTS {
+        claw_back_share(
+            &mut user_vesting.referral_by_mint[index],
+            &mut ledger.reward_by_mint[index],
+            ledger.claimed_by_mint[index],
+            &mut presale_account.referral_owed_by_mint[index],
+            num,
+            den,
+        )?;
+    }
+
+    emit!(ReferralClawedBack {
+        referrer,
+        buyer: user_vesting.owner,
+        tokens,
+        lamports,
+    });
+
+    Ok(())
+}
+
+// Take `num / den` of one buyer's credit back off the referrer's reward and the
+// presale-wide total owed. Returns what actually came off the reward.
+pub fn claw_back_share(
+    credited: &mut u64,
+    reward: &mut u64,
+    claimed: u64,
+    owed: &mut u64,
+    num: u64,
+    den: u64,
+) -> Result<u64> {
+    let share = mul_div(*credited, num, den)?;
+    *credited -= share;
+    let taken = share.min(reward.saturating_sub(claimed));
+    *reward -= taken;
+    *owed = owed.saturating_sub(taken);
+    Ok(taken)
+}
+
+// Allocate `amount` tokens across the active round and any rounds it rolls into.
+// Returns the total cost; round and per-wallet round totals are updated in place.
+pub fn fill_sale_rounds(
+    presale_account: &mut PresaleAccount,
+    user_vesting: &mut UserVesting,
+    presale_key: Pubkey,
+    amount: u64,
+    sol_price_in_usd: u64,
+    current_time: i64,
+    allowlisted: bool,
+) -> Result<PurchaseCost> {
+    let mut remaining = amount;
+    let mut total_cost = PurchaseCost::default();
+    let mut first_fill = true;
+
+    while remaining > 0 {
+        let round_index = match presale_account.active_round_index(current_time) {
+            Some(index) => index,
+            None if first_fill => return Err(ErrorCode::SaleNotActive.into()),
+            None => return Err(ErrorCode::HardCapReached.into()),
+        };
+        let round = &mut presale_account.rounds[round_index];
+
+        // Private rounds only sell to wallets with a verified allowlist proof
+        if round.allowlist_only && !allowlisted {
+            return Err(ErrorCode::NotAllowlisted.into());
+        }
+
+        // Take as much as the round has left; the remainder rolls into the next round
+        let available = round.token_cap.saturating_sub(round.tokens_sold);
+        let fill = remaining.min(available);
+
+        let wallet_total = user_vesting.round_purchased[round_index]
+            .checked_add(fill)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if wallet_total > round.wallet_cap {
+            return Err(ErrorCode::ExceedsRoundWalletCap.into());
+        }
+
+        let cost_in_usd = fill.checked_mul(round.price).ok_or(ErrorCode::MathOverflow)?;
+        let cost_in_sol = calculate_sol_price(fill, round.price, sol_price_in_usd)?;
+
+        round.tokens_sold = round
+            .tokens_sold
+            .checked_add(fill)
+            .ok_or(ErrorCode::MathOverflow)?;
+        round.sol_raised = round
+            .sol_raised
+            .checked_add(cost_in_sol)
+            .ok_or(ErrorCode::MathOverflow)?;
+        user_vesting.round_purchased[round_index] = wallet_total;
+
+        emit!(RoundPurchaseEvent {
+            presale_account: presale_key,
+            round_index: round_index as u8,
+            amount: fill,
+            cost_in_sol,
+            round_tokens_sold: round.tokens_sold,
+            round_sol_raised: round.sol_raised,
+        });
+
+        total_cost.usd = total_cost
+            .usd
+            .checked_add(cost_in_usd)
+            .ok_or(ErrorCode::MathOverflow)?;
+        total_cost.sol = total_cost
+            .sol
+            .checked_add(cost_in_sol)
+            .ok_or(ErrorCode::MathOverflow)?;
+        remaining -= fill;
+        first_fill = false;
+    }
+
+    Ok(total_cost)
+}
+
+// Check the voucher against an Ed25519 program instruction placed immediately before
+// this one. The signed message is buyer || presale || max_amount || expiry || nonce,
+// integers little-endian. Nonces must increase per buyer, so each voucher is single-use.
+pub fn verify_purchase_voucher(
+    instructions_sysvar: &AccountInfo,
+    compliance_signer: Pubkey,
+    user_vesting: &mut UserVesting,
+    presale_key: Pubkey,
+    buyer: Pubkey,
+    amount: u64,
+    voucher: Option<PurchaseVoucher>,
+    current_time: i64,
+) -> Result<()> {
+    let voucher = voucher.ok_or(ErrorCode::VoucherRequired)?;
+    if current_time > voucher.expiry {
+        return Err(ErrorCode::VoucherExpired.into());
+    }
+    if amount > voucher.max_amount {
+        return Err(ErrorCode::VoucherAmountExceeded.into());
+    }
+    if voucher.nonce <= user_vesting.last_voucher_nonce {
+        return Err(ErrorCode::VoucherNonceUsed.into());
+    }
+
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    if current_index == 0 {
+        return Err(ErrorCode::InvalidVoucherSignature.into());
+    }
+    let signature_ix = load_instruction_at_checked(current_index as usize - 1, instructions_sysvar)?;
+    if signature_ix.program_id != ed25519_program::ID {
+        return Err(ErrorCode::InvalidVoucherSignature.into());
+    }
+
+    // Ed25519 instruction data: count, padding, then one 14-byte offsets record of
+    // (signature, signature ix, pubkey, pubkey ix, message, message size, message ix)
+    let data = &signature_ix.data;
+    if data.len() < 16 || data[0] != 1 {
+        return Err(ErrorCode::InvalidVoucherSignature.into());
+    }
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let (pubkey_offset, message_offset, message_size) =
+        (read_u16(6) as usize, read_u16(10) as usize, read_u16(12) as usize);
+    // Every part must live in the Ed25519 instruction itself
+    if read_u16(4) != u16::MAX || read_u16(8) != u16::MAX || read_u16(14) != u16::MAX {
+        return Err(ErrorCode::InvalidVoucherSignature.into());
+    }
+
+    let signed_pubkey = data
+        .get(pubkey_offset..pubkey_offset + 32)
+        .ok_or(ErrorCode::InvalidVoucherSignature)?;
+    if signed_pubkey != compliance_signer.as_ref() {
+        return Err(ErrorCode::InvalidVoucherSignature.into());
+    }
+
+    let mut expected = Vec::with_capacity(VOUCHER_MESSAGE_LEN);
+    expected.extend_from_slice(buyer.as_ref());
+    expected.extend_from_slice(presale_key.as_ref());
+    expected.extend_from_slice(&voucher.max_amount.to_le_bytes());
+    expected.extend_from_slice(&voucher.expiry.to_le_bytes());
+    expected.extend_from_slice(&voucher.nonce.to_le_bytes());
+    if message_size != VOUCHER_MESSAGE_LEN
+        || data.get(message_offset..message_offset + message_size) != Some(&expected[..])
+    {
+        return Err(ErrorCode::InvalidVoucherSignature.into());
+    }
+
+    user_vesting.last_voucher_nonce = voucher.nonce;
+    Ok(())
+}
+
+// Leaves are keccak(wallet || max_allocation as little-endian u64); pairs are hashed
+// in sorted order so proofs do not need to carry left/right flags.
+pub fn verify_allowlist_proof(
+    root: [u8; 32],
+    wallet: &Pubkey,
+    max_allocation: u64,
+    proof: &[[u8; 32]],
+) -> bool {
+    let mut computed = keccak::hashv(&[wallet.as_ref(), &max_allocation.to_le_bytes()]).0;
+    for node in proof.iter() {
+        computed = if computed <= *node {
+            keccak::hashv(&[&computed, node]).0
+        } else {
+            keccak::hashv(&[node, &computed]).0
+        };
+    }
+    computed == root
+}
+
+pub fn validate_oracle_config(max_age: u64, max_conf_bps: u16) -> Result<()> {
+    if max_age == 0 || max_conf_bps == 0 || max_conf_bps as u64 > BPS_DENOMINATOR {
+        return Err(ErrorCode::InvalidOracleConfig.into());
+    }
+    Ok(())
+}
+
+// Scale an oracle value with exponent `expo` into program price units (USD cents)
+pub fn scale_oracle_value(value: u128, expo: i32) -> Result<u64> {
+    let shift = expo
+        .checked_add(PRICE_DECIMALS)
+        .ok_or(ErrorCode::InvalidOracleExponent)?;
+    if !(-18..=18).contains(&shift) {
+        return Err(ErrorCode::InvalidOracleExponent.into());
+    }
+    let scale = 10u128.pow(shift.unsigned_abs());
+    let scaled = if shift >= 0 {
+        value.checked_mul(scale).ok_or(ErrorCode::MathOverflow)?
+    } else {
+        value / scale
+    };
+    u64::try_from(scaled).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+pub fn normalize_oracle_price(price: i128, conf: u128, expo: i32, publish_time: i64) -> Result<OraclePrice> {
+    if price <= 0 {
+        return Err(ErrorCode::InvalidOraclePrice.into());
+    }
+    let price = scale_oracle_value(price as u128, expo)?;
+    if price == 0 {
+        return Err(ErrorCode::InvalidOraclePrice.into());
+    }
+    Ok(OraclePrice {
+        price,
+        conf: scale_oracle_value(conf, expo)?,
+        publish_time,
+        manual: false,
+    })
+}
+
+// Fails unless `oracle_account` is the feed the presale is bound to for this provider
+pub fn verify_oracle_binding(
+    kind: OracleKind,
+    oracle_account: &AccountInfo,
+    expected_feed: Pubkey,
+) -> Result<()> {
+    let bound = match kind {
+        OracleKind::PythLegacy | OracleKind::Switchboard => oracle_account.key() == expected_feed,
+        // Price updates are posted to fresh accounts, so bind on the feed id instead
+        OracleKind::PythPriceUpdateV2 => {
+            if oracle_account.owner != &pyth_solana_receiver_sdk::ID {
+                return Err(ErrorCode::OracleFeedMismatch.into());
+            }
+            let data = oracle_account.try_borrow_data()?;
+            let price_update = PriceUpdateV2::try_deserialize(&mut &data[..])
+                .map_err(|_| ErrorCode::PriceFeedUnavailable)?;
+            price_update.price_message.feed_id == expected_feed.to_bytes()
+        }
+    };
+    if !bound {
+        return Err(ErrorCode::OracleFeedMismatch.into());
+    }
+    Ok(())
+}
+
+// Provider adapter: every oracle kind is reduced to the same normalized `OraclePrice`
+pub fn load_oracle_price(kind: OracleKind, oracle_account: &AccountInfo) -> Result<OraclePrice> {
+    match kind {
+        OracleKind::PythLegacy => {
+            let price_feed = load_price_feed_from_account_info(oracle_account)
+                .map_err(|_| ErrorCode::PriceFeedUnavailable)?;
+            let price_data = price_feed.get_price_unchecked();
+            normalize_oracle_price(
+                price_data.price as i128,
+                price_data.conf as u128,
+                price_data.expo,
+                price_data.publish_time,
+            )
+        }
+        OracleKind::PythPriceUpdateV2 => {
+            let data = oracle_account.try_borrow_data()?;
+            let price_update = PriceUpdateV2::try_deserialize(&mut &data[..])
+                .map_err(|_| ErrorCode::PriceFeedUnavailable)?;
+            if price_update.verification_level != VerificationLevel::Full {
+                return Err(ErrorCode::PriceFeedUnavailable.into());
+            }
+            let message = &price_update.price_message;
+            normalize_oracle_price(
+                message.price as i128,
+                message.conf as u128,
+                message.exponent,
+                message.publish_time,
+            )
+        }
+        OracleKind::Switchboard => {
+            if oracle_account.owner != &switchboard_solana::SWITCHBOARD_PROGRAM_ID {
+                return Err(ErrorCode::PriceFeedUnavailable.into());
+            }
+            let aggregator = AggregatorAccountData::new(oracle_account)
+                .map_err(|_| ErrorCode::PriceFeedUnavailable)?;
+            let round = &aggregator.latest_confirmed_round;
+            let result = aggregator
+                .get_result()
+                .map_err(|_| ErrorCode::PriceFeedUnavailable)?;
+            let mut oracle_price = normalize_oracle_price(
+                result.mantissa,
+                0,
+                -(result.scale as i32),
+                round.round_open_timestamp,
+            )?;
+            // The round's standard deviation carries its own scale
+            oracle_price.conf = scale_oracle_value(
+                round.std_deviation.mantissa.unsigned_abs(),
+                -(round.std_deviation.scale as i32),
+            )?;
+            Ok(oracle_price)
+        }
+    }
+}
+
+// Provider-agnostic freshness and confidence checks on a normalized price
+pub fn read_oracle_price(
+    oracle_account: &AccountInfo,
+    oracle_config: &OracleConfig,
+    current_time: i64,
+) -> Result<OraclePrice> {
+    let oracle_price = load_oracle_price(oracle_config.kind, oracle_account)?;
+
+    let age = current_time.saturating_sub(oracle_price.publish_time);
+    if age < 0 || age as u64 > oracle_config.max_age {
+        return Err(ErrorCode::StalePrice.into());
+    }
+
+    // Reject prints whose confidence interval is too wide relative to the price
+    let max_conf = (oracle_price.price as u128)
+        .checked_mul(oracle_config.max_conf_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / BPS_DENOMINATOR as u128;
+    if oracle_price.conf as u128 > max_conf {
+        return Err(ErrorCode::PriceConfidenceTooWide.into());
+    }
+
+    Ok(oracle_price)
+}
+
+pub fn get_price_from_oracle(
+    oracle_account: &AccountInfo,
+    expected_feed: Pubkey,
+    oracle_config: &OracleConfig,
+    manual_price_override: Option<u64>,
+    current_time: i64,
+) -> Result<OraclePrice> {
+    // Only the feed stored on the presale is ever trusted, override or not
+    verify_oracle_binding(oracle_config.kind, oracle_account, expected_feed)?;
+
+    match read_oracle_price(oracle_account, oracle_config, current_time) {
+        Ok(oracle_price) => Ok(oracle_price),
+        Err(oracle_error) => {
+            // Fallback to manual price override if oracle fails or returns invalid data
+            match manual_price_override {
+                Some(price) => {
+                    if price == 0 || price > 1_000_000 {
+                        // Validate fallback price
+                        return Err(ErrorCode::InvalidPrice.into());
+                    }
+                    Ok(OraclePrice {
+                        price,
+                        conf: 0,
+                        publish_time: current_time,
+                        manual: true,
+                    })
+                }
+                None => Err(oracle_error),
+            }
+        }
+    }
+}
+
+// Reads the bound feed's publish time, ahead of any fallback, and reports whether it is
+// older than the breaker allows. An unreadable feed is left to the normal price path.
+pub fn oracle_staleness_trip(
+    presale_account: &PresaleAccount,
+    oracle_account: &AccountInfo,
+    current_time: i64,
+) -> Result<Option<PauseReason>> {
+    let max_staleness = presale_account.circuit_breaker.max_oracle_staleness;
+    if max_staleness == 0 {
+        return Ok(None);
+    }
+
+    // Only the bound feed may trip the breaker
+    verify_oracle_binding(presale_account.oracle_kind, oracle_account, presale_account.sol_usd_feed)?;
+
+    Ok(match load_oracle_price(presale_account.oracle_kind, oracle_account) {
+        Ok(oracle_price) if current_time.saturating_sub(oracle_price.publish_time) > max_staleness as i64 => {
+            Some(PauseReason::OracleStale)
+        }
+        _ => None,
+    })
+}
+
+// Pause the sale and record why
+pub fn trip_circuit_breaker(
+    presale_account: &mut PresaleAccount,
+    presale_key: Pubkey,
+    reason: PauseReason,
+    current_time: i64,
+) -> Result<()> {
+    presale_account.paused = true;
+    presale_account.pause_reason = Some(reason);
+
+    emit!(CircuitBreakerTripped {
+        presale_account: presale_key,
+        reason,
+        timestamp: current_time,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(sale_id: u64)]
+pub struct Initialize<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>, // Super-admin of the new sale, pays for its accounts
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PresaleAccount::INIT_SPACE,
+        seeds = [
+            b"presale",
+            authority.key().as_ref(),
+            sale_mint.key().as_ref(),
+            &sale_id.to_le_bytes(),
+        ],
+        bump,
+    )]
+    pub presale_account: Account<'info, PresaleAccount>,
+    pub sale_mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"sale_vault", presale_account.key().as_ref()],
+        bump,
+        token::mint = sale_mint,
+        token::authority = sale_vault, // The vault PDA owns itself
+    )]
+    pub sale_vault: Account<'info, TokenAccount>,
+    /// CHECK: System-owned PDA that receives every SOL payment; only moved by signed transfers
+    #[account(mut, seeds = [b"treasury", presale_account.key().as_ref()], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + UnlockSchedule::INIT_SPACE,
+        seeds = [b"unlock_schedule", presale_account.key().as_ref()],
+        bump,
+    )]
+    pub unlock_schedule: Account<'info, UnlockSchedule>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct FundVault<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    pub funder: Signer<'info>, // Project wallet supplying the sale tokens
+    #[account(
+        mut,
+        token::mint = presale_account.sale_mint,
+        token::authority = funder,
+    )]
+    pub funder_token_account: Account<'info, TokenAccount>,
+    #[account(mut, address = presale_account.sale_vault)]
+    pub sale_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawProceeds<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    pub withdrawer: Signer<'info>, // Super-admin or treasury withdrawer
+    /// CHECK: Receives the SOL, or owns the destination token account
+    #[account(mut)]
+    pub recipient: UncheckedAccount<'info>,
+    /// CHECK: Presale treasury PDA, pays SOL withdrawals
+    #[account(
+        mut,
+        seeds = [b"treasury", presale_account.key().as_ref()],
+        bump = presale_account.treasury_bump,
+    )]
+    pub treasury: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GetProceedsStatus<'info> {
+    pub presale_account: Account<'info, PresaleAccount>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeSale<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(mut, address = presale_account.sale_mint)]
+    pub sale_mint: Account<'info, Mint>,
+    #[account(mut, address = presale_account.sale_vault)]
+    pub sale_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = presale_account.sale_mint,
+        token::authority = presale_account.authority,
+    )]
+    pub project_token_account: Option<Account<'info, TokenAccount>>, // Needed to return unsold tokens
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CastMilestoneVote<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(
+        mut,
+        has_one = presale_account,
+        constraint = user_vesting.owner == buyer.key() @ ErrorCode::UnauthorizedAccess,
+    )]
+    pub user_vesting: Account<'info, UserVesting>,
+    pub buyer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveMilestone<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimMilestoneRefund<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(
+        mut,
+        has_one = presale_account,
+        constraint = user_vesting.owner == buyer.key() @ ErrorCode::UnauthorizedAccess,
+    )]
+    pub user_vesting: Account<'info, UserVesting>,
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    /// CHECK: Presale treasury PDA, pays the SOL share
+    #[account(
+        mut,
+        seeds = [b"treasury", presale_account.key().as_ref()],
+        bump = presale_account.treasury_bump,
+    )]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub referral_ledger: Option<Account<'info, ReferralLedger>>, // Buyer's referrer's ledger, if they were referred
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+// State accounts carry a layout version. Layouts only grow: new fields go between
+// `version` and `reserved`, must decode from zeroed bytes, and bump VERSION. Fields that
+// fit are carved out of `reserved`; otherwise `migrate` grows older accounts in place.
+pub trait Versioned {
+    const VERSION: u8;
+
+    fn version_mut(&mut self) -> &mut u8;
+
+    // Returns the version upgraded from. Every change so far decodes from zeros, so
+    // there is nothing to rewrite; per-version fix-ups go in the implementing type.
+    fn upgrade(&mut self) -> u8 {
+        std::mem::replace(self.version_mut(), Self::VERSION)
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct UserVesting {
+    pub total_amount: u64,        // Total tokens purchased
+    pub claimed_amount: u64,      // Tokens already claimed
+    pub start_time: i64,          // Unused; vesting runs from the sale-wide `tge_time`
+    pub airdrops_completed: u8,   // Number of airdrops already distributed
+    pub total_purchased_sol: u64, // Total SOL equivalent purchased by this user
+    pub round_purchased: [u64; MAX_SALE_ROUNDS], // Tokens bought in each sale round
+    pub paid_lamports: u64,       // Lamports actually paid, refundable if the sale fails
+    pub paid_by_mint: [u64; MAX_PAYMENT_MINTS], // Stablecoin paid, by registry index
+    pub owner: Pubkey,            // Buyer this record belongs to, bound on first purchase
+    pub presale_account: Pubkey,  // Presale this record belongs to
+    pub paid_usd: u64,            // USD cents paid at purchase-time prices
+    pub clearing_settled: bool,   // Dutch auction clearing-price settlement done
+    pub allocation_settled: bool, // Final allocation settled in a pro-rata or lottery sale
+    pub contributor_index: u32,   // Order of the buyer's first purchase; the lottery ticket number
+    pub last_voucher_nonce: u64,  // Highest compliance voucher nonce used
+    pub version: u8,              // Layout version, see `Versioned`
+    pub milestone_votes: u8,      // Bit i set once the buyer has voted on milestone i
+    pub milestone_refunded: bool, // Share of a failed milestone's locked funds reclaimed
+    pub referrer: Option<Pubkey>, // Bound by the buyer's first referred purchase
+    pub referral_tokens: u64,     // Sale tokens this buyer's purchases credited to the referrer
+    pub referral_lamports: u64,   // Lamports this buyer's purchases credited to the referrer
+    pub referral_by_mint: [u64; MAX_PAYMENT_MINTS], // Stablecoin credited to the referrer, by registry index
+    pub reserved: [u8; 62],       // Zeroed space for future fields
+}
+
+impl Versioned for UserVesting {
+    const VERSION: u8 = 3;
+
+    fn version_mut(&mut self) -> &mut u8 {
+        &mut self.version
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct PresaleAccount {
+    pub presale_start: i64,
+    pub presale_end: i64,
+    pub public_sale_start: i64,
+    pub price: u64, // Price per token in USD cents
+    pub max_allocation: u64,
+    pub cliff_period: i64,
+    pub vesting_period: i64,
+    pub vesting_interval: i64,
+    pub total_airdrop_periods: u8,
+    #[max_len(MAX_AIRDROP_PERIODS)]
+    pub airdrop_percentages: Vec<u8>,
+    pub total_sold_sol: u64,                // SOL-equivalent raised at purchase-time prices
+    pub min_buy_amount_usd: u64,            // Minimum spend per purchase, in USD cents
+    pub max_buy_amount_usd: u64,            // Maximum spend per user, in USD cents
+    pub hard_cap_usd: u64,                  // Maximum raise for the entire presale, in USD cents
+    pub soft_cap_usd: u64,                  // Minimum raise for the sale to succeed, in USD cents
+    pub sale_state: SaleState,              // Lifecycle of the sale
+    pub authority: Pubkey,                  // Admin authority key
+    pub manual_price_override: Option<u64>, // Optional manual price in USD cents
+    pub paused: bool,                       // Whether the presale is paused
+    #[max_len(MAX_SALE_ROUNDS)]
+    pub rounds: Vec<SaleRound>,             // Ordered sale rounds (seed, private, public...)
+    pub allowlist_root: Option<[u8; 32]>,   // Merkle root of (wallet, max allocation) leaves
+    pub referral_bps: u16,                  // Referral reward in basis points
+    pub referral_reward_kind: ReferralRewardKind, // Pay referrers in sale tokens or in the payment currency
+    #[max_len(MAX_PAYMENT_MINTS)]
+    pub accepted_mints: Vec<AcceptedMint>,  // Registered stablecoin payment mints
+    pub oracle_kind: OracleKind,            // Provider for every price read
+    pub sol_usd_feed: Pubkey,               // SOL/USD feed bound at initialize (feed id for PriceUpdateV2)
+    pub oracle_max_age: u64,                // Oldest accepted oracle price, in seconds
+    pub oracle_max_conf_bps: u16,           // Widest accepted confidence, in bps of price
+    pub price_mode: PriceMode,              // Spot, TWAP or worse-of pricing for purchases
+    pub twap_window: i64,                   // TWAP lookback, in seconds
+    pub price_observations: [PriceObservation; MAX_PRICE_OBSERVATIONS], // SOL/USD ring buffer
+    pub observation_index: u8,              // Next slot to write in the ring buffer
+    pub observation_count: u8,              // Filled slots in the ring buffer
+    pub total_tokens_sold: u64,             // Tokens sold across all rounds
+    pub pricing_mode: PricingMode,          // How purchases are priced
+    pub dutch_auction: DutchAuctionConfig,  // Curve used in DutchAuction mode
+    pub last_auction_price: u64,            // Auction price of the latest purchase, in USD cents
+    pub clearing_price: u64,                // Uniform clearing price once finalized, 0 before
+    pub bonding_curve: BondingCurveConfig,  // Curve used in BondingCurve mode
+    pub allocation_mode: AllocationMode,    // How an oversubscribed sale is allocated
+    pub dust_recipient: Pubkey,             // Receives pro-rata rounding dust
+    pub allocation_finalized: bool,         // Pro-rata fill ratio fixed
+    pub accepted_sol: u64,                  // SOL-equivalent accepted at finalization
+    pub contributor_count: u32,             // Wallets that have bought
+    pub settled_contributors: u32,          // Wallets whose allocation is settled
+    pub allocated_tokens: u64,              // Tokens allocated by settlement so far
+    pub total_paid_lamports: u64,           // Lamports paid in through `purchase`
+    pub refunded_lamports: u64,             // Lamports refunded to buyers so far
+    pub dust_swept: bool,                   // Rounding dust sent to the dust recipient
+    pub lottery: LotteryConfig,             // Ticket and draw rules in Lottery mode
+    pub lottery_commitment: Option<[u8; 32]>, // keccak(secret) committed by the admin
+    pub lottery_entropy: [u8; 32],          // Entropy supplied by the draw requester
+    pub lottery_revealer: Pubkey,           // Who requested the draw
+    pub lottery_draw_slot: u64,             // Slot whose hash is mixed into the seed, 0 before request
+    pub lottery_seed: Option<[u8; 32]>,     // Final seed once revealed
+    pub lottery_winners: u32,               // Winning tickets drawn
+    pub compliance_signer: Option<Pubkey>,  // Signs purchase vouchers for KYC-approved wallets
+    pub multisig: Option<Pubkey>,           // Multisig that replaces `authority` for admin actions
+    #[max_len(MAX_ROLE_ASSIGNMENTS)]
+    pub role_assignments: Vec<RoleAssignment>, // Roles granted by the super-admin
+    pub pending_authority: Option<Pubkey>,  // Proposed super-admin awaiting acceptance
+    pub timelock_delay: i64,                // Minimum seconds between queueing and executing a change
+    #[max_len(MAX_QUEUED_CHANGES)]
+    pub queued_changes: Vec<QueuedChange>,  // Pending timelocked changes, public to buyers
+    pub next_change_id: u64,                // Id of the next queued change
+    pub circuit_breaker: CircuitBreakerConfig, // Thresholds that pause purchases automatically
+    pub pause_reason: Option<PauseReason>,  // Why the breaker tripped; None for a manual pause
+    pub last_accepted_price: u64,           // SOL/USD spot of the latest accepted purchase, 0 to restart
+    pub velocity_window_start: i64,         // Start of the current raise-velocity window
+    pub velocity_window_raised: u64,        // SOL-equivalent raised in the current window
+    pub version: u8,                        // Layout version, see `Versioned`
+    pub sale_mint: Pubkey,                  // Mint of the token being sold
+    pub sale_vault: Pubkey,                 // Program-owned vault holding the sale tokens
+    pub sale_vault_bump: u8,
+    pub sale_id: u64,                       // Third PDA seed, after authority and sale mint
+    pub bump: u8,
+    pub treasury_bump: u8,                  // System-owned PDA holding raised SOL
+    pub vault_funded: u64,                  // Sale tokens deposited through `fund_vault`
+    pub release_schedule: ReleaseSchedule,  // Unlock schedule for withdrawing the raise
+    pub withdrawn_lamports: u64,            // SOL withdrawn by the project so far
+    pub withdrawn_by_mint: [u64; MAX_PAYMENT_MINTS], // Stablecoin withdrawn, by registry index
+    pub withdraw_period_start: i64,         // Start of the withdrawal-cap period last withdrawn in
+    pub period_withdrawn_lamports: u64,     // SOL withdrawn in that period
+    pub period_withdrawn_by_mint: [u64; MAX_PAYMENT_MINTS], // Stablecoin withdrawn in that period
+    #[max_len(MAX_MILESTONES)]
+    pub milestones: Vec<Milestone>,         // Vote-gated release tranches, in order
+    pub milestone_failed: bool,             // A milestone vote failed; the locked share is refundable
+    pub unsold_policy: UnsoldTokenPolicy,   // What `finalize_sale` does with unsold vault tokens
+    pub finalized: bool,                    // Sale closed and parameters frozen
+    pub tge_time: i64,                      // Sale-wide vesting start, set by `finalize_sale`
+    pub referral_tokens_owed: u64,          // Sale tokens credited to referrers
+    pub vesting_mode: VestingMode,          // Stepped (the original behaviour) or linear
+    pub tge_unlock_bps: u16,                // Share unlocked at TGE in linear mode
+    pub total_sold_usd: u64,                // USD cents raised, what the caps are counted in
+    pub oracle_kind_by_mint: [OracleKind; MAX_PAYMENT_MINTS], // Provider of each payment mint's feed
+    pub referral_lamports_owed: u64,        // Lamports credited to referrers, paid from the treasury
+    pub referral_owed_by_mint: [u64; MAX_PAYMENT_MINTS], // Stablecoin credited to referrers, by registry index
+    pub lottery_secret: Option<[u8; 32]>,   // Admin's secret once revealed, before the draw
+    pub raise_at_close_usd: Option<u64>,    // total_sold_usd frozen before the first post-sale refund
+    pub reserved: [u8; 12],                 // Zeroed space for future fields
+}
+
+impl Versioned for PresaleAccount {
+    const VERSION: u8 = 9;
+
+    fn version_mut(&mut self) -> &mut u8 {
+        &mut self.version
+    }
+
+    // Before version 8 every payment mint feed was read as the SOL/USD feed's provider
+    fn upgrade(&mut self) -> u8 {
+        let from_version = std::mem::replace(&mut self.version, Self::VERSION);
+        if from_version < 8 {
+            self.oracle_kind_by_mint = [self.oracle_kind; MAX_PAYMENT_MINTS];
+        }
+        from_version
+    }
+}
+
+impl PresaleAccount {
+    // Append to the observation ring buffer. Observations are spaced at least
+    // twap_window / MAX_PRICE_OBSERVATIONS apart so the buffer always spans the window.
+    pub fn record_price_observation(&mut self, price: u64, timestamp: i64) {
+        if self.observation_count > 0 {
+            let last_index = (self.observation_index as usize + MAX_PRICE_OBSERVATIONS - 1)
+                % MAX_PRICE_OBSERVATIONS;
+            let min_spacing = (self.twap_window / MAX_PRICE_OBSERVATIONS as i64).max(1);
+            if timestamp < self.price_observations[last_index].timestamp + min_spacing {
+                return;
+            }
+        }
+        self.price_observations[self.observation_index as usize] =
+            PriceObservation { price, timestamp };
+        self.observation_index = ((self.observation_index as usize + 1) % MAX_PRICE_OBSERVATIONS) as u8;
+        if (self.observation_count as usize) < MAX_PRICE_OBSERVATIONS {
+            self.observation_count += 1;
+        }
+    }
+
+    // Time-weighted average over the last `twap_window` seconds. Each observation holds
+    // until the next one; the observation in force at the window start is clipped to it.
+    // Until the buffer reaches back a full window, the average covers what it has.
+    pub fn twap(&self, current_time: i64) -> Result<u64> {
+        let count = self.observation_count as usize;
+        if count == 0 {
+            return Err(ErrorCode::TwapUnavailable.into());
+        }
+        let oldest = (self.observation_index as usize + MAX_PRICE_OBSERVATIONS - count)
+            % MAX_PRICE_OBSERVATIONS;
+        let window_start = current_time.saturating_sub(self.twap_window);
+
+        let mut weighted_sum: u128 = 0;
+        let mut total_time: u128 = 0;
+        for offset in 0..count {
+            let observation = &self.price_observations[(oldest + offset) % MAX_PRICE_OBSERVATIONS];
+            let segment_end = if offset + 1 < count {
+                self.price_observations[(oldest + offset + 1) % MAX_PRICE_OBSERVATIONS].timestamp
+            } else {
+                current_time
+            };
+            let segment_start = observation.timestamp.max(window_start);
+            if segment_end <= segment_start {
+                continue;
+            }
+            let duration = (segment_end - segment_start) as u128;
+            weighted_sum = weighted_sum
+                .checked_add(
+                    (observation.price as u128)
+                        .checked_mul(duration)
+                        .ok_or(ErrorCode::MathOverflow)?,
+                )
+                .ok_or(ErrorCode::MathOverflow)?;
+            total_time += duration;
+        }
+
+        // A single observation taken this second is its own average
+        if total_time == 0 {
+            let newest = &self.price_observations
+                [(self.observation_index as usize + MAX_PRICE_OBSERVATIONS - 1) % MAX_PRICE_OBSERVATIONS];
+            return if newest.timestamp == current_time {
+                Ok(newest.price)
+            } else {
+                Err(ErrorCode::TwapUnavailable.into())
+            };
+        }
+
+        Ok((weighted_sum / total_time) as u64)
+    }
+
+    // Record the spot reading and return the SOL/USD price purchases are charged at.
+    // A lower SOL price means more lamports per token, so "worse" for buyers is the minimum.
+    // Only real oracle reads feed the TWAP; a manual override never moves it.
+    pub fn purchase_sol_price(&mut self, spot: OraclePrice, current_time: i64) -> Result<u64> {
+        let spot_price = spot.price;
+        if !spot.manual {
+            self.record_price_observation(spot_price, current_time);
+        }
+        match self.price_mode {
+            PriceMode::Spot => Ok(spot_price),
+            PriceMode::Twap => self.twap(current_time),
+            PriceMode::WorseOf => Ok(spot_price.min(self.twap(current_time)?)),
+        }
+    }
+
+    // Current auction price, falling from start_price at presale_start to floor_price
+    // at presale_end, either continuously or once per step_duration.
+    pub fn dutch_auction_price(&self, current_time: i64) -> u64 {
+        let auction = &self.dutch_auction;
+        let duration = (self.presale_end - self.presale_start).max(1) as u128;
+        let elapsed = (current_time.clamp(self.presale_start, self.presale_end)
+            - self.presale_start) as u128;
+        let (progress, span) = match auction.curve {
+            AuctionCurve::Linear => (elapsed, duration),
+            AuctionCurve::Stepped => {
+                let step = auction.step_duration.max(1) as u128;
+                let total_steps = (duration / step).max(1);
+                ((elapsed / step).min(total_steps), total_steps)
+            }
+        };
+        let drop = (auction.start_price - auction.floor_price) as u128 * progress / span;
+        auction.start_price - drop as u64
+    }
+
+    // The super-admin implicitly holds every role, unless admin actions have moved to a
+    // multisig; explicitly granted roles always count.
+    pub fn has_role(&self, key: &Pubkey, role: Role) -> bool {
+        (*key == self.authority && self.multisig.is_none())
+            || self
+                .role_assignments
+                .iter()
+                .any(|assignment| assignment.role == role && assignment.holder == *key)
+    }
+
+    // Raised, released and withdrawn amounts of SOL (None) or a registered stablecoin.
+    // The raise is what was paid in, net of settlement refunds and of the referral
+    // rewards owed in the same currency, which are paid out of the same treasury or vault.
+    pub fn proceeds_status(&self, payment_mint: Option<Pubkey>, current_time: i64) -> Result<ProceedsStatus> {
+        let (raised, withdrawn, period_withdrawn) = match payment_mint {
+            None => (
+                self.total_paid_lamports
+                    .saturating_sub(self.refunded_lamports)
+                    .saturating_sub(self.referral_lamports_owed),
+                self.withdrawn_lamports,
+                self.period_withdrawn_lamports,
+            ),
+            Some(mint) => {
+                let index = self.payment_mint_index(mint)?;
+                let accepted = &self.accepted_mints[index];
+                (
+                    accepted
+                        .total_received
+                        .saturating_sub(accepted.total_refunded)
+                        .saturating_sub(self.referral_owed_by_mint[index]),
+                    self.withdrawn_by_mint[index],
+                    self.period_withdrawn_by_mint[index],
+                )
+            }
+        };
+
+        let schedule = &self.release_schedule;
+        let released_bps = schedule
+            .released_bps(current_time)
+            .min(self.milestone_released_bps());
+        let unlocked = mul_div(raised, released_bps, BPS_DENOMINATOR)?;
+        let period_remaining = if schedule.period_length == 0 {
+            u64::MAX
+        } else {
+            let period_cap = mul_div(raised, schedule.period_cap_bps as u64, BPS_DENOMINATOR)?;
+            let period_used = if self.withdraw_period_start == schedule.period_start(current_time) {
+                period_withdrawn
+            } else {
+                0
+            };
+            period_cap.saturating_sub(period_used)
+        };
+
+        Ok(ProceedsStatus {
+            raised,
+            unlocked,
+            withdrawn,
+            locked: raised.saturating_sub(unlocked),
+            period_remaining,
+        })
+    }
+
+    // Book a withdrawal against the totals and the current cap period
+    pub fn record_withdrawal(&mut self, payment_mint: Option<Pubkey>, amount: u64, current_time: i64) -> Result<()> {
+        let period_start = self.release_schedule.period_start(current_time);
+        if self.withdraw_period_start != period_start {
+            self.withdraw_period_start = period_start;
+            self.period_withdrawn_lamports = 0;
+            self.period_withdrawn_by_mint = [0; MAX_PAYMENT_MINTS];
+        }
+
+        let (withdrawn, period_withdrawn) = match payment_mint {
+            None => (&mut self.withdrawn_lamports, &mut self.period_withdrawn_lamports),
+            Some(mint) => {
+                let index = self.payment_mint_index(mint)?;
+                (&mut self.withdrawn_by_mint[index], &mut self.period_withdrawn_by_mint[index])
+            }
+        };
+        *withdrawn = withdrawn.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        *period_withdrawn = period_withdrawn.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // Vesting runs from the sale-wide TGE; nothing vests before `finalize_sale`
+    // Piecewise sales read their unlock points from the sale's `UnlockSchedule`
+    pub fn vested_amount(
+        &self,
+        unlock_schedule: Option<&UnlockSchedule>,
+        total_amount: u64,
+        current_time: i64,
+    ) -> Result<u64> {
+        if !self.finalized {
+            return Ok(0);
+        }
+        let points = match (self.vesting_mode, unlock_schedule) {
+            (VestingMode::Piecewise, Some(unlock_schedule)) => unlock_schedule.points.as_slice(),
+            (VestingMode::Piecewise, None) => return Err(ErrorCode::MissingUnlockSchedule.into()),
+            _ => &[],
+        };
+        Ok(self.vesting_schedule(points).vested(self.vesting_mode, total_amount, current_time))
+    }
+
+    // Vested tokens not yet paid out to the buyer; piecewise airdrops pay exactly this
+    pub fn unclaimed_vested(
+        &self,
+        unlock_schedule: Option<&UnlockSchedule>,
+        user_vesting: &UserVesting,
+        current_time: i64,
+    ) -> Result<u64> {
+        let vested = self.vested_amount(unlock_schedule, user_vesting.total_amount, current_time)?;
+        Ok(vested.saturating_sub(user_vesting.claimed_amount))
+    }
+
+    // The initial airdrop is due at TGE; each later one needs another interval past it
+    pub fn airdrop_due(&self, user_vesting: &UserVesting, current_time: i64) -> bool {
+        let periods_elapsed = (current_time - self.tge_time) / self.vesting_interval;
+        user_vesting.airdrops_completed == 0
+            || periods_elapsed > user_vesting.airdrops_completed as i64
+    }
+
+    // A percentage of the buyer's allocation, capped at what claims and earlier airdrops
+    // have not already paid out
+    pub fn airdrop_amount(&self, user_vesting: &UserVesting, percentage: u8) -> Result<u64> {
+        let amount = user_vesting
+            .total_amount
+            .checked_mul(percentage as u64)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(100)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(amount.min(user_vesting.total_amount.saturating_sub(user_vesting.claimed_amount)))
+    }
+
+    pub fn vesting_schedule<'a>(&self, points: &'a [UnlockPoint]) -> VestingSchedule<'a> {
+        VestingSchedule {
+            start: self.tge_time,
+            cliff: self.cliff_period,
+            duration: self.vesting_period,
+            interval: self.vesting_interval,
+            tge_bps: self.tge_unlock_bps,
+            points,
+        }
+    }
+
+    // Sale tokens the vault must keep for buyers and referrers. Uniform clearing with
+    // extra tokens may still credit more, so it reserves the most it could.
+    pub fn tokens_owed(&self) -> Result<u64> {
+        let mut sold = if self.allocation_mode == AllocationMode::FirstCome {
+            self.total_tokens_sold
+        } else {
+            self.allocated_tokens
+        };
+        if self.pricing_mode == PricingMode::DutchAuction
+            && self.dutch_auction.clearing_settlement == Some(ClearingSettlement::ExtraTokens)
+        {
+            sold = mul_div(sold, self.dutch_auction.start_price, self.clearing_price)?;
+        }
+        sold.checked_add(self.referral_tokens_owed)
+            .ok_or(ErrorCode::MathOverflow.into())
+    }
+
+    // Share of the raise milestones allow out, in basis points: whatever no tranche gates,
+    // plus every approved tranche
+    pub fn milestone_released_bps(&self) -> u64 {
+        self.milestones.iter().fold(BPS_DENOMINATOR, |released, milestone| {
+            if milestone.status == MilestoneStatus::Approved {
+                released
+            } else {
+                released - milestone.release_bps as u64
+            }
+        })
+    }
+
+    // Total weight behind milestone votes and refunds: every token allocated to buyers
+    pub fn milestone_vote_weight(&self) -> u64 {
+        if self.allocation_mode == AllocationMode::FirstCome {
+            self.total_tokens_sold
+        } else {
+            self.allocated_tokens
+        }
+    }
+
+    pub fn payment_mint_index(&self, mint: Pubkey) -> Result<usize> {
+        self.accepted_mints
+            .iter()
+            .position(|accepted| accepted.mint == mint)
+            .ok_or(ErrorCode::InvalidPaymentMethod.into())
+    }
+
+    // Returns the trip reason if `price` is too far from the last accepted price
+    pub fn price_deviation_trip(&self, price: u64) -> Option<PauseReason> {
+        let max_deviation_bps = self.circuit_breaker.max_price_deviation_bps as u128;
+        if max_deviation_bps > 0 && self.last_accepted_price > 0 {
+            let deviation = (price as i128 - self.last_accepted_price as i128).unsigned_abs();
+            if deviation * BPS_DENOMINATOR as u128 > self.last_accepted_price as u128 * max_deviation_bps {
+                return Some(PauseReason::PriceDeviation);
+            }
+        }
+        None
+    }
+
+    // As `price_deviation_trip`; an accepted price becomes the new reference
+    pub fn accept_price(&mut self, price: u64) -> Option<PauseReason> {
+        let reason = self.price_deviation_trip(price);
+        if reason.is_none() {
+            self.last_accepted_price = price;
+        }
+        reason
+    }
+
+    // Adds `amount` to the raise-velocity window, starting a new window once the current
+    // one has elapsed. Returns true when the window is over the limit.
+    pub fn record_raise(&mut self, amount: u64, current_time: i64) -> Result<bool> {
+        let breaker = self.circuit_breaker;
+        if breaker.max_raise_per_window == 0 {
+            return Ok(false);
+        }
+        if current_time >= self.velocity_window_start.saturating_add(breaker.velocity_window) {
+            self.velocity_window_start = current_time;
+            self.velocity_window_raised = 0;
+        }
+        self.velocity_window_raised = self
+            .velocity_window_raised
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(self.velocity_window_raised > breaker.max_raise_per_window)
+    }
+
+    // The raise the soft cap is judged on: total_sold_usd as it stood at the close,
+    // before post-sale refunds started lowering it
+    pub fn closing_raise_usd(&self) -> u64 {
+        self.raise_at_close_usd.unwrap_or(self.total_sold_usd)
+    }
+
+    // In Lottery mode only the winning tickets are kept, so the soft cap is judged on
+    // their share of the raise. Every contributor holds exactly one ticket, and the
+    // number of winners is known before the draw.
+    pub fn soft_cap_met(&self) -> bool {
+        let raise = self.closing_raise_usd();
+        let kept = if self.allocation_mode == AllocationMode::Lottery {
+            if self.contributor_count == 0 {
+                0
+            } else {
+                let winners = self.lottery.winner_count.min(self.contributor_count);
+                (raise as u128 * winners as u128 / self.contributor_count as u128) as u64
+            }
+        } else {
+            raise
+        };
+        kept >= self.soft_cap_usd
+    }
+
+    pub fn lottery_reveal_deadline(&self) -> i64 {
+        self.presale_end.saturating_add(self.lottery.reveal_window)
+    }
+
+    // Whether the pending draw request can still be drawn: before its slot the admin
+    // may reveal, and after it the slot hash stays in SlotHashes for SLOT_HASHES_DEPTH
+    // slots. Unrevealed once its slot is reached, it is dead.
+    pub fn lottery_draw_live(&self, slot: u64) -> bool {
+        let draw_slot = self.lottery_draw_slot;
+        if draw_slot == 0 {
+            return false;
+        }
+        if self.lottery_secret.is_none() {
+            return slot < draw_slot;
+        }
+        slot <= draw_slot.saturating_add(SLOT_HASHES_DEPTH)
+    }
+
+    pub fn oracle_config(&self) -> OracleConfig {
+        OracleConfig {
+            kind: self.oracle_kind,
+            max_age: self.oracle_max_age,
+            max_conf_bps: self.oracle_max_conf_bps,
+        }
+    }
+
+    // A round opens at its start time, or as soon as the round before it sells out,
+    // and stays open until its end time or until its token cap is hit.
+    pub fn active_round_index(&self, current_time: i64) -> Option<usize> {
+        for (index, round) in self.rounds.iter().enumerate() {
+            if round.tokens_sold >= round.token_cap || current_time > round.end_time {
+                continue;
+            }
+            let previous_sold_out = index > 0
+                && self.rounds[index - 1].tokens_sold >= self.rounds[index - 1].token_cap;
+            if current_time >= round.start_time || previous_sold_out {
+                return Some(index);
+            }
+            return None;
+        }
+        None
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum ReferralRewardKind {
+    SaleToken,       // Reward is a share of the tokens bought
+    PaymentCurrency, // Reward is a share of the lamports paid
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum Role {
+    SuperAdmin,         // The presale `authority`; every role, transferred by handover only
+    Pauser,             // Pause and unpause the sale
+    PriceOperator,      // Set the manual SOL/USD price override
+    Distributor,        // Run airdrop distributions
+    TreasuryWithdrawer, // Withdraw raised funds
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct RoleAssignment {
+    pub role: Role,
+    pub holder: Pubkey,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Multisig {
+    pub presale_account: Pubkey,
+    #[max_len(MAX_MULTISIG_SIGNERS)]
+    pub signers: Vec<Pubkey>,    // Signer set, at most MAX_MULTISIG_SIGNERS
+    pub threshold: u8,           // Approvals needed to execute
+    pub signer_set_version: u32, // Bumped on every signer change to void pending approvals
+    pub proposal_count: u64,     // Next proposal index
+    pub bump: u8,
+    pub version: u8,             // Layout version, see `Versioned`
+    pub reserved: [u8; 32],      // Zeroed space for future fields
+}
+
+impl Versioned for Multisig {
+    const VERSION: u8 = 1;
+
+    fn version_mut(&mut self) -> &mut u8 {
+        &mut self.version
+    }
+}
+
+impl Multisig {
+    pub fn signer_index(&self, key: &Pubkey) -> Result<usize> {
+        let index = self
+            .signers
+            .iter()
+            .position(|signer| signer == key)
+            .ok_or(ErrorCode::NotMultisigSigner)?;
+        Ok(index)
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Proposal {
+    pub multisig: Pubkey,
+    pub index: u64,
+    pub proposer: Pubkey,
+    pub action: AdminAction,
+    pub signer_set_version: u32, // Signer set the approvals refer to
+    pub approvals: u16,          // Bitmap over the multisig's signer indexes
+    pub executed: bool,
+    pub created_at: i64,
+    pub bump: u8,
+    pub version: u8,             // Layout version, see `Versioned`
+    pub reserved: [u8; 32],      // Zeroed space for future fields
+}
+
+impl Versioned for Proposal {
+    const VERSION: u8 = 1;
+
+    fn version_mut(&mut self) -> &mut u8 {
+        &mut self.version
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub enum AdminAction {
+    UpdatePresaleParams(PresaleParams),
+    SetPauseState { paused: bool },
+    UpdateManualPriceOverride { new_price: Option<u64> },
+    ChangeSigners {
+        #[max_len(MAX_MULTISIG_SIGNERS)]
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    },
+    QueueParamChange { change: ParamChange, eta: i64 },
+    CancelParamChange { id: u64 },
+    SetComplianceSigner { compliance_signer: Option<Pubkey> },
+    SetAllowlistRoot { new_root: Option<[u8; 32]> },
+    SetReferralConfig { reward_bps: u16, reward_kind: ReferralRewardKind },
+    AddPaymentMint { mint: Pubkey, oracle: Option<Pubkey>, oracle_kind: OracleKind },
+    SetPaymentMintEnabled { mint: Pubkey, enabled: bool },
+    GrantRole { role: Role, holder: Pubkey },
+    RevokeRole { role: Role, holder: Pubkey },
+    ProposeAuthority { new_authority: Option<Pubkey> },
+    Migrate,
+}
+
+// Sensitive changes that go through the timelock queue
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub enum ParamChange {
+    PresaleParams(PresaleParams),
+    Price { new_price: u64 },
+    VestingParams { cliff_period: i64, vesting_period: i64, vesting_interval: i64 },
+    TimelockDelay { new_delay: i64 },
+    OracleConfig { oracle_kind: OracleKind, sol_usd_feed: Pubkey, max_age: u64, max_conf_bps: u16 },
+    PriceMode { price_mode: PriceMode, twap_window: i64 },
+}
+
+impl ParamChange {
+    // A finalized sale still prices refunds off the oracle, so its feed can be replaced
+    pub fn frozen_at_finalize(&self) -> bool {
+        !matches!(self, ParamChange::OracleConfig { .. })
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct QueuedChange {
+    pub id: u64,
+    pub change: ParamChange,
+    pub eta: i64,       // Earliest execution time
+    pub queued_at: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct PresaleParams {
+    pub new_price: Option<u64>,
+    pub new_min_buy_amount: Option<u64>,
+    pub new_max_buy_amount: Option<u64>,
+    pub new_hard_cap: Option<u64>,
+    pub new_soft_cap: Option<u64>,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ReferralLedger {
+    pub presale_account: Pubkey,
+    pub referrer: Pubkey,
+    pub reward_tokens: u64,    // Sale tokens credited to the referrer
+    pub reward_lamports: u64,  // Lamports credited to the referrer
+    pub claimed_tokens: u64,   // Sale tokens already claimed
+    pub claimed_lamports: u64, // Lamports already claimed
+    pub bump: u8,
+    pub reward_by_mint: [u64; MAX_PAYMENT_MINTS],  // Payment-token rewards, by registry index
+    pub claimed_by_mint: [u64; MAX_PAYMENT_MINTS], // Payment-token rewards already claimed
+    pub version: u8,                               // Layout version, see `Versioned`
+    pub reserved: [u8; 32],                        // Zeroed space for future fields
+}
+
+impl Versioned for ReferralLedger {
+    const VERSION: u8 = 1;
+
+    fn version_mut(&mut self) -> &mut u8 {
+        &mut self.version
+    }
+}
+
+// Piecewise unlock points, created with every sale and only read in Piecewise mode. Offsets
+// count from the TGE set by `finalize_sale`, since it is not known at initialize.
+#[account]
+#[derive(InitSpace)]
+pub struct UnlockSchedule {
+    pub presale_account: Pubkey,
+    #[max_len(MAX_UNLOCK_POINTS)]
+    pub points: Vec<UnlockPoint>, // Sorted by offset, summing to 100%
+    pub bump: u8,
+    pub version: u8,              // Layout version, see `Versioned`
+    pub reserved: [u8; 32],       // Zeroed space for future fields
+}
+
+impl Versioned for UnlockSchedule {
+    const VERSION: u8 = 1;
+
+    fn version_mut(&mut self) -> &mut u8 {
+        &mut self.version
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct AcceptedMint {
+    pub mint: Pubkey,
+    pub decimals: u8,
+    pub oracle: Option<Pubkey>, // USD price feed, or None for a 1:1 USD peg
+    pub vault: Pubkey,          // Program-owned token account receiving payments
+    pub vault_bump: u8,
+    pub enabled: bool,
+    pub total_received: u64,    // Base units received through this mint
+    pub total_refunded: u64,    // Base units refunded by pro-rata settlement
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum AllocationMode {
+    #[default]
+    FirstCome, // Purchases fill until the hard cap, then are rejected
+    ProRata,   // Purchases are accepted above the hard cap and scaled down at finalization
+    Lottery,   // Each purchase is a ticket; drawn winners keep it, the rest are refunded
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct LotteryConfig {
+    pub ticket_tokens: u64, // Tokens bought by every ticket, and won by every winner
+    pub winner_count: u32,  // Winning tickets to draw
+    pub reveal_window: i64, // Seconds after presale_end to draw before the lottery is voided
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum SaleState {
+    #[default]
+    Active, // Accepting purchases, or ended and paying out
+    Failed, // Ended below the soft cap; buyers reclaim their payment
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum PricingMode {
+    #[default]
+    Rounds,       // Fixed prices per sale round
+    DutchAuction, // Price falls from a start price to a floor over the sale window
+    BondingCurve, // Price rises with every token sold
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum AuctionCurve {
+    #[default]
+    Linear,  // Falls every second
+    Stepped, // Falls once per step_duration
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum ClearingSettlement {
+    ExtraTokens, // Overpayment buys more tokens at the clearing price
+    Refund,      // Overpayment is returned in the currencies it was paid in
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct DutchAuctionConfig {
+    pub start_price: u64,   // Price per token at presale_start, in USD cents
+    pub floor_price: u64,   // Price per token at presale_end, in USD cents
+    pub curve: AuctionCurve,
+    pub step_duration: i64, // Seconds between price drops for a stepped curve
+    pub clearing_settlement: Option<ClearingSettlement>, // Give every buyer the final price
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum CurveKind {
+    #[default]
+    Linear,      // base_price + slope_numerator / slope_denominator per token sold
+    Exponential, // base_price, growing by growth_bps every step_size tokens sold
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct BondingCurveConfig {
+    pub kind: CurveKind,
+    pub base_price: u64,        // Price of the first token, in USD cents
+    pub slope_numerator: u64,   // Linear: cents added per slope_denominator tokens sold
+    pub slope_denominator: u64,
+    pub growth_bps: u16,        // Exponential: growth per step, in bps
+    pub step_size: u64,         // Exponential: tokens sold per growth step
+}
+
+impl BondingCurveConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.base_price == 0 {
+            return Err(ErrorCode::InvalidBondingCurve.into());
+        }
+        match self.kind {
+            CurveKind::Linear if self.slope_denominator == 0 => {
+                Err(ErrorCode::InvalidBondingCurve.into())
+            }
+            CurveKind::Exponential if self.growth_bps == 0 || self.step_size == 0 => {
+                Err(ErrorCode::InvalidBondingCurve.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // CURVE_FIXED_POINT * (1 + growth_bps / 10_000) ^ steps, by repeated squaring
+    fn growth_factor(&self, steps: u64) -> Result<u128> {
+        let mut base = CURVE_FIXED_POINT + CURVE_FIXED_POINT * self.growth_bps as u128 / BPS_DENOMINATOR as u128;
+        let mut result = CURVE_FIXED_POINT;
+        let mut exponent = steps;
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.checked_mul(base).ok_or(ErrorCode::MathOverflow)? / CURVE_FIXED_POINT;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.checked_mul(base).ok_or(ErrorCode::MathOverflow)? / CURVE_FIXED_POINT;
+            }
+        }
+        Ok(result)
+    }
+
+    // Marginal price of the token sold after `sold` tokens, in USD cents (rounded up)
+    pub fn price_at(&self, sold: u64) -> Result<u64> {
+        let price = match self.kind {
+            CurveKind::Linear => {
+                self.base_price as u128
+                    + div_ceil(
+                        (self.slope_numerator as u128)
+                            .checked_mul(sold as u128)
+                            .ok_or(ErrorCode::MathOverflow)?,
+                        self.slope_denominator as u128,
+                    )
+            }
+            CurveKind::Exponential => div_ceil(
+                (self.base_price as u128)
+                    .checked_mul(self.growth_factor(sold / self.step_size)?)
+                    .ok_or(ErrorCode::MathOverflow)?,
+                CURVE_FIXED_POINT,
+            ),
+        };
+        u64::try_from(price).map_err(|_| ErrorCode::MathOverflow.into())
+    }
+
+    // Exact cost of tokens [sold, sold + amount), in USD cents, rounded up against the buyer.
+    // Linear: n * base + slope * (n * (2s + n - 1) / 2). Exponential: the partial steps at
+    // either end plus a geometric series over the whole steps in between. Rounding happens
+    // on the total, so it never exceeds the sum of the per-token `price_at` quotes.
+    pub fn cost(&self, sold: u64, amount: u64) -> Result<u64> {
+        if amount == 0 {
+            return Ok(0);
+        }
+        let s = sold as u128;
+        let n = amount as u128;
+        let cost = match self.kind {
+            CurveKind::Linear => {
+                let index_sum = n
+                    .checked_mul(2 * s + n - 1)
+                    .ok_or(ErrorCode::MathOverflow)?
+                    / 2;
+                let slope_cost = div_ceil(
+                    (self.slope_numerator as u128)
+                        .checked_mul(index_sum)
+                        .ok_or(ErrorCode::MathOverflow)?,
+                    self.slope_denominator as u128,
+                );
+                n.checked_mul(self.base_price as u128)
+                    .ok_or(ErrorCode::MathOverflow)?
+                    .checked_add(slope_cost)
+                    .ok_or(ErrorCode::MathOverflow)?
+            }
+            CurveKind::Exponential => {
+                let step = self.step_size as u128;
+                let end = s.checked_add(n).ok_or(ErrorCode::MathOverflow)?;
+                let (first_step, last_step) = ((s / step) as u64, (end / step) as u64);
+                if first_step == last_step {
+                    n * self.price_at(sold)? as u128
+                } else {
+                    // Tokens left in the first step, and tokens into the last one
+                    let head = (step - s % step) * self.price_at(sold)? as u128;
+                    let tail = (end % step) * self.price_at(end as u64)? as u128;
+
+                    // sum_{k=a+1}^{b-1} g^k = (g^b - g^(a+1)) / (g - 1)
+                    let series = div_ceil(
+                        (self.growth_factor(last_step)? - self.growth_factor(first_step + 1)?)
+                            .checked_mul(BPS_DENOMINATOR as u128)
+                            .ok_or(ErrorCode::MathOverflow)?,
+                        self.growth_bps as u128,
+                    );
+                    let middle = div_ceil(
+                        series
+                            .checked_mul(self.base_price as u128 * step)
+                            .ok_or(ErrorCode::MathOverflow)?,
+                        CURVE_FIXED_POINT,
+                    );
+                    head.checked_add(middle)
+                        .and_then(|total| total.checked_add(tail))
+                        .ok_or(ErrorCode::MathOverflow)?
+                }
+            }
+        };
+        u64::try_from(cost).map_err(|_| ErrorCode::MathOverflow.into())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct PurchaseQuote {
+    pub cost_in_usd: u64,   // Total cost in USD cents
+    pub average_price: u64, // Cost per token, in USD cents
+    pub start_price: u64,   // Marginal price before the purchase
+    pub end_price: u64,     // Marginal price after the purchase
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum PriceMode {
+    #[default]
+    Spot,    // Latest oracle price
+    Twap,    // Time-weighted average over `twap_window`
+    WorseOf, // Whichever of spot and TWAP charges the buyer more
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct PriceObservation {
+    pub price: u64,     // SOL/USD in USD cents
+    pub timestamp: i64, // When the observation was recorded
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum OracleKind {
+    #[default]
+    PythLegacy,        // Pyth push-oracle price account
+    PythPriceUpdateV2, // Pyth pull-oracle `PriceUpdateV2` account, bound by feed id
+    Switchboard,       // Switchboard V2 aggregator account
+}
+
+// Oracle reading normalized to program price units, whatever the provider
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct OraclePrice {
+    pub price: u64,        // USD cents
+    pub conf: u64,         // Confidence interval in USD cents
+    pub publish_time: i64, // Unix timestamp of the reading
+    pub manual: bool,      // The manual override stood in for a failed oracle read
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct CircuitBreakerConfig {
+    pub max_price_deviation_bps: u16, // Largest SOL/USD move from the last accepted price
+    pub max_oracle_staleness: u64,    // Oldest SOL/USD publish time tolerated, in seconds
+    pub velocity_window: i64,         // Raise-velocity window, in seconds
+    pub max_raise_per_window: u64,    // SOL-equivalent raise allowed per window
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum PauseReason {
+    PriceDeviation, // SOL/USD moved too far from the last accepted price
+    OracleStale,    // SOL/USD feed stopped updating
+    RaiseVelocity,  // Too much raised within one window
+}
+
+// Fixed at initialize. `tge_bps` of the raise unlocks at `tge_time`, the rest over
+// `release_duration`, either linearly or in `release_step` increments. A zero duration
+// releases only the TGE share, so a zeroed schedule keeps everything locked.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct ReleaseSchedule {
+    pub tge_time: i64,         // When releases start, no earlier than presale end
+    pub tge_bps: u16,          // Share of the raise released at TGE
+    pub release_duration: i64, // Seconds over which the remainder unlocks
+    pub release_step: i64,     // 0 for a linear release, otherwise the step length in seconds
+    pub period_length: i64,    // Withdrawal-cap period in seconds, 0 for no cap
+    pub period_cap_bps: u16,   // Share of the raise withdrawable per period
+}
+
+impl ReleaseSchedule {
+    pub fn validate(&self, presale_end: i64) -> Result<()> {
+        let bps = BPS_DENOMINATOR as u16;
+        if self.tge_time < presale_end
+            || self.tge_bps > bps
+            || self.release_duration < 0
+            || self.release_step < 0
+            || self.release_step > self.release_duration
+            || self.period_length < 0
+            || self.period_cap_bps > bps
+            || (self.period_length > 0) != (self.period_cap_bps > 0)
+        {
+            return Err(ErrorCode::InvalidReleaseSchedule.into());
+        }
+        Ok(())
+    }
+
+    // Share of the raise released at `current_time`, in basis points
+    pub fn released_bps(&self, current_time: i64) -> u64 {
+        if current_time < self.tge_time {
+            return 0;
+        }
+        let tge_bps = self.tge_bps as u64;
+        if self.release_duration == 0 {
+            return tge_bps;
+        }
+
+        let mut elapsed = (current_time - self.tge_time).min(self.release_duration);
+        if self.release_step > 0 && elapsed < self.release_duration {
+            elapsed -= elapsed % self.release_step;
+        }
+        let vesting_bps = (BPS_DENOMINATOR - tge_bps) as u128 * elapsed as u128
+            / self.release_duration as u128;
+        tge_bps + vesting_bps as u64
+    }
+
+    // Start of the withdrawal-cap period containing `current_time`
+    pub fn period_start(&self, current_time: i64) -> i64 {
+        if self.period_length == 0 || current_time < self.tge_time {
+            return self.tge_time;
+        }
+        current_time - (current_time - self.tge_time) % self.period_length
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct ProceedsStatus {
+    pub raised: u64,           // Paid in, net of settlement refunds
+    pub unlocked: u64,         // Released by the schedule so far, withdrawn or not
+    pub withdrawn: u64,        // Taken out by the project
+    pub locked: u64,           // Not yet released
+    pub period_remaining: u64, // Still withdrawable in the current cap period
+}
+
+// Zero decodes as KeepForLiquidity, so accounts from before the policy existed keep
+// their unsold tokens in the vault
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum UnsoldTokenPolicy {
+    #[default]
+    KeepForLiquidity, // Leave them in the vault
+    ReturnToProject,  // Send them to the authority's token account
+    Burn,             // Burn them from the vault
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct MilestoneConfig {
+    pub release_bps: u16, // Share of the raise the tranche releases once approved
+    pub vote_start: i64,
+    pub vote_end: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum MilestoneStatus {
+    #[default]
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct Milestone {
+    pub release_bps: u16,
+    pub vote_start: i64,
+    pub vote_end: i64,
+    pub yes_weight: u64, // Token allocation voting to release
+    pub no_weight: u64,  // Token allocation voting against
+    pub status: MilestoneStatus,
+}
+
+impl From<&MilestoneConfig> for Milestone {
+    fn from(config: &MilestoneConfig) -> Self {
+        Milestone {
+            release_bps: config.release_bps,
+            vote_start: config.vote_start,
+            vote_end: config.vote_end,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct OracleConfig {
+    pub kind: OracleKind,
+    pub max_age: u64,      // Seconds
+    pub max_conf_bps: u16, // Confidence interval as bps of price
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct PurchaseCost {
+    pub usd: u64, // Cost in USD cents
+    pub sol: u64, // Cost in lamports at the SOL/USD price used for the purchase
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct SaleRound {
+    pub start_time: i64,  // Round opens (earlier if the previous round sells out)
+    pub end_time: i64,    // Round closes
+    pub price: u64,       // Price per token in USD cents
+    pub token_cap: u64,   // Maximum tokens sold in this round
+    pub wallet_cap: u64,  // Maximum tokens a single wallet can buy in this round
+    pub allowlist_only: bool, // Restrict the round to wallets on the allowlist
+    pub tokens_sold: u64, // Tokens sold in this round so far
+    pub sol_raised: u64,  // SOL-equivalent lamports raised in this round so far
+}
+
+#[derive(Accounts)]
+pub struct CalculateClaimable<'info> {
+    #[account(mut)]
+    pub user_vesting: Account<'info, UserVesting>,
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(has_one = presale_account)]
+    pub unlock_schedule: Option<Account<'info, UnlockSchedule>>, // Required in Piecewise mode
+}
+
+#[derive(Accounts)]
+pub struct ExecuteParamChange<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+}
+
+#[derive(Accounts)]
+pub struct Migrate<'info> {
+    /// CHECK: Owner and discriminator are checked by `migrate`, which decodes it once grown
+    #[account(mut)]
+    pub presale_account: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub authority: Signer<'info>, // Super-admin, or a multisig signer; pays rent for the added space
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    pub new_authority: Signer<'info>, // Must match `pending_authority`
+}
+
+#[derive(Accounts)]
+pub struct CreateMultisig<'info> {
+    #[account(mut, has_one = authority)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Multisig::INIT_SPACE,
+        seeds = [b"multisig", presale_account.key().as_ref()],
+        bump,
+    )]
+    pub multisig: Account<'info, Multisig>,
+    #[account(mut)]
+    pub authority: Signer<'info>, // Admin account
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAdminAction<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.presale_account.as_ref()],
+        bump = multisig.bump,
+    )]
+    pub multisig: Account<'info, Multisig>,
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + Proposal::INIT_SPACE,
+        seeds = [b"proposal", multisig.key().as_ref(), &multisig.proposal_count.to_le_bytes()],
+        bump,
+    )]
+    pub proposal: Account<'info, Proposal>,
+    #[account(mut)]
+    pub proposer: Signer<'info>, // Must be in the signer set
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveProposal<'info> {
+    pub multisig: Account<'info, Multisig>,
+    #[account(mut, has_one = multisig)]
+    pub proposal: Account<'info, Proposal>,
+    pub signer: Signer<'info>, // Must be in the signer set
+}
+
+#[derive(Accounts)]
+pub struct ExecuteProposal<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(mut, has_one = presale_account)]
+    pub multisig: Account<'info, Multisig>,
+    #[account(mut, has_one = multisig)]
+    pub proposal: Account<'info, Proposal>,
+    pub executor: Signer<'info>, // Must be in the signer set
+}
+
+#[derive(Accounts)]
+pub struct QuotePurchase<'info> {
+    pub presale_account: Account<'info, PresaleAccount>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateManualPriceOverride<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    pub authority: Signer<'info>, // Super-admin or price operator
+}
+
+#[derive(Accounts)]
+pub struct UpdatePresaleParams<'info> {
+    #[account(mut, has_one = authority)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    pub authority: Signer<'info>, // Admin account
+}
+
+// Define the `Claim` context for claiming tokens
+#[derive(Accounts)]
+pub struct Claim<'info> {
+    #[account(
+        mut,
+        has_one = presale_account,
+        constraint = user_vesting.owner == buyer.key() @ ErrorCode::UnauthorizedAccess,
+    )]
+    pub user_vesting: Account<'info, UserVesting>,
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(has_one = presale_account)]
+    pub unlock_schedule: Option<Account<'info, UnlockSchedule>>, // Required in Piecewise mode
+    pub buyer: Signer<'info>,
+    #[account(mut, address = presale_account.sale_vault)]
+    pub sale_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = presale_account.sale_mint,
+        token::authority = buyer,
+    )]
+    pub buyer_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
 }
 
-pub fn get_price_from_oracle(
-    oracle_account: &AccountInfo,
-    manual_price_override: Option<u64>,
-) -> Result<u64> {
-    // Attempt to load the price feed from the oracle
-    if let Ok(price_feed) = load_price_feed_from_account_info(oracle_account) {
-        if let Some(price_data) = price_feed.get_current_price() {
-            if price_data.price > 0 {
-                return Ok(price_data.price as u64); // Return valid oracle price
-            }
-        }
-    }
+#[derive(Accounts)]
+pub struct DistributeAirdrop<'info> {
+    pub authority: Signer<'info>, // Super-admin or distributor
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(has_one = presale_account)]
+    pub unlock_schedule: Option<Account<'info, UnlockSchedule>>, // Required in Piecewise mode
+    #[account(mut, has_one = presale_account)]
+    pub user_vesting: Account<'info, UserVesting>,
+    #[account(mut, address = presale_account.sale_vault)]
+    pub sale_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = presale_account.sale_mint,
+        token::authority = user_vesting.owner,
+    )]
+    pub recipient_account: Account<'info, TokenAccount>, // The buyer's own sale token account
+    pub token_program: Program<'info, Token>,
+}
 
-    // Fallback to manual price override if oracle fails or returns invalid data
-    if let Some(price) = manual_price_override {
-        if price == 0 || price > 1_000_000 {
-            // Validate fallback price
-            return Err(ErrorCode::InvalidPrice.into());
-        }
-        return Ok(price);
-    }
+#[derive(Accounts)]
+pub struct Purchase<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = 8 + UserVesting::INIT_SPACE,
+        seeds = [b"vesting", presale_account.key().as_ref(), buyer.key().as_ref()],
+        bump,
+    )]
+    pub user_vesting: Account<'info, UserVesting>, // Created on the buyer's first purchase
+    #[account(mut)]
+    pub buyer: Signer<'info>, // Buyer of the presale
+    /// CHECK: Presale treasury PDA, receives the SOL payment
+    #[account(
+        mut,
+        seeds = [b"treasury", presale_account.key().as_ref()],
+        bump = presale_account.treasury_bump,
+    )]
+    pub treasury: UncheckedAccount<'info>,
+    pub sol_to_usd_oracle: AccountInfo<'info>, // Oracle for SOL to USD conversion
+    /// CHECK: Instructions sysvar, read for the compliance voucher signature
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
 
-    Err(ErrorCode::PriceFeedUnavailable.into())
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(
+        init,
+        payer = referrer,
+        space = 8 + ReferralLedger::INIT_SPACE,
+        seeds = [b"referral", presale_account.key().as_ref(), referrer.key().as_ref()],
+        bump
+    )]
+    pub referral_ledger: Account<'info, ReferralLedger>,
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(mut)] // Mark the payer account as mutable
-    pub user: Signer<'info>,
+pub struct ClaimReferralRewards<'info> {
     #[account(mut)]
-    pub presale_account: Account<'info, PresaleAccount>, 
-    #[account(init, payer = user, space = 8 + 64)] // Payer = user
-    pub data_account: Account<'info, SomeData>,
-    pub system_program: Program<'info, System>, // No need to mark this as mutable
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(has_one = presale_account)]
+    pub unlock_schedule: Option<Account<'info, UnlockSchedule>>, // Required in Piecewise mode
+    #[account(
+        mut,
+        seeds = [b"referral", presale_account.key().as_ref(), referrer.key().as_ref()],
+        bump = referral_ledger.bump,
+        has_one = referrer,
+    )]
+    pub referral_ledger: Account<'info, ReferralLedger>,
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+    #[account(mut, address = presale_account.sale_vault)]
+    pub sale_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = presale_account.sale_mint,
+        token::authority = referrer,
+    )]
+    pub referrer_token_account: Account<'info, TokenAccount>, // Referrer's sale token account
+    /// CHECK: Presale treasury PDA, pays lamport rewards
+    #[account(
+        mut,
+        seeds = [b"treasury", presale_account.key().as_ref()],
+        bump = presale_account.treasury_bump,
+    )]
+    pub treasury: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
-// Example account struct
-#[account]
-pub struct DataAccount {
-    pub data: u64,
+#[derive(Accounts)]
+pub struct ClaimReferralPaymentRewards<'info> {
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(has_one = presale_account)]
+    pub unlock_schedule: Option<Account<'info, UnlockSchedule>>, // Required in Piecewise mode
+    #[account(
+        mut,
+        seeds = [b"referral", presale_account.key().as_ref(), referrer.key().as_ref()],
+        bump = referral_ledger.bump,
+        has_one = referrer,
+    )]
+    pub referral_ledger: Account<'info, ReferralLedger>,
+    pub referrer: Signer<'info>,
+    pub payment_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"payment_vault", presale_account.key().as_ref(), payment_mint.key().as_ref()],
+        bump,
+    )]
+    pub payment_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = referrer,
+    )]
+    pub referrer_payment_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
 }
 
-#[account]
-pub struct SomeData {
-    pub value: String, // Store unsupported fields inside program-defined accounts
-    pub extra_data: [u8; 56], // 56 bytes
+#[derive(Accounts)]
+pub struct AddPaymentMint<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(mut)]
+    pub authority: Signer<'info>, // Admin, or a multisig signer; pays for the vault
+    #[account(has_one = presale_account)]
+    pub multisig: Option<Account<'info, Multisig>>, // Required once a multisig is configured
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>, // Approved `AddPaymentMint` proposal
+    pub payment_mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"payment_vault", presale_account.key().as_ref(), payment_mint.key().as_ref()],
+        bump,
+        token::mint = payment_mint,
+        token::authority = payment_vault, // The vault PDA owns itself
+    )]
+    pub payment_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
 }
 
-#[account]
-pub struct UserVesting {
-    pub total_amount: u64,        // Total tokens purchased
-    pub claimed_amount: u64,      // Tokens already claimed
-    pub start_time: i64,          // Presale end time
-    pub airdrops_completed: u8,   // Number of airdrops already distributed
-    pub total_purchased_sol: u64, // Total SOL equivalent purchased by this user
+#[derive(Accounts)]
+pub struct PurchaseWithToken<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = 8 + UserVesting::INIT_SPACE,
+        seeds = [b"vesting", presale_account.key().as_ref(), buyer.key().as_ref()],
+        bump,
+    )]
+    pub user_vesting: Account<'info, UserVesting>, // Created on the buyer's first purchase
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    pub payment_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = buyer,
+    )]
+    pub buyer_payment_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub payment_vault: Account<'info, TokenAccount>, // Checked against the registry
+    /// CHECK: USD feed for the payment mint, checked against the registry (unused for pegged mints)
+    pub payment_oracle: AccountInfo<'info>,
+    pub sol_to_usd_oracle: AccountInfo<'info>, // Oracle for SOL to USD conversion
+    /// CHECK: Instructions sysvar, read for the compliance voucher signature
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
-#[account]
-pub struct PresaleAccount {
-    pub presale_start: i64,
-    pub presale_end: i64,
-    pub public_sale_start: i64,
-    pub price: u64, // Price per token in USD cents
-    pub max_allocation: u64,
-    pub cliff_period: i64,
-    pub vesting_period: i64,
-    pub vesting_interval: i64,
-    pub total_airdrop_periods: u8,
-    pub airdrop_percentages: Vec<u8>,
-    pub total_sold_sol: u64,                // Total tokens sold
-    pub min_buy_amount_sol: u64,            // Minimum SOL amount per purchase
-    pub max_buy_amount_sol: u64,            // Maximum SOL amount per user
-    pub hard_cap_sol: u64,                  // Maximum SOL for the entire presale
-    pub authority: Pubkey,                  // Admin authority key
-    pub manual_price_override: Option<u64>, // Optional manual price in USD cents
-    pub paused: bool,                       // Whether the presale is paused
+#[derive(Accounts)]
+pub struct MarkSaleFailed<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
 }
 
 #[derive(Accounts)]
-pub struct CalculateClaimable<'info> {
+pub struct FinalizeClearingPrice<'info> {
     #[account(mut)]
-    pub user_vesting: Account<'info, UserVesting>,
     pub presale_account: Account<'info, PresaleAccount>,
 }
 
 #[derive(Accounts)]
-pub struct UpdateManualPriceOverride<'info> {
-    #[account(mut, has_one = authority)]
+pub struct SettleClearingPrice<'info> {
+    #[account(mut)]
     pub presale_account: Account<'info, PresaleAccount>,
-    pub authority: Signer<'info>, // Admin account
+    #[account(
+        mut,
+        has_one = presale_account,
+        constraint = user_vesting.owner == buyer.key() @ ErrorCode::UnauthorizedAccess,
+    )]
+    pub user_vesting: Account<'info, UserVesting>,
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    /// CHECK: Presale treasury PDA, pays SOL refunds
+    #[account(
+        mut,
+        seeds = [b"treasury", presale_account.key().as_ref()],
+        bump = presale_account.treasury_bump,
+    )]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub referral_ledger: Option<Account<'info, ReferralLedger>>, // Buyer's referrer's ledger, if they were referred
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct UpdatePresaleParams<'info> {
-    #[account(mut, has_one = authority)]
+pub struct FinalizeProRata<'info> {
+    #[account(mut)]
     pub presale_account: Account<'info, PresaleAccount>,
-    pub authority: Signer<'info>, // Admin account
 }
 
-// Define the `Claim` context for claiming tokens
 #[derive(Accounts)]
-pub struct Claim<'info> {
+pub struct SettleAllocation<'info> {
     #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(
+        mut,
+        has_one = presale_account,
+        constraint = user_vesting.owner == buyer.key() @ ErrorCode::UnauthorizedAccess,
+    )]
     pub user_vesting: Account<'info, UserVesting>,
+    /// CHECK: Owner of `user_vesting`, receives the SOL refund
     #[account(mut)]
-    pub presale_account: Account<'info, PresaleAccount>, // Added
+    pub buyer: UncheckedAccount<'info>,
+    pub cranker: Signer<'info>, // Anyone; settlement only pays the buyer
+    /// CHECK: Presale treasury PDA, pays SOL refunds
+    #[account(
+        mut,
+        seeds = [b"treasury", presale_account.key().as_ref()],
+        bump = presale_account.treasury_bump,
+    )]
+    pub treasury: UncheckedAccount<'info>,
     #[account(mut)]
-    pub buyer: Signer<'info>,
+    pub referral_ledger: Option<Account<'info, ReferralLedger>>, // Buyer's referrer's ledger, if they were referred
     pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct DistributeAirdrop<'info> {
+pub struct RequestLotteryDraw<'info> {
     #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    pub revealer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RevealLotterySeed<'info> {
+    #[account(mut, has_one = authority)]
+    pub presale_account: Account<'info, PresaleAccount>,
     pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DrawLottery<'info> {
     #[account(mut)]
     pub presale_account: Account<'info, PresaleAccount>,
+    /// CHECK: SlotHashes sysvar, parsed by `find_slot_hash`
+    #[account(address = anchor_lang::solana_program::sysvar::slot_hashes::ID)]
+    pub slot_hashes: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct VoidLottery<'info> {
     #[account(mut)]
-    pub recipient_account: Account<'info, TokenAccount>,
-    pub system_program: Program<'info, System>,
+    pub presale_account: Account<'info, PresaleAccount>,
 }
 
 #[derive(Accounts)]
-pub struct SomeContext<'info> {
+pub struct SweepProRataDust<'info> {
+    #[account(mut, has_one = authority, has_one = dust_recipient)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    pub authority: Signer<'info>, // Admin account
+    #[account(mut, address = presale_account.sale_vault)]
+    pub sale_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = presale_account.sale_mint,
+        token::authority = dust_recipient,
+    )]
+    pub dust_recipient_token_account: Account<'info, TokenAccount>,
+    /// CHECK: Bound to `presale_account.dust_recipient`; only receives lamports
     #[account(mut)]
-    pub program_pda: UncheckedAccount<'info>, // Must be mutable to modify lamports
+    pub dust_recipient: UncheckedAccount<'info>,
+    /// CHECK: Presale treasury PDA, pays the lamport dust
+    #[account(
+        mut,
+        seeds = [b"treasury", presale_account.key().as_ref()],
+        bump = presale_account.treasury_bump,
+    )]
+    pub treasury: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct Purchase<'info> {
+pub struct ReclaimSolPayment<'info> {
     #[account(mut)]
     pub presale_account: Account<'info, PresaleAccount>,
+    #[account(
+        mut,
+        has_one = presale_account,
+        constraint = user_vesting.owner == buyer.key() @ ErrorCode::UnauthorizedAccess,
+    )]
+    pub user_vesting: Account<'info, UserVesting>,
     #[account(mut)]
-    pub user_vesting: Account<'info, UserVesting>, // Example, replace with your actual accounts
-    #[account(mut)]
-    pub buyer: Signer<'info>, // Buyer of the presale
+    pub buyer: Signer<'info>,
+    /// CHECK: Presale treasury PDA, returns the SOL payment
+    #[account(
+        mut,
+        seeds = [b"treasury", presale_account.key().as_ref()],
+        bump = presale_account.treasury_bump,
+    )]
+    pub treasury: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimTokenPayment<'info> {
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(
+        mut,
+        has_one = presale_account,
+        constraint = user_vesting.owner == buyer.key() @ ErrorCode::UnauthorizedAccess,
+    )]
+    pub user_vesting: Account<'info, UserVesting>,
+    pub buyer: Signer<'info>,
+    pub payment_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"payment_vault", presale_account.key().as_ref(), payment_mint.key().as_ref()],
+        bump,
+    )]
+    pub payment_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = buyer,
+    )]
+    pub buyer_payment_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawSaleTokensOnFailure<'info> {
+    #[account(has_one = authority)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    pub authority: Signer<'info>, // Admin account
+    #[account(mut, address = presale_account.sale_vault)]
+    pub sale_vault: Account<'info, TokenAccount>, // Deposited sale tokens
+    #[account(mut, token::mint = presale_account.sale_mint)]
+    pub project_token_account: Account<'info, TokenAccount>, // Receives the sale tokens back
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePrice<'info> {
     #[account(mut)]
-    pub program_pda: UncheckedAccount<'info>, // Add the missing field
+    pub presale_account: Account<'info, PresaleAccount>,
     pub sol_to_usd_oracle: AccountInfo<'info>, // Oracle for SOL to USD conversion
-    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
 pub struct Refund<'info> {
     #[account(mut)]
     pub presale_account: Account<'info, PresaleAccount>, // Presale account storing presale details
-    #[account(mut)]
+    #[account(has_one = presale_account)]
+    pub unlock_schedule: Option<Account<'info, UnlockSchedule>>, // Required in Piecewise mode
+    #[account(
+        mut,
+        has_one = presale_account,
+        constraint = user_vesting.owner == buyer.key() @ ErrorCode::UnauthorizedAccess,
+    )]
     pub user_vesting: Account<'info, UserVesting>, // User's vesting account
     #[account(mut)]
     pub buyer: Signer<'info>, // User requesting the refund
-    pub sol_to_usd_oracle: AccountInfo<'info>, // Oracle account for SOL/USD price
+    /// CHECK: Presale treasury PDA, pays the refund
+    #[account(
+        mut,
+        seeds = [b"treasury", presale_account.key().as_ref()],
+        bump = presale_account.treasury_bump,
+    )]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub referral_ledger: Option<Account<'info, ReferralLedger>>, // Buyer's referrer's ledger, if they were referred
+    pub token_program: Program<'info, Token>, // Pays stablecoin shares out of the payment vaults
     pub system_program: Program<'info, System>, // System program for SOL transfers
 }
 
 #[derive(Accounts)]
 pub struct SetPauseState<'info> {
-    #[account(mut, has_one = authority)]
+    #[account(mut)]
     pub presale_account: Account<'info, PresaleAccount>,
-    pub authority: Signer<'info>, // Admin account
+    pub authority: Signer<'info>, // Super-admin or pauser
 }
 
 #[derive(Accounts)]
 pub struct BatchDistributeAirdrops<'info> {
-    #[account(mut)]
-    pub authority: Signer<'info>,               // The account that signs the transaction
-    #[account(mut)]
+    pub authority: Signer<'info>, // Super-admin or distributor
     pub presale_account: Account<'info, PresaleAccount>,
-    pub user_vesting: Account<'info, TokenAccount>,
-    pub system_program: Program<'info, System>, // Required system program
+    #[account(has_one = presale_account)]
+    pub unlock_schedule: Option<Account<'info, UnlockSchedule>>, // Required in Piecewise mode
+    #[account(mut, address = presale_account.sale_vault)]
+    pub sale_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy)]
+pub struct PurchaseVoucher {
+    pub max_amount: u64, // Most tokens this voucher can buy
+    pub expiry: i64,     // Unix timestamp after which the voucher is rejected
+    pub nonce: u64,      // Must exceed the buyer's last used nonce
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize, Clone)]
+pub struct AllowlistProof {
+    pub max_allocation: u64,   // Lamports this wallet may spend in total
+    pub proof: Vec<[u8; 32]>,  // Sibling hashes from the leaf up to the root
 }
 
 #[derive(AnchorDeserialize, AnchorSerialize, Clone)]
 pub struct UserDistribution {
     pub user_vesting_index: usize, // Index in the remaining accounts array
     pub airdrop_index: u8,         // Index of the current airdrop percentage
+    pub recipient_index: usize,    // Index of the buyer's sale token account in the remaining accounts
 }
 
 // Define the PurchaseEvent at the top of your contract
 #[event]
 pub struct PurchaseEvent {
     pub presale_account: Pubkey,
-    pub buyer: Pubkey,            // Buyer's wallet public key
-    pub cost_in_sol: Option<u64>, // Cost in SOL equivalent (in lamports, if applicable)
+    pub buyer: Pubkey,            // Buyer's wallet public key
+    pub amount: u64,              // Number of tokens purchased
+    pub cost_in_sol: Option<u64>, // Cost in SOL equivalent (in lamports, if applicable)
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenPurchaseEvent {
+    pub presale_account: Pubkey,
+    pub buyer: Pubkey,
+    pub amount: u64,                 // Number of tokens purchased
+    pub payment_mint: Pubkey,        // Stablecoin used for payment
+    pub payment_amount: u64,         // Base units of the payment mint charged
+    pub cost_in_usd: u64,            // Cost in USD cents
+    pub cost_in_sol_equivalent: u64, // Lamports counted against the SOL caps
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PaymentMintUpdated {
+    pub presale_account: Pubkey,
+    pub mint: Pubkey,
+    pub oracle: Option<Pubkey>,
+    pub oracle_kind: OracleKind,
+    pub enabled: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RoundPurchaseEvent {
+    pub presale_account: Pubkey,
+    pub round_index: u8,         // Round the tokens were sold from
+    pub amount: u64,             // Tokens sold from this round by the purchase
+    pub cost_in_sol: u64,        // Lamports charged for this round's share
+    pub round_tokens_sold: u64,  // Round total after the purchase
+    pub round_sol_raised: u64,   // Round lamports raised after the purchase
+}
+
+#[event]
+pub struct ReferralCredited {
+    pub presale_account: Pubkey,
+    pub referrer: Pubkey,
+    pub buyer: Pubkey,
+    pub reward_tokens: u64,         // Sale tokens credited for this purchase
+    pub payment_mint: Option<Pubkey>, // Payment mint of the credit, or None for SOL
+    pub reward_payment: u64,        // Payment currency credited for this purchase
+}
+
+#[event]
+pub struct ReferralClawedBack {
+    pub referrer: Pubkey,
+    pub buyer: Pubkey,
+    pub tokens: u64,   // Sale-token reward taken back
+    pub lamports: u64, // Lamport reward taken back
+}
+
+#[event]
+pub struct ReferralRewardsClaimed {
+    pub referrer: Pubkey,
+    pub tokens: u64,   // Sale tokens paid out
+    pub lamports: u64, // Lamports paid out
+}
+
+#[event]
+pub struct ReferralPaymentRewardsClaimed {
+    pub referrer: Pubkey,
+    pub payment_mint: Pubkey,
+    pub amount: u64, // Base units of the payment mint paid out
+}
+
+#[event]
+pub struct ParamChangeQueued {
+    pub presale_account: Pubkey,
+    pub id: u64,
+    pub change: ParamChange,
+    pub eta: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ParamChangeExecuted {
+    pub presale_account: Pubkey,
+    pub id: u64,
+    pub change: ParamChange,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ParamChangeCancelled {
+    pub presale_account: Pubkey,
+    pub id: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RoleUpdated {
+    pub presale_account: Pubkey,
+    pub role: Role,
+    pub holder: Pubkey,
+    pub granted: bool, // false when revoked
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityTransferProposed {
+    pub presale_account: Pubkey,
+    pub current_authority: Pubkey,
+    pub pending_authority: Option<Pubkey>, // None when a proposal is cancelled
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AccountMigrated {
+    pub account: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityTransferred {
+    pub presale_account: Pubkey,
+    pub previous_authority: Pubkey,
+    pub new_authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MultisigUpdated {
+    pub multisig: Pubkey,
+    pub signers: Vec<Pubkey>,
+    pub threshold: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalCreated {
+    pub multisig: Pubkey,
+    pub proposal: Pubkey,
+    pub index: u64,
+    pub proposer: Pubkey,
+    pub action: AdminAction,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalApproved {
+    pub proposal: Pubkey,
+    pub signer: Pubkey,
+    pub approvals: u8, // Approvals so far
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalExecuted {
+    pub proposal: Pubkey,
+    pub executor: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ComplianceSignerUpdated {
+    pub presale_account: Pubkey,
+    pub compliance_signer: Option<Pubkey>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AllowlistRootUpdated {
+    pub presale_account: Pubkey,
+    pub new_root: Option<[u8; 32]>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SaleRoundsUpdated {
+    pub presale_account: Pubkey,
+    pub round_count: u8,
     pub timestamp: i64,
 }
 
@@ -768,6 +6833,7 @@ pub struct PresaleParamsUpdated {
     pub new_min_buy_amount: Option<u64>,
     pub new_max_buy_amount: Option<u64>,
     pub new_hard_cap: Option<u64>,
+    pub new_soft_cap: Option<u64>,
     pub timestamp: i64,
 }
 
@@ -781,6 +6847,61 @@ pub struct RefundEvent {
     pub total_refunded_sol: u64,  // Total SOL refunded so far
 }
 
+#[event]
+pub struct VaultFunded {
+    pub presale_account: Pubkey,
+    pub funder: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProceedsWithdrawn {
+    pub presale_account: Pubkey,
+    pub payment_mint: Option<Pubkey>, // None for SOL
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub withdrawn: u64, // Total withdrawn in this currency after this withdrawal
+    pub locked: u64,    // Still locked by the release schedule
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SaleFinalized {
+    pub presale_account: Pubkey,
+    pub tge_time: i64,
+    pub total_sold_sol: u64,
+    pub unsold_tokens: u64, // Vault tokens beyond what buyers and referrers are owed
+    pub unsold_policy: UnsoldTokenPolicy,
+}
+
+#[event]
+pub struct MilestoneVoteCast {
+    pub presale_account: Pubkey,
+    pub voter: Pubkey,
+    pub index: u8,
+    pub approve: bool,
+    pub weight: u64, // Voter's token allocation
+}
+
+#[event]
+pub struct MilestoneResolved {
+    pub presale_account: Pubkey,
+    pub index: u8,
+    pub approved: bool,
+    pub yes_weight: u64,
+    pub no_weight: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MilestoneRefundClaimed {
+    pub presale_account: Pubkey,
+    pub buyer: Pubkey,
+    pub weight: u64,          // Buyer's token allocation
+    pub refund_lamports: u64, // SOL share; stablecoin shares follow the vault transfers
+}
+
 #[event]
 pub struct ClaimEvent {
     pub user: Pubkey,       // User's public key
@@ -789,132 +6910,170 @@ pub struct ClaimEvent {
 }
 
 #[event]
-pub struct ManualPriceOverrideUpdated {
-    pub new_price: Option<u64>, // Updated manual price
-    pub timestamp: i64,         // Time of the update
+pub struct DutchAuctionUpdated {
+    pub presale_account: Pubkey,
+    pub config: Option<DutchAuctionConfig>, // None when switched back to rounds
+    pub timestamp: i64,
 }
 
-impl<'info> Claim<'info> {
-    fn into_transfer_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
-        CpiContext::new(
-            self.token_program.to_account_info(),
-            Transfer {
-                from: self.user_vesting.to_account_info(),
-                to: self.buyer.to_account_info(),
-                authority: self.buyer.to_account_info(),
-            },
-        )
-    }
+#[event]
+pub struct BondingCurveUpdated {
+    pub presale_account: Pubkey,
+    pub config: Option<BondingCurveConfig>, // None when switched back to rounds
+    pub timestamp: i64,
 }
 
-impl<'info> BatchDistributeAirdrops<'info> {
-    pub fn into_transfer_context(
-        &self,
-        destination_account: Account<'info, TokenAccount>,
-    ) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
-        let cpi_accounts = Transfer {
-            from: self.presale_account.to_account_info(),
-            to: destination_account.to_account_info(),
-            authority: self.authority.to_account_info(),
-        };
-        CpiContext::new(self.system_program.to_account_info(), cpi_accounts)
-    }
+#[event]
+pub struct AllocationModeUpdated {
+    pub presale_account: Pubkey,
+    pub allocation_mode: AllocationMode,
+    pub dust_recipient: Pubkey,
+    pub timestamp: i64,
 }
 
-impl<'info> DistributeAirdrop<'info> {
-    pub fn into_transfer_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
-        let cpi_accounts = Transfer {
-            from: self.presale_account.to_account_info(),
-            to: self.recipient_account.to_account_info(),
-            authority: self.authority.to_account_info(),
-        };
-        CpiContext::new(self.system_program.to_account_info(), cpi_accounts)
-    }
+#[event]
+pub struct LotteryCommitted {
+    pub presale_account: Pubkey,
+    pub commitment: [u8; 32],
+    pub timestamp: i64,
 }
 
-pub fn claim(ctx: Context<Claim>) -> Result<()> {
-    let user_vesting = &mut ctx.accounts.user_vesting;
-    let clock = Clock::get()?;
-    let current_time = clock.unix_timestamp;
-
-    // Calculate vested tokens
-    let vested_amount = calculate_vested_amount(
-        user_vesting.total_amount,
-        user_vesting.start_time,
-        ctx.accounts.presale_account.vesting_period,
-        ctx.accounts.presale_account.vesting_interval,
-        current_time,
-    );
+#[event]
+pub struct LotteryDrawRequested {
+    pub presale_account: Pubkey,
+    pub revealer: Pubkey,
+    pub entropy: [u8; 32],
+    pub draw_slot: u64,
+}
+
+#[event]
+pub struct LotterySecretRevealed {
+    pub presale_account: Pubkey,
+    pub secret: [u8; 32],
+    pub timestamp: i64,
+}
 
-    let claimable_amount = vested_amount.saturating_sub(user_vesting.claimed_amount);
-    if claimable_amount > 0 {
-        token::transfer(ctx.accounts.into_transfer_context(), claimable_amount)?;
+#[event]
+pub struct LotteryDrawn {
+    pub presale_account: Pubkey,
+    pub seed: [u8; 32], // Anyone can recompute every ticket's rank from this
+    pub tickets: u32,
+    pub winners: u32,
+}
 
-        // Update claimed amount
-        user_vesting.claimed_amount += claimable_amount;
+#[event]
+pub struct ProRataFinalized {
+    pub presale_account: Pubkey,
+    pub contributed_sol: u64, // SOL-equivalent committed during the window
+    pub accepted_sol: u64,    // SOL-equivalent kept; the rest is refunded
+    pub timestamp: i64,
+}
 
-        // Emit event
-        emit!(ClaimEvent {
-            user: ctx.accounts.buyer.key(),
-            amount: claimable_amount,
-            total_claimed: user_vesting.claimed_amount,
-        });
+#[event]
+pub struct AllocationSettled {
+    pub buyer: Pubkey,
+    pub allocation: u64,        // Tokens the buyer ends up with
+    pub refunded_lamports: u64, // SOL share of the refunded excess
+}
 
-        Ok(())
-    } else {
-        Err(ErrorCode::NoTokensToClaim.into())
-    }
+#[event]
+pub struct ProRataDustSwept {
+    pub presale_account: Pubkey,
+    pub dust_recipient: Pubkey,
+    pub token_dust: u64,
+    pub lamport_dust: u64,
 }
 
-pub fn distribute_airdrops_batch(ctx: Context<BatchDistributeAirdrops>, amount: u64) -> Result<()> {
-    // Iterate through the dynamic accounts in `remaining_accounts`
-    let destination_account = ctx.accounts.user_vesting_account.clone();
-    let transfer_ctx = ctx.accounts.into_transfer_context(destination_account);
-    token::transfer(transfer_ctx, amount)?;
-    
-    for account_info in ctx.remaining_accounts.iter() {
-        // Validate each account as a TokenAccount
-        let recipient: Account<TokenAccount> = Account::try_from(account_info)?;
+#[event]
+pub struct ClearingPriceFinalized {
+    pub presale_account: Pubkey,
+    pub clearing_price: u64, // USD cents
+    pub timestamp: i64,
+}
 
-        // Log the recipient for debugging purposes
-        msg!(
-            "Distributing {} tokens to recipient {}",
-            amount,
-            recipient.key()
-        );
-    }
+#[event]
+pub struct ClearingPriceSettled {
+    pub buyer: Pubkey,
+    pub clearing_price: u64,
+    pub extra_tokens: u64,      // Tokens added to the buyer's vesting
+    pub refunded_lamports: u64, // SOL share of the refunded overpayment
+}
 
-    Ok(())
+#[event]
+pub struct SaleFailed {
+    pub presale_account: Pubkey,
+    pub total_sold_usd: u64, // Raise at the end of the sale, in USD cents
+    pub soft_cap_usd: u64,   // Target it fell short of
+    pub timestamp: i64,
 }
 
-// Helper to calculate vested amount
-pub fn calculate_vested_amount(
-    total_amount: u64,
-    start_time: i64,
-    vesting_period: i64,
-    vesting_interval: i64,
-    current_time: i64,
-) -> u64 {
-    if current_time < start_time {
-        return 0;
-    }
-    let elapsed_time = current_time - start_time;
+#[event]
+pub struct PaymentReclaimed {
+    pub buyer: Pubkey,
+    pub payment_mint: Option<Pubkey>, // None for SOL
+    pub amount: u64,                  // Lamports or payment-mint base units returned
+}
 
-    if vesting_period == 0 || vesting_interval == 0 {
-        return 0;
-    }
+#[event]
+pub struct PriceObserved {
+    pub presale_account: Pubkey,
+    pub price: u64, // SOL/USD in USD cents
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CircuitBreakerUpdated {
+    pub presale_account: Pubkey,
+    pub config: CircuitBreakerConfig,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CircuitBreakerTripped {
+    pub presale_account: Pubkey,
+    pub reason: PauseReason,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OracleConfigUpdated {
+    pub oracle_kind: OracleKind,
+    pub sol_usd_feed: Pubkey,
+    pub max_age: u64,
+    pub max_conf_bps: u16,
+    pub timestamp: i64,
+}
 
-    let vested_intervals = elapsed_time / vesting_interval;
-    let total_intervals = vesting_period / vesting_interval;
+#[event]
+pub struct ManualPriceOverrideUpdated {
+    pub new_price: Option<u64>, // Updated manual price
+    pub timestamp: i64,         // Time of the update
+}
 
-    if total_intervals == 0 {
-        return 0;
+impl<'info> FundVault<'info> {
+    fn into_transfer_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.funder_token_account.to_account_info(),
+                to: self.sale_vault.to_account_info(),
+                authority: self.funder.to_account_info(),
+            },
+        )
     }
+}
 
-    // Calculate the proportion of vested tokens
-    let vested_amount =
-        (total_amount as u128 * vested_intervals as u128 / total_intervals as u128) as u64;
-    vested_amount
+impl<'info> PurchaseWithToken<'info> {
+    fn into_transfer_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.buyer_payment_account.to_account_info(),
+                to: self.payment_vault.to_account_info(),
+                authority: self.buyer.to_account_info(),
+            },
+        )
+    }
 }
 
 #[error_code]
@@ -971,4 +7130,657 @@ pub enum ErrorCode {
     PresalePaused,
     #[msg("Presale timing is Invalid.")]
     InvalidPresaleTiming,
+    #[msg("Sale rounds are invalid.")]
+    InvalidSaleRounds,
+    #[msg("The presale has already started.")]
+    SaleAlreadyStarted,
+    #[msg("Purchase exceeds the per-wallet cap for this round.")]
+    ExceedsRoundWalletCap,
+    #[msg("No allowlist root has been committed.")]
+    AllowlistNotConfigured,
+    #[msg("Allowlist proof is invalid.")]
+    InvalidAllowlistProof,
+    #[msg("Buyer is not on the allowlist for this round.")]
+    NotAllowlisted,
+    #[msg("Purchase exceeds the buyer's allowlist allocation.")]
+    AllowlistAllocationExceeded,
+    #[msg("Buyers cannot refer themselves.")]
+    SelfReferral,
+    #[msg("Referral ledger account is missing or does not match the referrer.")]
+    InvalidReferralLedger,
+    #[msg("The buyer is already bound to a different referrer.")]
+    ReferrerMismatch,
+    #[msg("Maximum number of payment mints already registered.")]
+    PaymentMintLimitReached,
+    #[msg("Payment mint is already registered.")]
+    PaymentMintAlreadyRegistered,
+    #[msg("Payment vault does not match the registered mint.")]
+    InvalidPaymentVault,
+    #[msg("Oracle account does not match the configured price feed.")]
+    OracleFeedMismatch,
+    #[msg("Oracle price is older than the configured maximum age.")]
+    StalePrice,
+    #[msg("Oracle confidence interval is too wide.")]
+    PriceConfidenceTooWide,
+    #[msg("Oracle price exponent is out of range.")]
+    InvalidOracleExponent,
+    #[msg("Oracle returned a non-positive price.")]
+    InvalidOraclePrice,
+    #[msg("Oracle configuration is invalid.")]
+    InvalidOracleConfig,
+    #[msg("No price observations inside the TWAP window.")]
+    TwapUnavailable,
+    #[msg("The sale failed to reach its soft cap.")]
+    SaleFailed,
+    #[msg("The sale reached its soft cap.")]
+    SoftCapReached,
+    #[msg("The sale is not in the required state.")]
+    InvalidSaleState,
+    #[msg("Invalid Dutch auction configuration.")]
+    InvalidDutchAuction,
+    #[msg("Uniform clearing price is not enabled for this sale.")]
+    UniformClearingDisabled,
+    #[msg("The clearing price has not been finalized.")]
+    ClearingPriceNotFinalized,
+    #[msg("Already settled at the clearing price.")]
+    AlreadySettled,
+    #[msg("Invalid bonding curve configuration.")]
+    InvalidBondingCurve,
+    #[msg("Quotes are only available for curve-priced sales.")]
+    QuoteUnavailable,
+    #[msg("The sale allocation has not been finalized.")]
+    AllocationNotFinalized,
+    #[msg("The buyer's allocation has not been settled.")]
+    AllocationNotSettled,
+    #[msg("Invalid lottery configuration.")]
+    InvalidLotteryConfig,
+    #[msg("A lottery ticket must buy exactly the ticket size, once per wallet.")]
+    InvalidLotteryTicket,
+    #[msg("The lottery commitment can no longer be set.")]
+    LotteryCommitmentLocked,
+    #[msg("The admin has not committed a lottery seed.")]
+    LotteryNotCommitted,
+    #[msg("A lottery draw request is still pending.")]
+    LotteryDrawAlreadyRequested,
+    #[msg("The lottery draw is not ready.")]
+    LotteryDrawNotReady,
+    #[msg("The lottery reveal deadline has passed.")]
+    LotteryRevealExpired,
+    #[msg("The revealed secret does not match the commitment.")]
+    InvalidLotteryReveal,
+    #[msg("The draw slot hash is no longer available.")]
+    LotterySlotHashUnavailable,
+    #[msg("A compliance voucher is required for this sale.")]
+    VoucherRequired,
+    #[msg("The compliance voucher has expired.")]
+    VoucherExpired,
+    #[msg("The purchase exceeds the voucher's maximum amount.")]
+    VoucherAmountExceeded,
+    #[msg("The voucher nonce has already been used.")]
+    VoucherNonceUsed,
+    #[msg("Missing or invalid compliance voucher signature.")]
+    InvalidVoucherSignature,
+    #[msg("This action must go through the multisig.")]
+    MultisigRequired,
+    #[msg("This action is executed through its own instruction.")]
+    ActionNotExecutable,
+    #[msg("The proposal does not approve this action.")]
+    ProposalActionMismatch,
+    #[msg("Invalid multisig signer set or threshold.")]
+    InvalidMultisigConfig,
+    #[msg("Signer is not a member of the multisig.")]
+    NotMultisigSigner,
+    #[msg("The proposal has already been executed.")]
+    ProposalAlreadyExecuted,
+    #[msg("The signer set changed after this proposal was made.")]
+    StaleProposal,
+    #[msg("The proposal has not reached the approval threshold.")]
+    ThresholdNotMet,
+    #[msg("Invalid role assignment.")]
+    InvalidRoleAssignment,
+    #[msg("Maximum number of role assignments reached.")]
+    RoleLimitReached,
+    #[msg("This change must go through the timelock queue.")]
+    TimelockRequired,
+    #[msg("The execution time is earlier than the timelock delay allows.")]
+    TimelockTooShort,
+    #[msg("The timelock for this change has not elapsed.")]
+    TimelockNotElapsed,
+    #[msg("Queued change not found.")]
+    QueuedChangeNotFound,
+    #[msg("Too many pending timelocked changes.")]
+    ChangeQueueFull,
+    #[msg("Invalid circuit breaker configuration.")]
+    InvalidCircuitBreakerConfig,
+    #[msg("The circuit breaker has tripped; purchases are paused until it is reset.")]
+    CircuitBreakerTripped,
+    #[msg("Neither the feed staleness nor the price deviation limit is breached.")]
+    CircuitBreakerNotTripped,
+    #[msg("Account cannot be migrated with this presale.")]
+    InvalidMigrationAccount,
+    #[msg("Recipient token account does not match the sale mint or its owner.")]
+    InvalidRecipientAccount,
+    #[msg("Invalid treasury release schedule.")]
+    InvalidReleaseSchedule,
+    #[msg("Amount exceeds the proceeds released so far.")]
+    ProceedsLocked,
+    #[msg("Amount exceeds the withdrawal cap for this period.")]
+    WithdrawalPeriodCapExceeded,
+    #[msg("Invalid milestone configuration.")]
+    InvalidMilestones,
+    #[msg("No such milestone, or an earlier one is unresolved.")]
+    InvalidMilestoneIndex,
+    #[msg("The milestone is not open for voting.")]
+    MilestoneVotingClosed,
+    #[msg("Already voted on this milestone.")]
+    MilestoneAlreadyVoted,
+    #[msg("The milestone vote has not ended.")]
+    MilestoneVoteNotEnded,
+    #[msg("The milestone has already been resolved.")]
+    MilestoneAlreadyResolved,
+    #[msg("No milestone vote has failed.")]
+    MilestoneNotFailed,
+    #[msg("The sale has already been finalized.")]
+    SaleAlreadyFinalized,
+    #[msg("The sale has not been finalized.")]
+    SaleNotFinalized,
+    #[msg("Unlock points must be sorted, sum to 100% and fit the schedule.")]
+    InvalidUnlockSchedule,
+    #[msg("The sale's unlock schedule account is required.")]
+    MissingUnlockSchedule,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every field decodes from zeros, as for an account migrated from an older layout
+    fn zeroed_presale() -> PresaleAccount {
+        let data = vec![0u8; PresaleAccount::INIT_SPACE];
+        PresaleAccount::deserialize(&mut &data[..]).unwrap()
+    }
+
+    fn fully_released_presale() -> PresaleAccount {
+        let mut presale = zeroed_presale();
+        presale.release_schedule.tge_bps = BPS_DENOMINATOR as u16;
+        presale
+    }
+
+    #[test]
+    fn raise_excludes_refunds_and_referral_rewards_owed_in_sol() {
+        let mut presale = fully_released_presale();
+        presale.total_paid_lamports = 10_000;
+        presale.refunded_lamports = 1_000;
+        presale.referral_lamports_owed = 450;
+
+        let status = presale.proceeds_status(None, 0).unwrap();
+        assert_eq!(status.raised, 8_550);
+        assert_eq!(status.unlocked, 8_550);
+        assert_eq!(status.locked, 0);
+    }
+
+    #[test]
+    fn raise_excludes_referral_rewards_owed_in_the_same_mint_only() {
+        let mut presale = fully_released_presale();
+        let mint = Pubkey::new_unique();
+        presale.accepted_mints.push(AcceptedMint {
+            mint,
+            decimals: 6,
+            oracle: None,
+            vault: Pubkey::new_unique(),
+            vault_bump: 255,
+            enabled: true,
+            total_received: 5_000,
+            total_refunded: 500,
+        });
+        presale.referral_owed_by_mint[0] = 225;
+        presale.referral_lamports_owed = 1_000;
+
+        let status = presale.proceeds_status(Some(mint), 0).unwrap();
+        assert_eq!(status.raised, 4_275);
+        assert_eq!(presale.proceeds_status(None, 0).unwrap().raised, 0);
+    }
+
+    fn allowlist_leaf(wallet: &Pubkey, max_allocation: u64) -> [u8; 32] {
+        keccak::hashv(&[wallet.as_ref(), &max_allocation.to_le_bytes()]).0
+    }
+
+    fn hash_pair(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+        if a <= b {
+            keccak::hashv(&[&a, &b]).0
+        } else {
+            keccak::hashv(&[&b, &a]).0
+        }
+    }
+
+    #[test]
+    fn allowlist_proof_verifies_every_leaf_of_a_sorted_pair_tree() {
+        let wallets: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        let leaves: Vec<[u8; 32]> = wallets
+            .iter()
+            .enumerate()
+            .map(|(index, wallet)| allowlist_leaf(wallet, 1_000 * (index as u64 + 1)))
+            .collect();
+        let left = hash_pair(leaves[0], leaves[1]);
+        let right = hash_pair(leaves[2], leaves[3]);
+        let root = hash_pair(left, right);
+
+        // Siblings are passed without left/right flags, in either tree position
+        let proofs = [
+            [leaves[1], right],
+            [leaves[0], right],
+            [leaves[3], left],
+            [leaves[2], left],
+        ];
+        for (index, wallet) in wallets.iter().enumerate() {
+            assert!(verify_allowlist_proof(root, wallet, 1_000 * (index as u64 + 1), &proofs[index]));
+        }
+    }
+
+    #[test]
+    fn allowlist_proof_rejects_a_wrong_allocation_wallet_or_proof() {
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (alice_leaf, bob_leaf) = (allowlist_leaf(&alice, 500), allowlist_leaf(&bob, 700));
+        let root = hash_pair(alice_leaf, bob_leaf);
+
+        assert!(verify_allowlist_proof(root, &alice, 500, &[bob_leaf]));
+        assert!(!verify_allowlist_proof(root, &alice, 501, &[bob_leaf]));
+        assert!(!verify_allowlist_proof(root, &bob, 500, &[bob_leaf]));
+        assert!(!verify_allowlist_proof(root, &alice, 500, &[]));
+        assert!(!verify_allowlist_proof(root, &alice, 500, &[bob_leaf, bob_leaf]));
+    }
+
+    #[test]
+    fn single_entry_allowlist_is_its_own_root() {
+        let wallet = Pubkey::new_unique();
+        let root = allowlist_leaf(&wallet, 42);
+        assert!(verify_allowlist_proof(root, &wallet, 42, &[]));
+    }
+
+    fn twap_presale(price_mode: PriceMode) -> PresaleAccount {
+        let mut presale = zeroed_presale();
+        presale.price_mode = price_mode;
+        presale.twap_window = 160; // 10s minimum spacing between observations
+        presale
+    }
+
+    fn oracle_price(price: u64, manual: bool) -> OraclePrice {
+        OraclePrice { price, manual, ..Default::default() }
+    }
+
+    #[test]
+    fn price_observations_closer_than_the_minimum_spacing_are_dropped() {
+        let mut presale = twap_presale(PriceMode::Twap);
+        presale.record_price_observation(100, 0);
+        presale.record_price_observation(150, 9);
+        presale.record_price_observation(200, 10);
+
+        assert_eq!(presale.observation_count, 2);
+        assert_eq!(presale.observation_index, 2);
+        assert_eq!(presale.price_observations[1].price, 200);
+        // 100 held for 10s, 200 for 10s
+        assert_eq!(presale.twap(20).unwrap(), 150);
+    }
+
+    #[test]
+    fn twap_wraps_around_the_ring_buffer() {
+        let mut presale = twap_presale(PriceMode::Twap);
+        for step in 0..20u64 {
+            presale.record_price_observation(100 * (step + 1), 10 * step as i64);
+        }
+
+        // The first four observations were overwritten
+        assert_eq!(presale.observation_count as usize, MAX_PRICE_OBSERVATIONS);
+        assert_eq!(presale.observation_index, 4);
+        assert_eq!(presale.price_observations[4].timestamp, 40);
+        assert_eq!(presale.price_observations[3].timestamp, 190);
+        // 500..=2000 held for 10s each
+        assert_eq!(presale.twap(200).unwrap(), 1_250);
+    }
+
+    #[test]
+    fn twap_clips_the_oldest_observation_to_the_window() {
+        let mut presale = twap_presale(PriceMode::Twap);
+        for step in 0..20u64 {
+            presale.record_price_observation(100 * (step + 1), 10 * step as i64);
+        }
+
+        // Window [45, 205): 500 for 5s, 600..=1900 for 10s each, 2000 for 15s
+        assert_eq!(presale.twap(205).unwrap(), 207_500 / 160);
+    }
+
+    #[test]
+    fn twap_needs_an_observation() {
+        let mut presale = twap_presale(PriceMode::Twap);
+        assert!(presale.twap(0).is_err());
+
+        presale.record_price_observation(100, 50);
+        assert_eq!(presale.twap(50).unwrap(), 100);
+        assert_eq!(presale.twap(80).unwrap(), 100);
+    }
+
+    #[test]
+    fn manual_prices_never_feed_the_twap() {
+        let mut presale = twap_presale(PriceMode::Spot);
+        assert_eq!(presale.purchase_sol_price(oracle_price(90, true), 0).unwrap(), 90);
+        assert_eq!(presale.observation_count, 0);
+
+        presale.price_mode = PriceMode::Twap;
+        assert!(presale.purchase_sol_price(oracle_price(90, true), 0).is_err());
+        assert_eq!(presale.purchase_sol_price(oracle_price(100, false), 0).unwrap(), 100);
+        assert_eq!(presale.purchase_sol_price(oracle_price(10, true), 30).unwrap(), 100);
+        assert_eq!(presale.observation_count, 1);
+    }
+
+    #[test]
+    fn worse_of_charges_the_lower_of_spot_and_twap() {
+        let mut presale = twap_presale(PriceMode::WorseOf);
+        assert_eq!(presale.purchase_sol_price(oracle_price(100, false), 0).unwrap(), 100);
+        // Spot below the average
+        assert_eq!(presale.purchase_sol_price(oracle_price(80, false), 10).unwrap(), 80);
+        // Spot above the average of 100 for 10s and 80 for 10s
+        assert_eq!(presale.purchase_sol_price(oracle_price(120, false), 20).unwrap(), 90);
+    }
+
+    fn dutch_presale(start_price: u64, floor_price: u64, curve: AuctionCurve, step_duration: i64) -> PresaleAccount {
+        let mut presale = zeroed_presale();
+        presale.presale_start = 1_000;
+        presale.presale_end = 1_100;
+        presale.dutch_auction = DutchAuctionConfig {
+            start_price,
+            floor_price,
+            curve,
+            step_duration,
+            clearing_settlement: None,
+        };
+        presale
+    }
+
+    #[test]
+    fn linear_dutch_price_is_held_outside_the_sale_window() {
+        let presale = dutch_presale(1_000, 400, AuctionCurve::Linear, 0);
+        assert_eq!(presale.dutch_auction_price(0), 1_000);
+        assert_eq!(presale.dutch_auction_price(1_000), 1_000);
+        assert_eq!(presale.dutch_auction_price(1_100), 400);
+        assert_eq!(presale.dutch_auction_price(5_000), 400);
+    }
+
+    #[test]
+    fn linear_dutch_price_falls_every_second_rounding_up() {
+        let presale = dutch_presale(1_000, 400, AuctionCurve::Linear, 0);
+        assert_eq!(presale.dutch_auction_price(1_025), 850);
+        // 600 * 33 / 100 = 198 off the start price, the fraction stays with the buyer's price
+        assert_eq!(presale.dutch_auction_price(1_033), 802);
+        assert_eq!(presale.dutch_auction_price(1_099), 406);
+    }
+
+    #[test]
+    fn stepped_dutch_price_drops_on_step_boundaries() {
+        // Three whole 30s steps fit in the 100s window
+        let presale = dutch_presale(1_000, 400, AuctionCurve::Stepped, 30);
+        assert_eq!(presale.dutch_auction_price(1_029), 1_000);
+        assert_eq!(presale.dutch_auction_price(1_030), 800);
+        assert_eq!(presale.dutch_auction_price(1_059), 800);
+        assert_eq!(presale.dutch_auction_price(1_060), 600);
+        // The last step reaches the floor before presale_end, and it holds there
+        assert_eq!(presale.dutch_auction_price(1_090), 400);
+        assert_eq!(presale.dutch_auction_price(1_100), 400);
+    }
+
+    #[test]
+    fn stepped_dutch_price_rounds_each_step_up() {
+        let presale = dutch_presale(1_000, 0, AuctionCurve::Stepped, 30);
+        assert_eq!(presale.dutch_auction_price(1_030), 667);
+        assert_eq!(presale.dutch_auction_price(1_060), 334);
+        assert_eq!(presale.dutch_auction_price(1_090), 0);
+    }
+
+    #[test]
+    fn single_step_dutch_auction_drops_at_the_end() {
+        let presale = dutch_presale(1_000, 400, AuctionCurve::Stepped, 100);
+        assert_eq!(presale.dutch_auction_price(1_099), 1_000);
+        assert_eq!(presale.dutch_auction_price(1_100), 400);
+    }
+
+    fn release_schedule(release_duration: i64, release_step: i64) -> ReleaseSchedule {
+        ReleaseSchedule {
+            tge_time: 1_000,
+            tge_bps: 1_000,
+            release_duration,
+            release_step,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn linear_release_unlocks_the_tge_share_then_the_rest_per_second() {
+        let schedule = release_schedule(900, 0);
+        assert_eq!(schedule.released_bps(999), 0);
+        assert_eq!(schedule.released_bps(1_000), 1_000);
+        assert_eq!(schedule.released_bps(1_450), 5_500);
+        assert_eq!(schedule.released_bps(1_899), 9_990);
+        assert_eq!(schedule.released_bps(1_900), BPS_DENOMINATOR);
+        assert_eq!(schedule.released_bps(50_000), BPS_DENOMINATOR);
+    }
+
+    #[test]
+    fn stepped_release_unlocks_on_step_boundaries() {
+        let schedule = release_schedule(900, 300);
+        assert_eq!(schedule.released_bps(1_299), 1_000);
+        assert_eq!(schedule.released_bps(1_300), 4_000);
+        assert_eq!(schedule.released_bps(1_899), 7_000);
+        assert_eq!(schedule.released_bps(1_900), BPS_DENOMINATOR);
+    }
+
+    #[test]
+    fn stepped_release_unlocks_the_remainder_at_the_end() {
+        // The last 100s are shorter than a step
+        let schedule = release_schedule(1_000, 300);
+        assert_eq!(schedule.released_bps(1_999), 9_100);
+        assert_eq!(schedule.released_bps(2_000), BPS_DENOMINATOR);
+    }
+
+    #[test]
+    fn release_without_a_duration_stops_at_the_tge_share() {
+        assert_eq!(release_schedule(0, 0).released_bps(50_000), 1_000);
+        assert_eq!(ReleaseSchedule::default().released_bps(50_000), 0);
+    }
+
+    #[test]
+    fn withdrawal_periods_start_at_tge_and_roll_on_their_boundaries() {
+        let mut schedule = release_schedule(900, 0);
+        assert_eq!(schedule.period_start(1_250), 1_000);
+
+        schedule.period_length = 100;
+        assert_eq!(schedule.period_start(500), 1_000);
+        assert_eq!(schedule.period_start(1_000), 1_000);
+        assert_eq!(schedule.period_start(1_099), 1_000);
+        assert_eq!(schedule.period_start(1_100), 1_100);
+        assert_eq!(schedule.period_start(1_250), 1_200);
+    }
+
+    fn linear_curve(slope_numerator: u64, slope_denominator: u64) -> BondingCurveConfig {
+        BondingCurveConfig {
+            kind: CurveKind::Linear,
+            base_price: 100,
+            slope_numerator,
+            slope_denominator,
+            ..Default::default()
+        }
+    }
+
+    fn exponential_curve(growth_bps: u16, step_size: u64) -> BondingCurveConfig {
+        BondingCurveConfig {
+            kind: CurveKind::Exponential,
+            base_price: 100,
+            growth_bps,
+            step_size,
+            ..Default::default()
+        }
+    }
+
+    fn quoted_sum(curve: &BondingCurveConfig, sold: u64, amount: u64) -> u64 {
+        (sold..sold + amount).map(|index| curve.price_at(index).unwrap()).sum()
+    }
+
+    // The same sum before any per-token rounding, rounded up once
+    fn unrounded_sum(curve: &BondingCurveConfig, sold: u64, amount: u64) -> u64 {
+        let (numerator, denominator) = match curve.kind {
+            CurveKind::Linear => (
+                (sold..sold + amount)
+                    .map(|index| {
+                        curve.base_price as u128 * curve.slope_denominator as u128
+                            + curve.slope_numerator as u128 * index as u128
+                    })
+                    .sum::<u128>(),
+                curve.slope_denominator as u128,
+            ),
+            CurveKind::Exponential => (
+                (sold..sold + amount)
+                    .map(|index| {
+                        curve.base_price as u128 * curve.growth_factor(index / curve.step_size).unwrap()
+                    })
+                    .sum::<u128>(),
+                CURVE_FIXED_POINT,
+            ),
+        };
+        div_ceil(numerator, denominator) as u64
+    }
+
+    #[test]
+    fn curve_cost_of_nothing_is_zero() {
+        assert_eq!(linear_curve(3, 1).cost(0, 0).unwrap(), 0);
+        assert_eq!(linear_curve(3, 1).cost(500, 0).unwrap(), 0);
+        assert_eq!(exponential_curve(500, 10).cost(0, 0).unwrap(), 0);
+    }
+
+    #[test]
+    fn linear_cost_matches_the_per_token_sum_for_whole_cent_slopes() {
+        let curve = linear_curve(3, 1);
+        for sold in 0..40 {
+            for amount in 0..40 {
+                assert_eq!(curve.cost(sold, amount).unwrap(), quoted_sum(&curve, sold, amount));
+            }
+        }
+    }
+
+    #[test]
+    fn linear_cost_rounds_once_for_fractional_slopes() {
+        let curve = linear_curve(7, 3);
+        for sold in 0..40 {
+            for amount in 1..40 {
+                let cost = curve.cost(sold, amount).unwrap();
+                assert_eq!(cost, unrounded_sum(&curve, sold, amount));
+                assert!(cost <= quoted_sum(&curve, sold, amount));
+            }
+        }
+    }
+
+    #[test]
+    fn exponential_price_steps_at_step_boundaries() {
+        let curve = exponential_curve(500, 10);
+        assert_eq!(curve.price_at(0).unwrap(), 100);
+        assert_eq!(curve.price_at(9).unwrap(), 100);
+        assert_eq!(curve.price_at(10).unwrap(), 105);
+        assert_eq!(curve.price_at(19).unwrap(), 105);
+        // 100 * 1.05^2 = 110.25, rounded up
+        assert_eq!(curve.price_at(20).unwrap(), 111);
+    }
+
+    #[test]
+    fn exponential_cost_within_and_across_step_boundaries() {
+        let curve = exponential_curve(500, 10);
+        // Inside one step, and filling it exactly up to the boundary
+        assert_eq!(curve.cost(3, 5).unwrap(), 500);
+        assert_eq!(curve.cost(0, 10).unwrap(), 1_000);
+        // Starting on a boundary, and straddling one
+        assert_eq!(curve.cost(10, 10).unwrap(), 1_050);
+        assert_eq!(curve.cost(8, 4).unwrap(), 2 * 100 + 2 * 105);
+        // Partial head, one whole step in the middle, partial tail
+        assert_eq!(curve.cost(5, 20).unwrap(), 5 * 100 + 10 * 105 + 5 * 111);
+    }
+
+    #[test]
+    fn exponential_cost_is_bounded_by_the_brute_force_sums() {
+        for curve in [exponential_curve(500, 10), exponential_curve(125, 7), exponential_curve(10_000, 1)] {
+            for sold in (0..120).step_by(3) {
+                for amount in (1..120).step_by(7) {
+                    let cost = curve.cost(sold, amount).unwrap();
+                    assert!(cost >= unrounded_sum(&curve, sold, amount));
+                    assert!(cost <= quoted_sum(&curve, sold, amount));
+                }
+            }
+        }
+        // Doubling per token is exact in fixed point, so nothing is lost to rounding
+        let doubling = exponential_curve(10_000, 1);
+        assert_eq!(doubling.cost(2, 4).unwrap(), 400 + 800 + 1_600 + 3_200);
+    }
+
+    #[test]
+    fn referral_claw_back_takes_the_refunded_share_but_not_claimed_rewards() {
+        // A quarter of the buyer's 400 credit goes, out of the referrer's unclaimed 900
+        let (mut credited, mut reward, mut owed) = (400, 1_000, 5_000);
+        let taken = claw_back_share(&mut credited, &mut reward, 100, &mut owed, 1, 4).unwrap();
+        assert_eq!((taken, credited, reward, owed), (100, 300, 900, 4_900));
+
+        // Only 50 is still unclaimed, so that is all that can come back
+        let (mut credited, mut reward, mut owed) = (400, 1_000, 5_000);
+        let taken = claw_back_share(&mut credited, &mut reward, 950, &mut owed, 1, 1).unwrap();
+        assert_eq!((taken, credited, reward, owed), (50, 0, 950, 4_950));
+    }
+
+    #[test]
+    fn soft_cap_is_judged_on_the_raise_at_the_close() {
+        let mut presale = zeroed_presale();
+        presale.soft_cap_usd = 1_000;
+        presale.total_sold_usd = 1_200;
+        assert!(presale.soft_cap_met());
+
+        // Post-sale refunds lower the live total, not the frozen one
+        presale.raise_at_close_usd = Some(1_200);
+        presale.total_sold_usd = 400;
+        assert!(presale.soft_cap_met());
+        assert_eq!(presale.closing_raise_usd(), 1_200);
+    }
+
+    #[test]
+    fn lottery_soft_cap_counts_only_winning_tickets() {
+        let mut presale = zeroed_presale();
+        presale.allocation_mode = AllocationMode::Lottery;
+        presale.lottery.winner_count = 10;
+        presale.soft_cap_usd = 1_000;
+        presale.contributor_count = 40;
+        presale.total_sold_usd = 40 * 150;
+        assert!(presale.soft_cap_met());
+
+        presale.lottery.winner_count = 6;
+        assert!(!presale.soft_cap_met());
+
+        // Fewer tickets than winners: every ticket wins
+        presale.contributor_count = 5;
+        presale.total_sold_usd = 5 * 150;
+        assert!(!presale.soft_cap_met());
+        presale.total_sold_usd = 5 * 200;
+        assert!(presale.soft_cap_met());
+    }
+
+    #[test]
+    fn unrevealed_draw_request_dies_at_its_slot() {
+        let mut presale = zeroed_presale();
+        assert!(!presale.lottery_draw_live(100));
+
+        presale.lottery_draw_slot = 110;
+        assert!(presale.lottery_draw_live(109));
+        assert!(!presale.lottery_draw_live(110));
+    }
+
+    #[test]
+    fn revealed_draw_request_lives_while_its_slot_hash_is_kept() {
+        let mut presale = zeroed_presale();
+        presale.lottery_draw_slot = 110;
+        presale.lottery_secret = Some([7; 32]);
+        assert!(presale.lottery_draw_live(110));
+        assert!(presale.lottery_draw_live(110 + SLOT_HASHES_DEPTH));
+        assert!(!presale.lottery_draw_live(111 + SLOT_HASHES_DEPTH));
+    }
 }
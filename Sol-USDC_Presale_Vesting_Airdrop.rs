@@ -1,11 +1,15 @@
 use anchor_lang::prelude::*;
 use pyth_sdk_solana::{load_price_feed_from_account_info, PriceFeed};
 use pyth_sol_sdk::price_update::PriceUpdateV2;
-use arrayref::array_ref;
 use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
 
+mod vesting;
+use vesting::{VestingMode, VestingSchedule};
+
 declare_id!("13WjtSt6dp9qQFrvcx1ncD2gHSyhNMAqwEqwQkSgpmya");
 
+pub const USDC_DECIMALS: i32 = 6; // Rates are expressed in USDC base units per SOL
+
 #[program]
 pub mod fam_presale_contract {
     use super::*;
@@ -22,7 +26,10 @@ pub mod fam_presale_contract {
         vesting_period: i64,
         vesting_interval: i64,
         airdrop_percentages: Vec<u64>, // Accept airdrop percentages as input
-        max_airdrop_elements: u8       // Accept maximum airdrop elements as input
+        max_airdrop_elements: u8,      // Accept maximum airdrop elements as input
+        sol_to_usdc_feed: Pubkey,      // Pyth SOL/USD feed every purchase is bound to
+        oracle_max_age: u64,           // Oldest accepted SOL/USD price, in seconds
+        oracle_max_conf_bps: u16       // Widest accepted confidence interval, in bps of price
     ) -> ProgramResult {
         // Cap the size of the airdrop_percentages vector (e.g., max 12 elements)
         if airdrop_percentages.len() > max_airdrop_elements.into() {
@@ -32,6 +39,7 @@ pub mod fam_presale_contract {
         if vesting_period == 0 || vesting_interval == 0 {
             return Err(ErrorCode::InvalidVestingParameters.into());
         }
+        validate_oracle_config(oracle_max_age, oracle_max_conf_bps)?;
     
         let presale_account = &mut ctx.accounts.presale_account;
         presale_account.presale_start = presale_start;
@@ -43,6 +51,27 @@ pub mod fam_presale_contract {
         presale_account.vesting_period = vesting_period;
         presale_account.vesting_interval = vesting_interval;
         presale_account.airdrop_percentages = airdrop_percentages;
+        presale_account.sol_to_usdc_feed = sol_to_usdc_feed;
+        presale_account.version = PresaleAccount::VERSION;
+        presale_account.oracle_max_age = oracle_max_age;
+        presale_account.oracle_max_conf_bps = oracle_max_conf_bps;
+
+        Ok(())
+    }
+
+    // Update the staleness and confidence limits applied to the SOL/USD feed
+    pub fn update_oracle_config(
+        ctx: Context<UpdatePresaleParams>,
+        new_max_age: Option<u64>,
+        new_max_conf_bps: Option<u16>,
+    ) -> ProgramResult {
+        let presale_account = &mut ctx.accounts.presale_account;
+        let max_age = new_max_age.unwrap_or(presale_account.oracle_max_age);
+        let max_conf_bps = new_max_conf_bps.unwrap_or(presale_account.oracle_max_conf_bps);
+        validate_oracle_config(max_age, max_conf_bps)?;
+
+        presale_account.oracle_max_age = max_age;
+        presale_account.oracle_max_conf_bps = max_conf_bps;
 
         Ok(())
     }
@@ -84,6 +113,19 @@ pub mod fam_presale_contract {
         pub start_time: i64,             // Presale end time
         pub airdrops_completed: u8,      // Number of airdrops already distributed
         pub total_purchased_sol: u64,    // Total SOL equivalent purchased by this user
+        pub version: u8,                 // Layout version; new fields go before `reserved`
+        pub reserved: [u8; 32],          // Zeroed space for future fields
+    }
+
+    impl UserVesting {
+        pub const VERSION: u8 = 1;
+    }
+
+    // How a buyer pays for a purchase
+    #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+    pub enum PaymentMethod {
+        SOL,  // Lamports, converted at the SOL/USD feed price
+        USDC, // USDC base units, from the buyer's token account
     }
 
     // Define the PurchaseEvent at the top of your contract
@@ -105,14 +147,8 @@ pub mod fam_presale_contract {
         let presale_account = &mut ctx.accounts.presale_account;
         let user_vesting = &mut ctx.accounts.user_vesting;
     
-        // Fetch current timestamp and recent slot hash for "randomness"
+        // Fetch current timestamp
         let clock = Clock::get()?;
-        let recent_slothashes = &ctx.accounts.recent_slothashes;
-        let data = recent_slothashes.data.borrow();
-        let most_recent = array_ref![data, 12, 8];
-    
-        // Generate a pseudo-random seed using the slot hash and timestamp
-        let seed = u64::from_le_bytes(*most_recent).saturating_sub(clock.unix_timestamp as u64);
     
         // Ensure the presale is active
         if !(clock.unix_timestamp >= presale_account.presale_start && clock.unix_timestamp <= presale_account.presale_end) {
@@ -120,9 +156,15 @@ pub mod fam_presale_contract {
         }
     
         // Use the Pyth SDK to fetch and validate the SOL/USDC conversion rate
-        let price_feed = load_price_feed_from_account_info(&ctx.accounts.sol_to_usdc_feed)?;
-        let price_data = price_feed.get_current_price().ok_or(ErrorCode::PriceFeedUnavailable)?;
-        let sol_to_usdc_rate = price_data.price as u64;
+        validate_oracle_feed(&ctx.accounts.sol_to_usdc_feed)?;
+        if ctx.accounts.sol_to_usdc_feed.key() != presale_account.sol_to_usdc_feed {
+            return Err(ErrorCode::OracleFeedMismatch.into());
+        }
+        let sol_to_usdc_rate = get_sol_to_usdc_rate(
+            &ctx.accounts.sol_to_usdc_feed,
+            &presale_account.oracle_config(),
+            clock.unix_timestamp,
+        )?;
     
         // Calculate the discounted token price
         let discounted_price = presale_account
@@ -213,6 +255,7 @@ pub mod fam_presale_contract {
         }
     
         // Update user vesting and presale totals
+        user_vesting.version = UserVesting::VERSION;
         user_vesting.total_amount = user_vesting
             .total_amount
             .checked_add(amount)
@@ -257,10 +300,47 @@ pub mod fam_presale_contract {
         Ok(())
     }
     
-    fn get_sol_to_usdc_rate(price_feed: &AccountInfo) -> Result<u64, ProgramError> {
-        let price_data = pyth_client::load_price_feed_from_account(price_feed)?;
-        let price = price_data.get_current_price().ok_or(ErrorCode::PriceFeedUnavailable)?;
-        Ok(price.price as u64)
+    fn validate_oracle_config(max_age: u64, max_conf_bps: u16) -> ProgramResult {
+        if max_age == 0 || max_conf_bps == 0 || max_conf_bps > 10_000 {
+            return Err(ErrorCode::InvalidOracleConfig.into());
+        }
+        Ok(())
+    }
+
+    // Returns USDC base units per SOL, rejecting stale, low-confidence or malformed prints
+    fn get_sol_to_usdc_rate(
+        price_feed: &AccountInfo,
+        oracle_config: &OracleConfig,
+        current_time: i64,
+    ) -> Result<u64, ProgramError> {
+        let feed = load_price_feed_from_account_info(price_feed)
+            .map_err(|_| ErrorCode::PriceFeedUnavailable)?;
+        let price = feed
+            .get_price_no_older_than(current_time, oracle_config.max_age)
+            .ok_or(ErrorCode::StalePrice)?;
+        if price.price <= 0 {
+            return Err(ErrorCode::InvalidOraclePrice.into());
+        }
+        // Price and confidence share the feed exponent, so compare them before any rescaling
+        if (price.conf as u128) * 10_000 > (price.price as u128) * oracle_config.max_conf_bps as u128 {
+            return Err(ErrorCode::PriceConfidenceTooWide.into());
+        }
+
+        // Rescale from the feed exponent to USDC decimals
+        let shift = price.expo + USDC_DECIMALS;
+        if !(-18..=18).contains(&shift) {
+            return Err(ErrorCode::InvalidOracleExponent.into());
+        }
+        let scale = 10u128.pow(shift.unsigned_abs());
+        let rate = if shift >= 0 {
+            (price.price as u128).checked_mul(scale).ok_or(ErrorCode::MathOverflow)?
+        } else {
+            price.price as u128 / scale
+        };
+        if rate == 0 || rate > u64::MAX as u128 {
+            return Err(ErrorCode::InvalidOraclePrice.into());
+        }
+        Ok(rate as u64)
     }
 
     // Batch airdrop distribution to save compute units
@@ -325,11 +405,9 @@ pub mod fam_presale_contract {
         let current_time = clock.unix_timestamp;
 
         // Calculate vested tokens
-        let vested_amount = calculate_vested_amount(
+        let vested_amount = ctx.accounts.presale_account.vested_amount(
             user_vesting.total_amount,
             user_vesting.start_time,
-            ctx.accounts.presale_account.vesting_period,
-            ctx.accounts.presale_account.vesting_interval,
             current_time,
         );
 
@@ -356,36 +434,14 @@ pub mod fam_presale_contract {
         }
     }
 
-    // Helper to calculate vested amount
-    fn calculate_vested_amount(
-        total_amount: u64,
-        start_time: i64,
-        vesting_period: i64,
-        vesting_interval: i64,
-        current_time: i64,
-    ) -> u64 {
-        if current_time < start_time {
-            return 0;
-        }
-        let elapsed_time = current_time - start_time;
-        let vested_intervals = elapsed_time / vesting_interval;
-        let total_intervals = vesting_period / vesting_interval;
-
-        // Calculate the proportion of vested tokens
-        let vested_amount = (total_amount as u128 * vested_intervals as u128 / total_intervals as u128) as u64;
-        vested_amount
-    }
-
     pub fn calculate_claimable(ctx: Context<CalculateClaimable>) -> Result<u64> {
         let user_vesting = &ctx.accounts.user_vesting;
         let clock = Clock::get()?;
         let current_time = clock.unix_timestamp;
 
-        let vested_amount = calculate_vested_amount(
+        let vested_amount = ctx.accounts.presale_account.vested_amount(
             user_vesting.total_amount,
             user_vesting.start_time,
-            ctx.accounts.presale_account.vesting_period,
-            ctx.accounts.presale_account.vesting_interval,
             current_time,
         );
 
@@ -420,6 +476,41 @@ pub mod fam_presale_contract {
         pub max_buy_amount_sol: u64,     // Maximum SOL amount per user
         pub hard_cap_sol: u64,           // Maximum SOL equivalent for the entire presale
         pub total_sold_sol_equivalent: u64, // Track total SOL-equivalent sales
+        pub version: u8,                 // Layout version; new fields go before `reserved`
+        pub oracle_max_age: u64,         // Oldest accepted SOL/USD price, in seconds
+        pub oracle_max_conf_bps: u16,    // Widest accepted confidence interval, in bps of price
+        pub reserved: [u8; 54],          // Zeroed space for future fields
+    }
+
+    // Staleness and confidence limits for the SOL/USD feed
+    #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+    pub struct OracleConfig {
+        pub max_age: u64,      // Seconds
+        pub max_conf_bps: u16, // Confidence interval as bps of price
+    }
+
+    impl PresaleAccount {
+        pub const VERSION: u8 = 2;
+
+        pub fn oracle_config(&self) -> OracleConfig {
+            OracleConfig {
+                max_age: self.oracle_max_age,
+                max_conf_bps: self.oracle_max_conf_bps,
+            }
+        }
+
+        // Stepped vesting from the buyer's start time, via the shared vesting module
+        pub fn vested_amount(&self, total_amount: u64, start_time: i64, current_time: i64) -> u64 {
+            VestingSchedule {
+                start: start_time,
+                cliff: self.cliff_period,
+                duration: self.vesting_period,
+                interval: self.vesting_interval,
+                tge_bps: 0,
+                points: &[],
+            }
+            .vested(VestingMode::Stepped, total_amount, current_time)
+        }
     }
 
     impl<'info> Claim<'info> {
@@ -553,9 +644,6 @@ pub mod fam_presale_contract {
         /// CHECK: Oracle price feed account for SOL/USDC rate
         #[account(mut)]
         pub sol_to_usdc_feed: AccountInfo<'info>,
-        /// CHECK: Recent slothashes for randomness
-        #[account(mut)]
-        pub recent_slothashes: AccountInfo<'info>,
     }
 
     pub fn distribute_monthly_airdrop(ctx: Context<DistributeAirdrop>) -> ProgramResult {
@@ -640,5 +728,17 @@ pub mod fam_presale_contract {
         InvalidDiscountPercentage,
         #[msg("Invalid price. Price must be greater than zero.")]
         InvalidPrice,
+        #[msg("Oracle account does not match the configured price feed.")]
+        OracleFeedMismatch,
+        #[msg("Oracle price is older than the configured maximum age.")]
+        StalePrice,
+        #[msg("Oracle confidence interval is too wide.")]
+        PriceConfidenceTooWide,
+        #[msg("Oracle price exponent is out of range.")]
+        InvalidOracleExponent,
+        #[msg("Oracle returned a non-positive price.")]
+        InvalidOraclePrice,
+        #[msg("Oracle configuration is invalid.")]
+        InvalidOracleConfig,
     }
 }
@@ -2,17 +2,26 @@ use pyth_sdk::PriceFeed;
 use pyth_sdk_solana::load_price_feed_from_account_info;
 use arrayref::array_ref;
 use anchor_lang::prelude::*;
+use anchor_lang::system_program;
 use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
 
+mod migration;
+mod vesting;
+use migration::{migrate_account, write_account};
+use vesting::{VestingMode, VestingSchedule};
+
 declare_id!("BU9M4GMAjMCLiwd7opAqmZz12VtqHE3YRjbkx5MkTWq2");
 
+pub const MAX_AIRDROP_PERIODS: usize = 12; // Upper bound on airdrop_percentages entries
+
 #[program]
 pub mod fam_presale_contract {
     use super::*;
 
-    // Initialize presale and vesting parameters
+    // Create the presale PDA and its sale-token vault, with vesting parameters and caps
     pub fn initialize(
         ctx: Context<Initialize>,
+        sale_id: u64,                  // Distinguishes sales of the same mint by the same authority
         presale_start: i64,
         presale_end: i64,
         public_sale_start: i64,
@@ -22,10 +31,15 @@ pub mod fam_presale_contract {
         vesting_period: i64,
         vesting_interval: i64,
         airdrop_percentages: Vec<u64>, // Accept airdrop percentages as input
-        max_airdrop_elements: u8       // Accept maximum airdrop elements as input
+        max_airdrop_elements: u8,      // Accept maximum airdrop elements as input
+        min_buy_amount_sol: u64,       // Minimum SOL amount per purchase
+        max_buy_amount_sol: u64,       // Maximum SOL amount per user
+        hard_cap_sol: u64,             // Maximum SOL for the entire presale
     ) -> ProgramResult {
         // Cap the size of the airdrop_percentages vector (e.g., max 12 elements)
-        if airdrop_percentages.len() > max_airdrop_elements.into() {
+        if airdrop_percentages.len() > max_airdrop_elements.into()
+            || airdrop_percentages.len() > MAX_AIRDROP_PERIODS
+        {
             return Err(ErrorCode::AirdropConfigurationError.into());
         }
         let total_percentage: u64 = airdrop_percentages.iter().sum();
@@ -53,6 +67,13 @@ pub mod fam_presale_contract {
         if airdrop_percentages.iter().any(|&x| x == 0) {
             return Err(ErrorCode::AirdropConfigurationError.into());
         }
+        // Caps must nest: min buy <= max buy <= hard cap
+        if hard_cap_sol == 0
+            || min_buy_amount_sol > max_buy_amount_sol
+            || max_buy_amount_sol > hard_cap_sol
+        {
+            return Err(ErrorCode::InvalidParameterValue.into());
+        }
     
         let presale_account = &mut ctx.accounts.presale_account;
             presale_account.presale_start = presale_start;
@@ -63,7 +84,38 @@ pub mod fam_presale_contract {
             presale_account.cliff_period = cliff_period;
             presale_account.vesting_period = vesting_period;
             presale_account.vesting_interval = vesting_interval;
-            presale_account.airdrop_percentages = airdrop_percentages;
+            presale_account.airdrop_percentages = airdrop_percentages
+                .iter()
+                .map(|&x| x as u8)
+                .collect();
+            presale_account.total_airdrop_periods = airdrop_percentages.len() as u8;
+            presale_account.min_buy_amount_sol = min_buy_amount_sol;
+            presale_account.max_buy_amount_sol = max_buy_amount_sol;
+            presale_account.hard_cap_sol = hard_cap_sol;
+            presale_account.authority = ctx.accounts.authority.key(); // The initializer is the admin
+            presale_account.sale_mint = ctx.accounts.sale_mint.key();
+            presale_account.sale_vault = ctx.accounts.sale_vault.key();
+            presale_account.sale_vault_bump = *ctx.bumps.get("sale_vault").unwrap();
+            presale_account.sale_id = sale_id;
+            presale_account.bump = *ctx.bumps.get("presale_account").unwrap();
+            presale_account.treasury_bump = *ctx.bumps.get("treasury").unwrap();
+            presale_account.version = PresaleAccount::VERSION;
+
+        // Seed the treasury with its rent-exempt reserve so refunds never strand it
+        let reserve = Rent::get()?.minimum_balance(0);
+        let treasury_balance = ctx.accounts.treasury.lamports();
+        if treasury_balance < reserve {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.authority.to_account_info(),
+                        to: ctx.accounts.treasury.to_account_info(),
+                    },
+                ),
+                reserve - treasury_balance,
+            )?;
+        }
 
         Ok(())
     }
@@ -110,12 +162,22 @@ pub mod fam_presale_contract {
     }    
 
     #[account]
+    #[derive(InitSpace)]
     pub struct UserVesting {
         pub total_amount: u64,           // Total tokens purchased
         pub claimed_amount: u64,         // Tokens already claimed
         pub start_time: i64,             // Presale end time
         pub airdrops_completed: u8,      // Number of airdrops already distributed
         pub total_purchased_sol: u64,    // Total SOL equivalent purchased by this user
+        pub owner: Pubkey,               // Buyer this record belongs to, set on first purchase
+        pub presale_account: Pubkey,     // Presale this record belongs to
+        pub bump: u8,                    // Seeds are [b"vesting", presale, buyer]
+        pub version: u8,                 // Layout version; new fields go before `reserved`
+        pub reserved: [u8; 32],          // Zeroed space for future fields
+    }
+
+    impl UserVesting {
+        pub const VERSION: u8 = 1;
     }
 
     // Define the PurchaseEvent at the top of your contract
@@ -193,6 +255,13 @@ pub mod fam_presale_contract {
         }
     
         // --- STATE UPDATES ---
+        // Bind a freshly created record to its buyer and sale
+        if user_vesting.owner == Pubkey::default() {
+            user_vesting.owner = ctx.accounts.buyer.key();
+            user_vesting.presale_account = presale_account.key();
+            user_vesting.bump = *ctx.bumps.get("user_vesting").unwrap();
+            user_vesting.version = UserVesting::VERSION;
+        }
         presale_account.total_sold_sol = presale_account
             .total_sold_sol
             .checked_add(total_cost_in_sol)
@@ -225,9 +294,16 @@ pub mod fam_presale_contract {
         }
     
         // --- EXTERNAL CALL ---
-        let program_pda = ctx.accounts.presale_account.to_account_info().key;
-        **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? -= total_cost_in_sol;
-        **program_pda.try_borrow_mut_lamports()? += total_cost_in_sol;
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.buyer.to_account_info(),
+                    to: ctx.accounts.treasury.to_account_info(),
+                },
+            ),
+            total_cost_in_sol,
+        )?;
     
         // Emit event
         emit!(PurchaseEvent {
@@ -281,6 +357,7 @@ pub mod fam_presale_contract {
         const MAX_BATCH_SIZE: usize = 50; // Set a limit for batch size
     
         let presale_account = &ctx.accounts.presale_account;
+        let presale_key = presale_account.key();
         let clock = Clock::get()?;
         let current_time = clock.unix_timestamp;
     
@@ -296,8 +373,10 @@ pub mod fam_presale_contract {
     
         // Iterate over users and process airdrops
         for user in users.iter() {
-            // Validate that the user_vesting_index is within bounds
-            if user.user_vesting_index >= ctx.remaining_accounts.len() {
+            // Validate that both indexes are within bounds
+            if user.user_vesting_index >= ctx.remaining_accounts.len()
+                || user.recipient_index >= ctx.remaining_accounts.len()
+            {
                 return Err(ErrorCode::InvalidUserAccountIndex.into());
             }
     
@@ -305,11 +384,31 @@ pub mod fam_presale_contract {
             let user_vesting_account = &mut Account::<UserVesting>::try_from(
                 &ctx.remaining_accounts[user.user_vesting_index],
             )?;
+            if user_vesting_account.presale_account != presale_key {
+                return Err(ErrorCode::UnauthorizedAccess.into());
+            }
+
+            // Tokens only go to the buyer's own sale token account
+            let recipient_info = &ctx.remaining_accounts[user.recipient_index];
+            let recipient = Account::<TokenAccount>::try_from(recipient_info)?;
+            if recipient.mint != presale_account.sale_mint
+                || recipient.owner != user_vesting_account.owner
+            {
+                return Err(ErrorCode::InvalidRecipientAccount.into());
+            }
     
             // Skip users who have completed all their airdrops
             if user_vesting_account.airdrops_completed >= presale_account.total_airdrop_periods {
                 continue;
             }
+
+            // Airdrops go out in order, each once it is due
+            if user.airdrop_index != user_vesting_account.airdrops_completed {
+                return Err(ErrorCode::AirdropConfigurationError.into());
+            }
+            if !presale_account.airdrop_due(user_vesting_account, current_time) {
+                continue;
+            }
     
             // Ensure airdrop index is valid
             let airdrop_percentage = presale_account
@@ -317,30 +416,29 @@ pub mod fam_presale_contract {
                 .get(user.airdrop_index as usize)
                 .ok_or(ErrorCode::AirdropConfigurationError)?;
     
-            // Calculate the airdrop amount
-            let airdrop_amount = user_vesting_account
-                .total_amount
-                .checked_mul(*airdrop_percentage as u64)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(100)
+            // Calculate the airdrop amount, never more than the buyer has left to receive
+            let airdrop_amount = presale_account.airdrop_amount(user_vesting_account, *airdrop_percentage)?;
+            user_vesting_account.airdrops_completed = user_vesting_account
+                .airdrops_completed
+                .checked_add(1)
                 .ok_or(ErrorCode::MathOverflow)?;
-            // Transfer the airdrop tokens
-            token::transfer(
-                ctx.accounts.into_transfer_context(user_vesting_account),
-                airdrop_amount,
-            )?;
-            if airdrop_amount == 0 {
-                continue; // Avoid unnecessary transfers or updates
-            }
-            // Update user vesting account
             user_vesting_account.claimed_amount = user_vesting_account
                 .claimed_amount
                 .checked_add(airdrop_amount)
                 .ok_or(ErrorCode::MathOverflow)?;
-            user_vesting_account.airdrops_completed = user_vesting_account
-                .airdrops_completed
-                .checked_add(1)
-                .ok_or(ErrorCode::MathOverflow)?;
+            user_vesting_account.exit(ctx.program_id)?;
+            if airdrop_amount == 0 {
+                continue; // Avoid unnecessary transfers
+            }
+
+            // Transfer the airdrop tokens out of the vault
+            pay_from_sale_vault(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.sale_vault.to_account_info(),
+                recipient_info,
+                presale_account,
+                airdrop_amount,
+            )?;
         }
     
         Ok(())
@@ -358,11 +456,9 @@ pub mod fam_presale_contract {
         }
     
         // Calculate claimable and refundable tokens
-        let claimable_tokens = calculate_vested_amount(
+        let claimable_tokens = presale_account.vested_amount(
             user_vesting.total_amount,
             user_vesting.start_time,
-            presale_account.vesting_period,
-            presale_account.vesting_interval,
             current_time,
         )
         .saturating_sub(user_vesting.claimed_amount);
@@ -385,15 +481,31 @@ pub mod fam_presale_contract {
         // Calculate refund amount in SOL
         let refund_sol = calculate_sol_price(refund_amount, presale_account.price, sol_price_in_usd)?;
     
-        // Ensure the program PDA has enough SOL for the refund
-        let program_pda = ctx.accounts.presale_account.to_account_info();
-        if **program_pda.lamports.borrow() < refund_sol {
+        // Ensure the treasury holds enough SOL above its rent reserve
+        let treasury = ctx.accounts.treasury.to_account_info();
+        let reserve = Rent::get()?.minimum_balance(0);
+        if treasury.lamports().saturating_sub(reserve) < refund_sol {
             return Err(ErrorCode::InsufficientProgramBalance.into());
         }
     
-        // Process the refund
-        **program_pda.try_borrow_mut_lamports()? -= refund_sol;
-        **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? += refund_sol;
+        // Process the refund, signed by the treasury PDA
+        let presale_key = presale_account.key();
+        let seeds = &[
+            b"treasury".as_ref(),
+            presale_key.as_ref(),
+            &[presale_account.treasury_bump],
+        ];
+        system_program::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: treasury,
+                    to: ctx.accounts.buyer.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            refund_sol,
+        )?;
     
         // Update metrics
         user_vesting.total_amount = user_vesting
@@ -417,6 +529,119 @@ pub mod fam_presale_contract {
     
         Ok(())
     }        
+
+    // Bring the presale, and any of its vesting records passed as remaining accounts, up to
+    // the current layout. The admin pays for any added space.
+    pub fn migrate<'info>(ctx: Context<'_, '_, '_, 'info, Migrate<'info>>) -> Result<()> {
+        let payer = ctx.accounts.authority.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let presale_info = ctx.accounts.presale_account.to_account_info();
+        let presale_key = presale_info.key();
+
+        let mut presale_account =
+            migrate_account::<PresaleAccount>(&presale_info, &payer, &system_program)?;
+        if presale_account.authority != payer.key() {
+            return Err(ErrorCode::UnauthorizedAccess.into());
+        }
+        presale_account.version = PresaleAccount::VERSION;
+        write_account(&presale_info, &presale_account)?;
+
+        for account in ctx.remaining_accounts.iter() {
+            if !account.is_writable {
+                return Err(ErrorCode::InvalidMigrationAccount.into());
+            }
+            let mut user_vesting = migrate_account::<UserVesting>(account, &payer, &system_program)?;
+            // Records from before buyer binding carry no presale yet
+            if user_vesting.presale_account != presale_key
+                && user_vesting.presale_account != Pubkey::default()
+            {
+                return Err(ErrorCode::InvalidMigrationAccount.into());
+            }
+            user_vesting.version = UserVesting::VERSION;
+            write_account(account, &user_vesting)?;
+        }
+
+        Ok(())
+    }
+
+    #[derive(Accounts)]
+    pub struct Migrate<'info> {
+        /// CHECK: Owner and discriminator are checked by `migrate`, which decodes it once grown
+        #[account(mut)]
+        pub presale_account: UncheckedAccount<'info>,
+        #[account(mut)]
+        pub authority: Signer<'info>, // Admin account, pays for the added space
+        pub system_program: Program<'info, System>,
+    }
+
+    #[event]
+    pub struct SolWithdrawn {
+        pub authority: Pubkey, // Admin receiving the SOL
+        pub amount: u64,       // Lamports withdrawn
+        pub remaining: u64,    // Lamports left in the treasury above its rent reserve
+        pub timestamp: i64,
+    }
+
+    // The admin withdraws raised SOL from the treasury PDA once the sale has ended. The
+    // treasury always keeps its rent-exempt reserve.
+    pub fn withdraw_sol(ctx: Context<WithdrawSol>, amount: u64) -> ProgramResult {
+        let presale_account = &ctx.accounts.presale_account;
+        let clock = Clock::get()?;
+
+        // Ensure presale has ended
+        if clock.unix_timestamp < presale_account.presale_end {
+            return Err(ErrorCode::PresaleNotEnded.into());
+        }
+
+        let treasury = ctx.accounts.treasury.to_account_info();
+        let available = treasury.lamports().saturating_sub(Rent::get()?.minimum_balance(0));
+        if amount == 0 || amount > available {
+            return Err(ErrorCode::InsufficientProgramBalance.into());
+        }
+
+        let presale_key = presale_account.key();
+        let seeds = &[
+            b"treasury".as_ref(),
+            presale_key.as_ref(),
+            &[presale_account.treasury_bump],
+        ];
+        system_program::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: treasury,
+                    to: ctx.accounts.authority.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+        )?;
+
+        emit!(SolWithdrawn {
+            authority: ctx.accounts.authority.key(),
+            amount,
+            remaining: available - amount,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    #[derive(Accounts)]
+    pub struct WithdrawSol<'info> {
+        #[account(has_one = authority)]
+        pub presale_account: Account<'info, PresaleAccount>,
+        #[account(mut)]
+        pub authority: Signer<'info>, // Admin account, receives the SOL
+        /// CHECK: Presale treasury PDA holding the raised SOL
+        #[account(
+            mut,
+            seeds = [b"treasury", presale_account.key().as_ref()],
+            bump = presale_account.treasury_bump,
+        )]
+        pub treasury: UncheckedAccount<'info>,
+        pub system_program: Program<'info, System>,
+    }
     
     fn get_price_from_oracle(
         oracle_account: &AccountInfo,
@@ -501,10 +726,21 @@ pub mod fam_presale_contract {
     pub struct Refund<'info> {
         #[account(mut)]
         pub presale_account: Account<'info, PresaleAccount>, // Presale account storing presale details
-        #[account(mut)]
+        #[account(
+            mut,
+            seeds = [b"vesting", presale_account.key().as_ref(), buyer.key().as_ref()],
+            bump = user_vesting.bump,
+        )]
         pub user_vesting: Account<'info, UserVesting>,       // User's vesting account
         #[account(mut)]
         pub buyer: Signer<'info>,                           // User requesting the refund
+        /// CHECK: Presale treasury PDA holding the raised SOL
+        #[account(
+            mut,
+            seeds = [b"treasury", presale_account.key().as_ref()],
+            bump = presale_account.treasury_bump,
+        )]
+        pub treasury: UncheckedAccount<'info>,
         pub sol_to_usd_oracle: AccountInfo<'info>,          // Oracle account for SOL/USD price
         pub system_program: Program<'info, System>,         // System program for SOL transfers
     }
@@ -520,6 +756,7 @@ pub mod fam_presale_contract {
     pub struct UserDistribution {
         pub user_vesting_index: usize, // Index in the remaining accounts array
         pub airdrop_index: u8,         // Index of the current airdrop percentage
+        pub recipient_index: usize,    // Index of the buyer's sale token account in the remaining accounts
     }
 
     #[event]
@@ -541,18 +778,19 @@ pub mod fam_presale_contract {
         let current_time = clock.unix_timestamp;
 
         // Calculate vested tokens
-        let vested_amount = calculate_vested_amount(
+        let vested_amount = ctx.accounts.presale_account.vested_amount(
             user_vesting.total_amount,
             user_vesting.start_time,
-            ctx.accounts.presale_account.vesting_period,
-            ctx.accounts.presale_account.vesting_interval,
             current_time,
         );
 
         let claimable_amount = vested_amount.saturating_sub(user_vesting.claimed_amount);
         if claimable_amount > 0 {
-            token::transfer(
-                ctx.accounts.into_transfer_context(),
+            pay_from_sale_vault(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.sale_vault.to_account_info(),
+                &ctx.accounts.buyer_token_account.to_account_info(),
+                &ctx.accounts.presale_account,
                 claimable_amount,
             )?;
 
@@ -572,45 +810,14 @@ pub mod fam_presale_contract {
         }
     }
 
-    // Helper to calculate vested amount
-    fn calculate_vested_amount(
-        total_amount: u64,
-        start_time: i64,
-        vesting_period: i64,
-        vesting_interval: i64,
-        current_time: i64,
-    ) -> u64 {
-        if current_time < start_time {
-            return 0;
-        }
-        let elapsed_time = current_time - start_time;
-
-        if vesting_period == 0 || vesting_interval == 0 {
-            return 0;
-        }
-
-        let vested_intervals = elapsed_time / vesting_interval;
-        let total_intervals = vesting_period / vesting_interval;
-        
-        if total_intervals == 0 {
-            return 0;
-        }
-
-        // Calculate the proportion of vested tokens
-        let vested_amount = (total_amount as u128 * vested_intervals as u128 / total_intervals as u128) as u64;
-        vested_amount
-    }
-
     pub fn calculate_claimable(ctx: Context<CalculateClaimable>) -> Result<u64> {
         let user_vesting = &ctx.accounts.user_vesting;
         let clock = Clock::get()?;
         let current_time = clock.unix_timestamp;
 
-        let vested_amount = calculate_vested_amount(
+        let vested_amount = ctx.accounts.presale_account.vested_amount(
             user_vesting.total_amount,
             user_vesting.start_time,
-            ctx.accounts.presale_account.vesting_period,
-            ctx.accounts.presale_account.vesting_interval,
             current_time,
         );
 
@@ -619,13 +826,50 @@ pub mod fam_presale_contract {
     }
 
     #[derive(Accounts)]
-    pub struct CalculateClaimable<'info> {
+    #[instruction(sale_id: u64)]
+    pub struct Initialize<'info> {
         #[account(mut)]
+        pub authority: Signer<'info>, // Admin of the new sale, pays for its accounts
+        #[account(
+            init,
+            payer = authority,
+            space = 8 + PresaleAccount::INIT_SPACE,
+            seeds = [
+                b"presale",
+                authority.key().as_ref(),
+                sale_mint.key().as_ref(),
+                &sale_id.to_le_bytes(),
+            ],
+            bump,
+        )]
+        pub presale_account: Account<'info, PresaleAccount>,
+        pub sale_mint: Account<'info, Mint>,
+        #[account(
+            init,
+            payer = authority,
+            seeds = [b"sale_vault", presale_account.key().as_ref()],
+            bump,
+            token::mint = sale_mint,
+            token::authority = sale_vault, // The vault PDA owns itself
+        )]
+        pub sale_vault: Account<'info, TokenAccount>,
+        /// CHECK: System-owned PDA that receives every SOL payment
+        #[account(mut, seeds = [b"treasury", presale_account.key().as_ref()], bump)]
+        pub treasury: UncheckedAccount<'info>,
+        pub token_program: Program<'info, Token>,
+        pub system_program: Program<'info, System>,
+        pub rent: Sysvar<'info, Rent>,
+    }
+
+    #[derive(Accounts)]
+    pub struct CalculateClaimable<'info> {
+        #[account(has_one = presale_account)]
         pub user_vesting: Account<'info, UserVesting>,
         pub presale_account: Account<'info, PresaleAccount>,
     }
 
     #[account]
+    #[derive(InitSpace)]
     pub struct PresaleAccount {
         pub presale_start: i64,
         pub presale_end: i64,
@@ -636,6 +880,7 @@ pub mod fam_presale_contract {
         pub vesting_period: i64,
         pub vesting_interval: i64,
         pub total_airdrop_periods: u8,
+        #[max_len(MAX_AIRDROP_PERIODS)]
         pub airdrop_percentages: Vec<u8>,
         pub total_sold_sol: u64,         // Total tokens sold
         pub min_buy_amount_sol: u64,     // Minimum SOL amount per purchase
@@ -644,19 +889,80 @@ pub mod fam_presale_contract {
         pub authority: Pubkey,           // Admin authority key
         pub manual_price_override: Option<u64>, // Optional manual price in USD cents
         pub paused: bool, // Whether the presale is paused
+        pub sale_mint: Pubkey,           // Mint of the token being sold
+        pub sale_vault: Pubkey,          // Program-owned vault holding the sale tokens
+        pub sale_vault_bump: u8,
+        pub sale_id: u64,                // Third PDA seed, after authority and sale mint
+        pub bump: u8,
+        pub treasury_bump: u8,           // System-owned PDA holding raised SOL
+        pub version: u8,                 // Layout version; new fields go before `reserved`
+        pub reserved: [u8; 64],          // Zeroed space for future fields
     }
 
-    impl<'info> Claim<'info> {
-        fn into_transfer_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
-            CpiContext::new(
-                self.token_program.to_account_info(),
+    impl PresaleAccount {
+        pub const VERSION: u8 = 1;
+
+        // Stepped vesting from the buyer's start time, via the shared vesting module
+        pub fn vested_amount(&self, total_amount: u64, start_time: i64, current_time: i64) -> u64 {
+            VestingSchedule {
+                start: start_time,
+                cliff: self.cliff_period,
+                duration: self.vesting_period,
+                interval: self.vesting_interval,
+                tge_bps: 0,
+                points: &[],
+            }
+            .vested(VestingMode::Stepped, total_amount, current_time)
+        }
+
+        // The initial airdrop is due once the sale ends; each later one needs another
+        // interval past the buyer's start time
+        pub fn airdrop_due(&self, user_vesting: &UserVesting, current_time: i64) -> bool {
+            let periods_elapsed = (current_time - user_vesting.start_time) / self.vesting_interval;
+            user_vesting.airdrops_completed == 0
+                || periods_elapsed > user_vesting.airdrops_completed as i64
+        }
+
+        // A percentage of the buyer's allocation, capped at what claims and earlier
+        // airdrops have not already paid out
+        pub fn airdrop_amount(&self, user_vesting: &UserVesting, percentage: u8) -> Result<u64, ProgramError> {
+            let amount = user_vesting
+                .total_amount
+                .checked_mul(percentage as u64)
+                .ok_or(ErrorCode::MathOverflow)?
+                .checked_div(100)
+                .ok_or(ErrorCode::MathOverflow)?;
+            Ok(amount.min(user_vesting.total_amount.saturating_sub(user_vesting.claimed_amount)))
+        }
+    }
+
+    // Move sale tokens out of the vault, signed by the vault PDA's seeds
+    fn pay_from_sale_vault<'info>(
+        token_program: &AccountInfo<'info>,
+        sale_vault: &AccountInfo<'info>,
+        recipient: &AccountInfo<'info>,
+        presale_account: &Account<'info, PresaleAccount>,
+        amount: u64,
+    ) -> ProgramResult {
+        let presale_key = presale_account.key();
+        let seeds = &[
+            b"sale_vault".as_ref(),
+            presale_key.as_ref(),
+            &[presale_account.sale_vault_bump],
+        ];
+        token::transfer(
+            CpiContext::new_with_signer(
+                token_program.clone(),
                 Transfer {
-                    from: self.user_vesting.to_account_info(),
-                    to: self.buyer.to_account_info(),
-                    authority: self.buyer.to_account_info(),
+                    from: sale_vault.clone(),
+                    to: recipient.clone(),
+                    authority: sale_vault.clone(),
                 },
-            )
-        }
+                &[&seeds[..]],
+            ),
+            amount,
+        )?;
+        Ok(())
     }
 
     pub fn update_presale_discount(
@@ -687,25 +993,28 @@ pub mod fam_presale_contract {
             return Err(ErrorCode::PresaleNotEnded.into());
         }
 
+        // The initial airdrop goes out once
+        if user_vesting.airdrops_completed != 0 {
+            return Err(ErrorCode::AirdropCompleted.into());
+        }
+
         // Calculate initial airdrop percentage
         let initial_percentage = *presale_account.airdrop_percentages.get(0).ok_or(ErrorCode::AirdropConfigurationError)?;
-        let initial_airdrop = user_vesting
-            .total_amount
-            .checked_mul(initial_percentage)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(100)
-            .ok_or(ErrorCode::MathOverflow)?;
-
-        // Transfer initial airdrop
-        token::transfer(
-            ctx.accounts.into_transfer_context(),
-            initial_airdrop,
-        )?;
+        let initial_airdrop = presale_account.airdrop_amount(user_vesting, initial_percentage)?;
 
         // Update claimed amount and airdrop count
         user_vesting.claimed_amount += initial_airdrop;
         user_vesting.airdrops_completed = 1;
 
+        // Transfer initial airdrop out of the vault
+        pay_from_sale_vault(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.sale_vault.to_account_info(),
+            &ctx.accounts.recipient_account.to_account_info(),
+            presale_account,
+            initial_airdrop,
+        )?;
+
         Ok(())
     }
 
@@ -726,12 +1035,22 @@ pub mod fam_presale_contract {
     // Define the `Claim` context for claiming tokens
     #[derive(Accounts)]
     pub struct Claim<'info> {
-        #[account(mut)]
+        #[account(
+            mut,
+            seeds = [b"vesting", presale_account.key().as_ref(), buyer.key().as_ref()],
+            bump = user_vesting.bump,
+        )]
         pub user_vesting: Account<'info, UserVesting>,
-        #[account(mut)]
-        pub presale_account: Account<'info, PresaleAccount>,  // Added
-        #[account(mut)]
+        pub presale_account: Account<'info, PresaleAccount>,
         pub buyer: Signer<'info>,
+        #[account(mut, address = presale_account.sale_vault)]
+        pub sale_vault: Account<'info, TokenAccount>,
+        #[account(
+            mut,
+            token::mint = presale_account.sale_mint,
+            token::authority = buyer,
+        )]
+        pub buyer_token_account: Account<'info, TokenAccount>,
         pub token_program: Program<'info, Token>,
     }
 
@@ -746,10 +1065,29 @@ pub mod fam_presale_contract {
 
     #[derive(Accounts)]
     pub struct DistributeAirdrop<'info> {
-        #[account(mut)]
+        #[account(mut, has_one = presale_account)]
         pub user_vesting: Account<'info, UserVesting>,
-        #[account(mut)]
+        #[account(has_one = authority)]
         pub presale_account: Account<'info, PresaleAccount>,
+        pub authority: Signer<'info>, // Admin account
+        #[account(mut, address = presale_account.sale_vault)]
+        pub sale_vault: Account<'info, TokenAccount>,
+        #[account(
+            mut,
+            token::mint = presale_account.sale_mint,
+            token::authority = user_vesting.owner,
+        )]
+        pub recipient_account: Account<'info, TokenAccount>, // The buyer's own sale token account
+        pub token_program: Program<'info, Token>,
+    }
+
+    #[derive(Accounts)]
+    pub struct BatchDistributeAirdrops<'info> {
+        #[account(has_one = authority)]
+        pub presale_account: Account<'info, PresaleAccount>,
+        pub authority: Signer<'info>, // Admin account
+        #[account(mut, address = presale_account.sale_vault)]
+        pub sale_vault: Account<'info, TokenAccount>,
         pub token_program: Program<'info, Token>,
     }
 
@@ -757,10 +1095,23 @@ pub mod fam_presale_contract {
     pub struct Purchase<'info> {
         #[account(mut)]
         pub presale_account: Account<'info, PresaleAccount>,
-        #[account(mut)]
+        #[account(
+            init_if_needed,
+            payer = buyer,
+            space = 8 + UserVesting::INIT_SPACE,
+            seeds = [b"vesting", presale_account.key().as_ref(), buyer.key().as_ref()],
+            bump,
+        )]
         pub user_vesting: Account<'info, UserVesting>,
         #[account(mut)]
         pub buyer: Signer<'info>,
+        /// CHECK: Presale treasury PDA holding the raised SOL
+        #[account(
+            mut,
+            seeds = [b"treasury", presale_account.key().as_ref()],
+            bump = presale_account.treasury_bump,
+        )]
+        pub treasury: UncheckedAccount<'info>,
         pub sol_to_usd_oracle: AccountInfo<'info>, // Oracle account for SOL/USD price
         pub system_program: Program<'info, System>,
     }
@@ -772,8 +1123,7 @@ pub mod fam_presale_contract {
         let current_time = clock.unix_timestamp;
 
         // Ensure at least one month has passed since the last airdrop
-        let months_elapsed = (current_time - user_vesting.start_time) / presale_account.vesting_interval;
-        if months_elapsed as u8 <= user_vesting.airdrops_completed {
+        if user_vesting.airdrops_completed == 0 || !presale_account.airdrop_due(user_vesting, current_time) {
             return Err(ErrorCode::AirdropNotDue.into());
         }
 
@@ -789,23 +1139,21 @@ pub mod fam_presale_contract {
             .ok_or(ErrorCode::AirdropConfigurationError)?;
 
         // Calculate the airdrop amount
-        let airdrop_amount = user_vesting
-            .total_amount
-            .checked_mul(current_percentage)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(100)
-            .ok_or(ErrorCode::MathOverflow)?;
-
-        // Transfer the airdrop amount
-        token::transfer(
-            ctx.accounts.into_transfer_context(),
-            airdrop_amount,
-        )?;
+        let airdrop_amount = presale_account.airdrop_amount(user_vesting, current_percentage)?;
 
         // Update claimed amount and airdrop count
         user_vesting.claimed_amount += airdrop_amount;
         user_vesting.airdrops_completed += 1;
 
+        // Transfer the airdrop amount out of the vault
+        pay_from_sale_vault(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.sale_vault.to_account_info(),
+            &ctx.accounts.recipient_account.to_account_info(),
+            presale_account,
+            airdrop_amount,
+        )?;
+
         Ok(())
     }
 
@@ -859,6 +1207,10 @@ pub mod fam_presale_contract {
         InvalidUserAccountIndex,
         #[msg("Presale is currently paused.")]
         PresalePaused,
+        #[msg("Tokens must go to the buyer's own sale token account.")]
+        InvalidRecipientAccount,
+        #[msg("Account cannot be migrated by this presale.")]
+        InvalidMigrationAccount,
     }
 
 }
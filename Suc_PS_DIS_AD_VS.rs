@@ -9,6 +9,11 @@ use solana_program::{
     clock::Clock,
 };
 
+mod migration;
+mod multisig;
+use multisig::{approve, propose, require_admin, set_signers, Multisig, Proposal, MAX_MULTISIG_SIGNERS};
+use migration::{migrate_account, write_account};
+
 declare_id!("4UjdrPr1Tv1974XZgLRZ63Wu4XisLRS2rh9K4ChK1wB7");
 
 #[program]
@@ -28,6 +33,7 @@ pub mod presale_vesting {
         presale.total_tokens_allocated = 0;
         presale.is_closed = false;
         presale.bump = bump; // Save the bump seed
+        presale.version = PresaleAccount::VERSION;
         presale.public_sale_price = public_sale_price; // Set the public sale price
         Ok(())
     }
@@ -52,6 +58,7 @@ pub mod presale_vesting {
 
         // Update allocation account with the contributed amount
         let allocation = &mut ctx.accounts.allocation_account;
+        allocation.version = AllocationAccount::VERSION;
         allocation.amount += tokens_to_allocate; // Allocate tokens
         allocation.cliff_timestamp = presale.cliff_timestamp;
         allocation.vesting_end_timestamp = presale.vesting_end_timestamp;
@@ -155,11 +162,93 @@ pub mod presale_vesting {
         Ok(())
     }
 
+    // Hands admin actions to an M-of-N multisig. Once one exists, changing its signers takes
+    // an approved `ChangeSigners` proposal, like any other admin action.
+    pub fn set_admin_signers(
+        ctx: Context<SetAdminSigners>,
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        let presale = &mut ctx.accounts.presale_account;
+        require_admin(
+            presale,
+            Some(&ctx.accounts.multisig),
+            ctx.accounts.proposal.as_mut(),
+            &ctx.accounts.admin.key(),
+            AdminAction::ChangeSigners { signers: signers.clone(), threshold },
+        )?;
+
+        let multisig = &mut ctx.accounts.multisig;
+        if presale.signer_threshold == 0 {
+            multisig.presale_account = presale.key();
+            multisig.bump = *ctx.bumps.get("multisig").unwrap();
+            multisig.version = Multisig::VERSION;
+        }
+        set_signers(multisig, signers, threshold)?;
+        presale.signer_threshold = threshold;
+
+        Ok(())
+    }
+
+    // Any multisig signer proposes an admin action; proposing counts as the first approval
+    pub fn propose_admin_action(ctx: Context<ProposeAdminAction>, action: AdminAction) -> Result<()> {
+        propose(
+            &mut ctx.accounts.multisig,
+            &mut ctx.accounts.proposal,
+            &ctx.accounts.proposer.key(),
+            action,
+            *ctx.bumps.get("proposal").unwrap(),
+        )
+    }
+
+    pub fn approve_proposal(ctx: Context<ApproveProposal>) -> Result<()> {
+        approve(
+            &ctx.accounts.multisig,
+            &mut ctx.accounts.proposal,
+            &ctx.accounts.signer.key(),
+        )
+    }
+
+    // Bring the presale, and any allocation accounts passed as remaining accounts, up to the
+    // current layout. The admin pays for any added space; under a multisig they must be a
+    // signer executing an approved `Migrate` proposal.
+    pub fn migrate<'info>(ctx: Context<'_, '_, '_, 'info, Migrate<'info>>) -> Result<()> {
+        let payer = ctx.accounts.admin.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let presale_info = ctx.accounts.presale_account.to_account_info();
+
+        let mut presale = migrate_account::<PresaleAccount>(&presale_info, &payer, &system_program)?;
+        require_admin(
+            &presale,
+            ctx.accounts.multisig.as_ref(),
+            ctx.accounts.proposal.as_mut(),
+            &payer.key(),
+            AdminAction::Migrate,
+        )?;
+        presale.version = PresaleAccount::VERSION;
+        write_account(&presale_info, &presale)?;
+
+        for account in ctx.remaining_accounts {
+            require!(account.is_writable, CustomError::InvalidMigrationAccount);
+            let mut allocation = migrate_account::<AllocationAccount>(account, &payer, &system_program)?;
+            allocation.version = AllocationAccount::VERSION;
+            write_account(account, &allocation)?;
+        }
+
+        Ok(())
+    }
+
     pub fn close_presale(ctx: Context<ClosePresale>) -> Result<()> {
         let presale = &mut ctx.accounts.presale_account;
 
-        // Only the admin can close the presale
-        require!(ctx.accounts.admin.key() == presale.admin, CustomError::Unauthorized);
+        // Only the admin, or the multisig once one is configured, can close the presale
+        require_admin(
+            presale,
+            ctx.accounts.multisig.as_ref(),
+            ctx.accounts.proposal.as_mut(),
+            &ctx.accounts.admin.key(),
+            AdminAction::ClosePresale,
+        )?;
         presale.is_closed = true;
 
         Ok(())
@@ -168,7 +257,7 @@ pub mod presale_vesting {
 
 #[derive(Accounts)]
 pub struct InitializePresale<'info> {
-    #[account(init, payer = admin, space = 8 + 32 + 32 + 8 + 8 + 1)]
+    #[account(init, payer = admin, space = 8 + PresaleAccount::INIT_SPACE)]
     pub presale_account: Account<'info, PresaleAccount>,
     #[account(mut)]
     pub admin: Signer<'info>,
@@ -183,7 +272,7 @@ pub struct Contribute<'info> {
     #[account(
         init_if_needed,
         payer = contributor,
-        space = 8 + 8 + 8 + 8 + 8
+        space = 8 + AllocationAccount::INIT_SPACE
     )]
     pub allocation_account: Account<'info, AllocationAccount>, // Allocation state for the contributor
     #[account(mut)]
@@ -229,9 +318,82 @@ pub struct ClosePresale<'info> {
     pub presale_account: Account<'info, PresaleAccount>,
     #[account(signer)]
     pub admin: AccountInfo<'info>,
+    #[account(
+        seeds = [b"multisig", presale_account.key().as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Option<Account<'info, Multisig>>, // Required once a multisig is configured
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>, // Approved proposal for this action, under a multisig
+}
+
+#[derive(Accounts)]
+pub struct SetAdminSigners<'info> {
+    #[account(mut)]
+    pub presale_account: Account<'info, PresaleAccount>,
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + Multisig::INIT_SPACE,
+        seeds = [b"multisig", presale_account.key().as_ref()],
+        bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>, // Approved `ChangeSigners` proposal, once the multisig exists
+    #[account(mut)]
+    pub admin: Signer<'info>, // Admin on first setup, pays for the multisig; a signer afterwards
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAdminAction<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.presale_account.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + Proposal::INIT_SPACE,
+        seeds = [b"proposal", multisig.key().as_ref(), &multisig.proposal_count.to_le_bytes()],
+        bump
+    )]
+    pub proposal: Account<'info, Proposal>,
+    #[account(mut)]
+    pub proposer: Signer<'info>, // Must be in the signer set
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveProposal<'info> {
+    pub multisig: Account<'info, Multisig>,
+    #[account(mut, has_one = multisig)]
+    pub proposal: Account<'info, Proposal>,
+    pub signer: Signer<'info>, // Must be in the signer set
+}
+
+#[derive(Accounts)]
+pub struct Migrate<'info> {
+    /// CHECK: Owner and discriminator are checked by `migrate`, which decodes it once grown
+    #[account(mut)]
+    pub presale_account: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub admin: Signer<'info>, // Admin, or a multisig signer once one exists; pays for added space
+    #[account(
+        seeds = [b"multisig", presale_account.key().as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Option<Account<'info, Multisig>>, // Required once a multisig is configured
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>, // Approved proposal for this action, under a multisig
+    pub system_program: Program<'info, System>,
 }
 
 #[account]
+#[derive(InitSpace)]
 pub struct PresaleAccount {
     pub token_mint: Pubkey,              // Token mint address
     pub admin: Pubkey,                  // Admin address
@@ -241,14 +403,41 @@ pub struct PresaleAccount {
     pub is_closed: bool,                // Whether the presale is closed
     pub bump: u8,                       // PDA bump seed
     pub public_sale_price: u64,         // Token price in public sale (e.g., 1 token = X lamports)
+    pub version: u8,                    // Layout version; new fields go before `reserved`
+    pub signer_threshold: u8,           // Multisig approvals needed for admin actions; zero means the single admin
+    pub reserved: [u8; 63],             // Zeroed space for future fields
+}
+
+impl PresaleAccount {
+    pub const VERSION: u8 = 2;
 }
 
 #[account]
+#[derive(InitSpace)]
 pub struct AllocationAccount {
     pub amount: u64,
     pub claimed_amount: u64,
     pub cliff_timestamp: u64,
     pub vesting_end_timestamp: u64,
+    pub version: u8,        // Layout version; new fields go before `reserved`
+    pub reserved: [u8; 32], // Zeroed space for future fields
+}
+
+impl AllocationAccount {
+    pub const VERSION: u8 = 1;
+}
+
+// What a multisig proposal authorizes; each runs in its own instruction, which checks the
+// arguments it is called with against the proposal
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub enum AdminAction {
+    ChangeSigners {
+        #[max_len(MAX_MULTISIG_SIGNERS)]
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    },
+    Migrate,
+    ClosePresale,
 }
 
 #[error_code]
@@ -263,4 +452,22 @@ pub enum CustomError {
     Unauthorized,
     #[msg("Invalid contribution. You must contribute enough to purchase at least one token.")]
     InvalidContribution, // New error variant
-}
+    #[msg("Signers must be unique, at most ten, and cover the threshold.")]
+    InvalidSignerSet,
+    #[msg("Not enough signers approved this action.")]
+    ThresholdNotMet,
+    #[msg("Account cannot be migrated by this presale.")]
+    InvalidMigrationAccount,
+    #[msg("Signer is not part of the multisig.")]
+    NotMultisigSigner,
+    #[msg("This presale is administered by its multisig; pass it and an approved proposal.")]
+    MultisigRequired,
+    #[msg("The proposal does not authorize this action.")]
+    ProposalActionMismatch,
+    #[msg("The proposal has already been executed.")]
+    ProposalAlreadyExecuted,
+    #[msg("The signer set changed after this proposal was made.")]
+    StaleProposal,
+    #[msg("Arithmetic overflow.")]
+    MathOverflow,
+}
\ No newline at end of file
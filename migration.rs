@@ -0,0 +1,69 @@
+// In-place account migration shared by the presale programs. A state account grows to its
+// current layout and decodes with the fields it did not have yet as zeros; the caller bumps
+// the version byte and writes it back.
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+
+// Grow a program account to `new_len`, topping its rent up from `payer`. The added
+// bytes are zeroed.
+pub fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    new_len: usize,
+) -> Result<()> {
+    if account.data_len() >= new_len {
+        return Ok(());
+    }
+
+    let rent_due = Rent::get()?
+        .minimum_balance(new_len)
+        .saturating_sub(account.lamports());
+    if rent_due > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            rent_due,
+        )?;
+    }
+
+    account.realloc(new_len, true)?;
+    Ok(())
+}
+
+// Decode a program account of type `T`, growing it to the current layout first so an
+// older, shorter encoding reads its missing fields as zeros. Fails the way `Account<T>`
+// would for an account of another program or type.
+pub fn migrate_account<'info, T>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<T>
+where
+    T: AccountDeserialize + Discriminator + Space,
+{
+    if account.owner != &crate::ID {
+        return Err(anchor_lang::error::ErrorCode::AccountOwnedByWrongProgram.into());
+    }
+    if account.try_borrow_data()?.get(..8) != Some(&T::DISCRIMINATOR[..]) {
+        return Err(anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch.into());
+    }
+
+    grow_account(account, payer, system_program, 8 + T::INIT_SPACE)?;
+
+    let data = account.try_borrow_data()?;
+    T::try_deserialize(&mut &data[..])
+}
+
+// Rewrite an account in full, zeroing whatever an older encoding left past the new one
+pub fn write_account<T: AccountSerialize>(account: &AccountInfo, value: &T) -> Result<()> {
+    let mut data = account.try_borrow_mut_data()?;
+    data.fill(0);
+    value.try_serialize(&mut &mut data[..])
+}
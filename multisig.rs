@@ -0,0 +1,222 @@
+// M-of-N admin for the smaller presale programs, the same design as Presale-Play's: admin
+// actions become proposals that collect approvals from a signer set, and each runs in the
+// instruction it authorizes once the threshold is met. Each program supplies its own
+// `PresaleAccount` (with `admin` and `signer_threshold`), `AdminAction` and `CustomError`.
+use anchor_lang::prelude::*;
+
+use crate::{AdminAction, CustomError, PresaleAccount};
+
+pub const MAX_MULTISIG_SIGNERS: usize = 10; // Upper bound on the admin signer set
+
+#[account]
+#[derive(InitSpace)]
+pub struct Multisig {
+    pub presale_account: Pubkey, // Presale these signers administer
+    #[max_len(MAX_MULTISIG_SIGNERS)]
+    pub signers: Vec<Pubkey>,    // Signer set, at most MAX_MULTISIG_SIGNERS
+    pub threshold: u8,           // Approvals needed to execute
+    pub signer_set_version: u32, // Bumped on every signer change to void pending approvals
+    pub proposal_count: u64,     // Next proposal index
+    pub bump: u8,                // PDA bump seed
+    pub version: u8,             // Layout version; new fields go before `reserved`
+    pub reserved: [u8; 32],      // Zeroed space for future fields
+}
+
+impl Multisig {
+    pub const VERSION: u8 = 1;
+
+    pub fn signer_index(&self, key: &Pubkey) -> Result<usize> {
+        let index = self
+            .signers
+            .iter()
+            .position(|signer| signer == key)
+            .ok_or(CustomError::NotMultisigSigner)?;
+        Ok(index)
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Proposal {
+    pub multisig: Pubkey,
+    pub index: u64,
+    pub proposer: Pubkey,
+    pub action: AdminAction,
+    pub signer_set_version: u32, // Signer set the approvals refer to
+    pub approvals: u16,          // Bitmap over the multisig's signer indexes
+    pub executed: bool,
+    pub created_at: i64,
+    pub bump: u8,
+    pub version: u8,             // Layout version; new fields go before `reserved`
+    pub reserved: [u8; 32],      // Zeroed space for future fields
+}
+
+impl Proposal {
+    pub const VERSION: u8 = 1;
+}
+
+pub fn validate_multisig_signers(signers: &[Pubkey], threshold: u8) -> Result<()> {
+    require!(
+        threshold > 0
+            && threshold as usize <= signers.len()
+            && signers.len() <= MAX_MULTISIG_SIGNERS,
+        CustomError::InvalidSignerSet
+    );
+    for (i, signer) in signers.iter().enumerate() {
+        require!(!signers[..i].contains(signer), CustomError::InvalidSignerSet);
+    }
+    Ok(())
+}
+
+// Replace the signer set. Approvals are positional, so pending proposals go stale.
+pub fn set_signers(multisig: &mut Account<Multisig>, signers: Vec<Pubkey>, threshold: u8) -> Result<()> {
+    validate_multisig_signers(&signers, threshold)?;
+    multisig.signers = signers;
+    multisig.threshold = threshold;
+    multisig.signer_set_version = multisig
+        .signer_set_version
+        .checked_add(1)
+        .ok_or(CustomError::MathOverflow)?;
+
+    emit!(MultisigUpdated {
+        multisig: multisig.key(),
+        signers: multisig.signers.clone(),
+        threshold,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
+
+// Any signer proposes an admin action; proposing counts as the first approval
+pub fn propose(
+    multisig: &mut Account<Multisig>,
+    proposal: &mut Account<Proposal>,
+    proposer: &Pubkey,
+    action: AdminAction,
+    bump: u8,
+) -> Result<()> {
+    let signer_index = multisig.signer_index(proposer)?;
+    if let AdminAction::ChangeSigners { signers, threshold } = &action {
+        validate_multisig_signers(signers, *threshold)?;
+    }
+
+    let clock = Clock::get()?;
+    proposal.multisig = multisig.key();
+    proposal.index = multisig.proposal_count;
+    proposal.proposer = *proposer;
+    proposal.action = action;
+    proposal.signer_set_version = multisig.signer_set_version;
+    proposal.approvals = 1 << signer_index;
+    proposal.executed = false;
+    proposal.created_at = clock.unix_timestamp;
+    proposal.bump = bump;
+    proposal.version = Proposal::VERSION;
+    multisig.proposal_count = multisig
+        .proposal_count
+        .checked_add(1)
+        .ok_or(CustomError::MathOverflow)?;
+
+    emit!(ProposalCreated {
+        multisig: multisig.key(),
+        proposal: proposal.key(),
+        index: proposal.index,
+        proposer: *proposer,
+        action: proposal.action.clone(),
+        timestamp: clock.unix_timestamp,
+    });
+    Ok(())
+}
+
+pub fn approve(multisig: &Multisig, proposal: &mut Account<Proposal>, signer: &Pubkey) -> Result<()> {
+    let signer_index = multisig.signer_index(signer)?;
+    require!(!proposal.executed, CustomError::ProposalAlreadyExecuted);
+    // Approvals are positional, so they only count for the signer set they were made under
+    require!(
+        proposal.signer_set_version == multisig.signer_set_version,
+        CustomError::StaleProposal
+    );
+    proposal.approvals |= 1 << signer_index;
+
+    emit!(ProposalApproved {
+        proposal: proposal.key(),
+        signer: *signer,
+        approvals: proposal.approvals.count_ones() as u8,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
+
+// Single admin until a multisig is configured. After that, `admin` must be one of the
+// signers and bring an approved proposal for exactly `action`, which this consumes; the
+// admin's own signature counts toward the threshold.
+pub fn require_admin(
+    presale: &PresaleAccount,
+    multisig: Option<&Account<Multisig>>,
+    proposal: Option<&mut Account<Proposal>>,
+    admin: &Pubkey,
+    action: AdminAction,
+) -> Result<()> {
+    if presale.signer_threshold == 0 {
+        require!(*admin == presale.admin, CustomError::Unauthorized);
+        return Ok(());
+    }
+    let multisig = multisig.ok_or(CustomError::MultisigRequired)?;
+    let proposal = proposal.ok_or(CustomError::MultisigRequired)?;
+    require!(
+        proposal.multisig == multisig.key() && proposal.action == action,
+        CustomError::ProposalActionMismatch
+    );
+
+    let signer_index = multisig.signer_index(admin)?;
+    require!(!proposal.executed, CustomError::ProposalAlreadyExecuted);
+    require!(
+        proposal.signer_set_version == multisig.signer_set_version,
+        CustomError::StaleProposal
+    );
+    proposal.approvals |= 1 << signer_index;
+    require!(
+        proposal.approvals.count_ones() >= multisig.threshold as u32,
+        CustomError::ThresholdNotMet
+    );
+    proposal.executed = true;
+
+    emit!(ProposalExecuted {
+        proposal: proposal.key(),
+        executor: *admin,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
+
+#[event]
+pub struct MultisigUpdated {
+    pub multisig: Pubkey,
+    pub signers: Vec<Pubkey>,
+    pub threshold: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalCreated {
+    pub multisig: Pubkey,
+    pub proposal: Pubkey,
+    pub index: u64,
+    pub proposer: Pubkey,
+    pub action: AdminAction,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalApproved {
+    pub proposal: Pubkey,
+    pub signer: Pubkey,
+    pub approvals: u8, // Approvals so far
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalExecuted {
+    pub proposal: Pubkey,
+    pub executor: Pubkey,
+    pub timestamp: i64,
+}
@@ -0,0 +1,191 @@
+// Vesting math shared by the presale programs. Everything here is a pure function of
+// amounts and timestamps, so claims, airdrops and read-only quotes agree everywhere.
+use anchor_lang::prelude::*;
+
+pub const VESTING_BPS: u64 = 10_000; // Basis points in 100%
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum VestingMode {
+    #[default]
+    Stepped,   // Whole `interval` steps from the start; cliff and TGE unlock are ignored
+    Linear,    // TGE unlock at the start, the rest per second, nothing more until the cliff
+    Piecewise, // Explicit unlock points; the other schedule fields are ignored
+}
+
+// Unlocks `bps` of the total once `offset` seconds have passed since the start
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub struct UnlockPoint {
+    pub offset: i64,
+    pub bps: u16,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VestingSchedule<'a> {
+    pub start: i64,                // TGE; nothing vests before it
+    pub cliff: i64,                // Seconds after the start before linear vesting pays out
+    pub duration: i64,             // Seconds from the start until everything has vested
+    pub interval: i64,             // Step length in Stepped mode
+    pub tge_bps: u16,              // Share unlocked at the start in Linear mode
+    pub points: &'a [UnlockPoint], // Unlock points in Piecewise mode
+}
+
+impl VestingSchedule<'_> {
+    // Amount of `total` vested at `current_time`; never more than `total`
+    pub fn vested(&self, mode: VestingMode, total: u64, current_time: i64) -> u64 {
+        if current_time < self.start {
+            return 0;
+        }
+        let elapsed = current_time - self.start;
+        match mode {
+            VestingMode::Stepped => self.stepped(total, elapsed),
+            VestingMode::Linear => self.linear(total, elapsed),
+            VestingMode::Piecewise => self.piecewise(total, elapsed),
+        }
+    }
+
+    // Each reached point is rounded down; the last one releases whatever is left, so the
+    // rounding dust goes out with the final tranche
+    fn piecewise(&self, total: u64, elapsed: i64) -> u64 {
+        let reached = self.points.iter().take_while(|point| elapsed >= point.offset).count();
+        if reached == 0 {
+            return 0;
+        }
+        if reached == self.points.len() {
+            return total;
+        }
+        let bps: u64 = self.points[..reached].iter().map(|point| point.bps as u64).sum();
+        (total as u128 * bps.min(VESTING_BPS) as u128 / VESTING_BPS as u128) as u64
+    }
+
+    fn stepped(&self, total: u64, elapsed: i64) -> u64 {
+        if self.duration <= 0 || self.interval <= 0 {
+            return 0;
+        }
+        let total_steps = self.duration / self.interval;
+        if total_steps == 0 {
+            return 0;
+        }
+        let steps = (elapsed / self.interval).min(total_steps);
+        (total as u128 * steps as u128 / total_steps as u128) as u64
+    }
+
+    fn linear(&self, total: u64, elapsed: i64) -> u64 {
+        let tge_bps = (self.tge_bps as u64).min(VESTING_BPS);
+        let unlocked_at_tge = (total as u128 * tge_bps as u128 / VESTING_BPS as u128) as u64;
+        if elapsed < self.cliff {
+            return unlocked_at_tge;
+        }
+        if self.duration <= 0 || elapsed >= self.duration {
+            return total;
+        }
+        let rest = (total - unlocked_at_tge) as u128;
+        unlocked_at_tge + (rest * elapsed as u128 / self.duration as u128) as u64
+    }
+}
+
+// Points must be non-empty, bounded, strictly increasing in offset from zero, and sum
+// to exactly 100%
+pub fn valid_unlock_points(points: &[UnlockPoint], max_points: usize) -> bool {
+    if points.is_empty() || points.len() > max_points || points[0].offset < 0 {
+        return false;
+    }
+    let increasing = points.windows(2).all(|pair| pair[0].offset < pair[1].offset);
+    let total_bps: u64 = points.iter().map(|point| point.bps as u64).sum();
+    increasing && points.iter().all(|point| point.bps > 0) && total_bps == VESTING_BPS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn schedule(tge_bps: u16) -> VestingSchedule<'static> {
+        VestingSchedule {
+            start: 1_000,
+            cliff: 30 * DAY,
+            duration: 120 * DAY,
+            interval: 30 * DAY,
+            tge_bps,
+            points: &[],
+        }
+    }
+
+    #[test]
+    fn nothing_vests_before_the_start() {
+        let s = schedule(1_000);
+        assert_eq!(s.vested(VestingMode::Stepped, 1_000_000, 999), 0);
+        assert_eq!(s.vested(VestingMode::Linear, 1_000_000, 999), 0);
+    }
+
+    #[test]
+    fn stepped_vests_in_whole_intervals_and_caps_at_total() {
+        let s = schedule(0);
+        assert_eq!(s.vested(VestingMode::Stepped, 1_000, 1_000 + 30 * DAY - 1), 0);
+        assert_eq!(s.vested(VestingMode::Stepped, 1_000, 1_000 + 30 * DAY), 250);
+        assert_eq!(s.vested(VestingMode::Stepped, 1_000, 1_000 + 119 * DAY), 750);
+        assert_eq!(s.vested(VestingMode::Stepped, 1_000, 1_000 + 400 * DAY), 1_000);
+    }
+
+    #[test]
+    fn linear_holds_at_the_tge_unlock_until_the_cliff() {
+        let s = schedule(1_000);
+        assert_eq!(s.vested(VestingMode::Linear, 1_000_000, 1_000), 100_000);
+        assert_eq!(s.vested(VestingMode::Linear, 1_000_000, 1_000 + 30 * DAY - 1), 100_000);
+        // At the cliff the elapsed quarter of the remainder vests at once
+        assert_eq!(s.vested(VestingMode::Linear, 1_000_000, 1_000 + 30 * DAY), 325_000);
+    }
+
+    #[test]
+    fn linear_vests_per_second_and_ends_exactly_at_total() {
+        let s = schedule(0);
+        let at = |seconds: i64| s.vested(VestingMode::Linear, 120 * DAY as u64, 1_000 + seconds);
+        assert_eq!(at(60 * DAY), 60 * DAY as u64);
+        assert_eq!(at(60 * DAY + 1) - at(60 * DAY), 1);
+        assert_eq!(at(120 * DAY), 120 * DAY as u64);
+        assert_eq!(at(500 * DAY), 120 * DAY as u64);
+    }
+
+    #[test]
+    fn linear_never_exceeds_total_with_rounding() {
+        let s = VestingSchedule { start: 0, cliff: 0, duration: 7, interval: 1, tge_bps: 3_333, points: &[] };
+        for t in 0..10 {
+            assert!(s.vested(VestingMode::Linear, 10, t) <= 10);
+        }
+        assert_eq!(s.vested(VestingMode::Linear, 10, 7), 10);
+    }
+
+    // 5% at TGE, 2.5% a month for months 3-14, 15% at month 18, the rest at month 24
+    fn tokenomics_points() -> Vec<UnlockPoint> {
+        let month = 30 * DAY;
+        let mut points = vec![UnlockPoint { offset: 0, bps: 500 }];
+        points.extend((3..15).map(|m| UnlockPoint { offset: m * month, bps: 250 }));
+        points.push(UnlockPoint { offset: 18 * month, bps: 1_500 });
+        points.push(UnlockPoint { offset: 24 * month, bps: 5_000 });
+        points
+    }
+
+    #[test]
+    fn unlock_points_are_validated() {
+        let points = tokenomics_points();
+        assert!(valid_unlock_points(&points, 32));
+        assert!(!valid_unlock_points(&points, 8));
+        assert!(!valid_unlock_points(&[], 32));
+        assert!(!valid_unlock_points(&points[..points.len() - 1], 32));
+        let mut unsorted = points.clone();
+        unsorted.swap(1, 2);
+        assert!(!valid_unlock_points(&unsorted, 32));
+    }
+
+    #[test]
+    fn piecewise_steps_through_points_and_sweeps_dust() {
+        let points = tokenomics_points();
+        let s = VestingSchedule { start: 1_000, points: &points, ..Default::default() };
+        let at = |seconds: i64| s.vested(VestingMode::Piecewise, 999_999, 1_000 + seconds);
+        assert_eq!(at(-1), 0);
+        assert_eq!(at(0), 49_999);
+        assert_eq!(at(90 * DAY), 74_999);
+        assert_eq!(at(24 * 30 * DAY - 1), 499_999);
+        assert_eq!(at(24 * 30 * DAY), 999_999);
+    }
+}